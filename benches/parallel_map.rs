@@ -0,0 +1,39 @@
+//! Compares sequential and `--parallel-map` evaluation of a regex-heavy map.
+//!
+//! Run with `cargo bench --bench parallel_map`.
+
+use std::time::{Duration, Instant};
+
+use qf::query::{self, env::Env};
+use serde_json::{json, Value};
+
+const QUERY: &str = r#"map(.text | [match("[a-z]+[0-9]+"; "g").string | ascii_upcase] | join(","))"#;
+
+fn time(input: &Value, env: &Env) -> (Duration, Vec<Value>) {
+    let start = Instant::now();
+    let out = query::query_with_env(input, QUERY, env).unwrap();
+    (start.elapsed(), out)
+}
+
+fn main() {
+    let input = Value::Array(
+        (0..20_000)
+            .map(|i| json!({ "text": format!("alpha{i} beta{} gamma{} delta", i * 7, i * 13).repeat(8) }))
+            .collect(),
+    );
+
+    let sequential_env = Env::new();
+    let mut parallel_env = Env::new();
+    parallel_env.set_parallel_map(true);
+
+    let (seq_time, seq_out) = time(&input, &sequential_env);
+    let (par_time, par_out) = time(&input, &parallel_env);
+    assert_eq!(seq_out, par_out);
+
+    println!("sequential map: {seq_time:?}");
+    println!("parallel map:   {par_time:?}");
+    println!(
+        "speedup:        {:.2}x",
+        seq_time.as_secs_f64() / par_time.as_secs_f64()
+    );
+}
@@ -14,6 +14,9 @@ pub enum QfError {
     #[error("parse error: {0}")]
     Parse(String),
 
+    #[error("encode error: {0}")]
+    Encode(String),
+
     #[error("invalid query path: {0}")]
     InvalidQuery(String),
 
@@ -50,3 +53,53 @@ pub enum QfError {
     #[error("I/O error: {0}")]
     Io(#[from] std::io::Error),
 }
+
+impl QfError {
+    /// A stable name for the kind of error, for machine-readable reports:
+    /// the variant's name in snake_case.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            QfError::UnsupportedFormat(_) => "unsupported_format",
+            QfError::NoExtension => "no_extension",
+            QfError::UnknownExtension(_) => "unknown_extension",
+            QfError::Parse(_) => "parse",
+            QfError::Encode(_) => "encode",
+            QfError::InvalidQuery(_) => "invalid_query",
+            QfError::PathNotFound(_) => "path_not_found",
+            QfError::IndexOutOfBounds { .. } => "index_out_of_bounds",
+            QfError::ExpectedArray(_) => "expected_array",
+            QfError::ExpectedObject(_) => "expected_object",
+            QfError::SyntaxError { .. } => "syntax_error",
+            QfError::TypeError(_) => "type_error",
+            QfError::UndefinedVariable(_) => "undefined_variable",
+            QfError::UndefinedFunction(..) => "undefined_function",
+            QfError::Runtime(_) => "runtime",
+            QfError::UserError(_) => "user_error",
+            QfError::Io(_) => "io",
+        }
+    }
+
+    /// The error's message without the kind prefix its `Display` adds, for
+    /// reports that give the kind separately.
+    pub fn message(&self) -> String {
+        match self {
+            QfError::UnsupportedFormat(s)
+            | QfError::Parse(s)
+            | QfError::Encode(s)
+            | QfError::InvalidQuery(s)
+            | QfError::PathNotFound(s)
+            | QfError::ExpectedArray(s)
+            | QfError::ExpectedObject(s)
+            | QfError::TypeError(s)
+            | QfError::Runtime(s)
+            | QfError::UserError(s)
+            | QfError::SyntaxError { message: s, .. } => s.clone(),
+            QfError::NoExtension => "no file extension".into(),
+            QfError::UnknownExtension(ext) => format!(".{ext}"),
+            QfError::IndexOutOfBounds { index, length } => format!("{index} (length {length})"),
+            QfError::UndefinedVariable(name) => format!("${name}"),
+            QfError::UndefinedFunction(name, arity) => format!("{name}/{arity}"),
+            QfError::Io(e) => e.to_string(),
+        }
+    }
+}
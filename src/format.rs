@@ -6,13 +6,28 @@ use crate::error::QfError;
 pub enum Format {
     Yaml,
     Json,
+    /// JSON5: comments, trailing commas, unquoted keys, single quotes.
+    /// Written back out as plain JSON.
+    Json5,
     Xml,
     Toml,
     Csv,
     Tsv,
+    Properties,
+    /// Aligned text table; output only.
+    Table,
+    /// GitHub-flavored Markdown table; output only.
+    Markdown,
+    /// Binary CBOR; requires the `cbor` feature.
+    Cbor,
 }
 
 impl Format {
+    /// Whether the format is read and written as raw bytes rather than text.
+    pub fn is_binary(self) -> bool {
+        matches!(self, Format::Cbor)
+    }
+
     /// Detect format from a file extension.
     pub fn from_extension(path: &Path) -> Result<Self, QfError> {
         let ext = path
@@ -23,37 +38,182 @@ impl Format {
         match ext.to_ascii_lowercase().as_str() {
             "yaml" | "yml" => Ok(Format::Yaml),
             "json" => Ok(Format::Json),
+            "json5" => Ok(Format::Json5),
             "xml" => Ok(Format::Xml),
             "toml" => Ok(Format::Toml),
             "csv" => Ok(Format::Csv),
             "tsv" => Ok(Format::Tsv),
+            "properties" => Ok(Format::Properties),
+            "cbor" => Ok(Format::Cbor),
             other => Err(QfError::UnknownExtension(other.to_string())),
         }
     }
 
+    /// The usual file extension for the format, if it has one.
+    pub fn extension(self) -> Option<&'static str> {
+        match self {
+            Format::Yaml => Some("yaml"),
+            Format::Json => Some("json"),
+            Format::Json5 => Some("json5"),
+            Format::Xml => Some("xml"),
+            Format::Toml => Some("toml"),
+            Format::Csv => Some("csv"),
+            Format::Tsv => Some("tsv"),
+            Format::Properties => Some("properties"),
+            Format::Cbor => Some("cbor"),
+            Format::Table | Format::Markdown => None,
+        }
+    }
+
+    /// The output format used when none is given: the input format, or JSON
+    /// for formats that can only be read.
+    pub fn default_output(self) -> Format {
+        match self {
+            Format::Properties => Format::Json,
+            other => other,
+        }
+    }
+
+    /// Guess the format of piped input from its content.
+    ///
+    /// JSON and XML are recognized by their first character (or, for a bare
+    /// JSON number, string, `true`, `false` or `null`, by parsing it), TOML by
+    /// `[table]` headers and `key = value` lines, CSV/TSV by a consistent
+    /// number of fields across the first lines. Anything else is YAML, which
+    /// also accepts most plain text.
+    pub fn detect(input: &str) -> Format {
+        let trimmed = input.trim_start();
+        let sample: Vec<&str> = trimmed
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .take(DETECT_SAMPLE_LINES)
+            .collect();
+
+        if trimmed.starts_with('<') {
+            Format::Xml
+        } else if sample.first().is_some_and(|l| is_toml_table_header(l)) {
+            Format::Toml
+        } else if trimmed.starts_with('{') || trimmed.starts_with('[') || is_json_scalar(trimmed) {
+            Format::Json
+        } else if !sample.is_empty()
+            && sample.iter().all(|l| is_toml_table_header(l) || is_toml_key_value(l))
+        {
+            Format::Toml
+        } else if has_consistent_fields(trimmed, b'\t') {
+            Format::Tsv
+        } else if !looks_like_yaml(&sample) && has_consistent_fields(trimmed, b',') {
+            Format::Csv
+        } else {
+            Format::Yaml
+        }
+    }
+
     /// Parse a format string from CLI flags.
     pub fn from_str_name(s: &str) -> Result<Self, QfError> {
         match s.to_ascii_lowercase().as_str() {
             "yaml" | "yml" => Ok(Format::Yaml),
             "json" => Ok(Format::Json),
+            "json5" => Ok(Format::Json5),
             "xml" => Ok(Format::Xml),
             "toml" => Ok(Format::Toml),
             "csv" => Ok(Format::Csv),
             "tsv" => Ok(Format::Tsv),
+            "properties" => Ok(Format::Properties),
+            "table" => Ok(Format::Table),
+            "markdown" | "md" => Ok(Format::Markdown),
+            "cbor" => Ok(Format::Cbor),
             other => Err(QfError::UnsupportedFormat(other.to_string())),
         }
     }
 }
 
+/// A lone JSON scalar, which is read as JSON so that `42` or `"s"` gives the
+/// same value (and output format) as jq rather than going through YAML.
+fn is_json_scalar(input: &str) -> bool {
+    let maybe_json = input.starts_with(|c: char| c == '"' || c == '-' || c.is_ascii_digit())
+        || ["true", "false", "null"].iter().any(|word| input.starts_with(word));
+    maybe_json
+        && serde_json::from_str::<serde_json::Value>(input)
+            .is_ok_and(|v| !v.is_array() && !v.is_object())
+}
+
+/// Lines examined by [`Format::detect`].
+const DETECT_SAMPLE_LINES: usize = 20;
+
+/// `[table]` or `[[array.of.tables]]` with bare keys. Requiring a leading
+/// letter keeps JSON arrays such as `[1]` or `[true]` out.
+fn is_toml_table_header(line: &str) -> bool {
+    let inner = line
+        .strip_prefix("[[")
+        .and_then(|l| l.strip_suffix("]]"))
+        .or_else(|| line.strip_prefix('[').and_then(|l| l.strip_suffix(']')));
+    inner.is_some_and(|name| {
+        let name = name.trim();
+        name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && !matches!(name, "true" | "false")
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || "_-.".contains(c))
+    })
+}
+
+/// `key = value` with a bare, quoted or dotted key.
+fn is_toml_key_value(line: &str) -> bool {
+    let Some((key, value)) = line.split_once('=') else {
+        return false;
+    };
+    let key = key.trim();
+    let bare = |k: &str| {
+        !k.is_empty() && k.chars().all(|c| c.is_ascii_alphanumeric() || "_-.".contains(c))
+    };
+    let quoted = |k: &str| k.len() >= 2 && (k.starts_with('"') || k.starts_with('\''));
+    (bare(key) || quoted(key)) && !value.trim().is_empty()
+}
+
+/// YAML mappings and sequences that could otherwise pass for CSV rows.
+fn looks_like_yaml(sample: &[&str]) -> bool {
+    sample.iter().any(|l| {
+        l.starts_with("- ") || l.ends_with(':') || l.contains(": ") || *l == "---"
+    })
+}
+
+/// At least two rows, all with the same number (> 1) of fields.
+fn has_consistent_fields(input: &str, delimiter: u8) -> bool {
+    let mut rdr = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(input.as_bytes());
+    let mut widths = rdr
+        .records()
+        .take(DETECT_SAMPLE_LINES)
+        .map(|r| r.map(|rec| rec.len()));
+    let Some(Ok(first)) = widths.next() else {
+        return false;
+    };
+    let mut rows = 1;
+    for width in widths {
+        match width {
+            Ok(w) if w == first => rows += 1,
+            _ => return false,
+        }
+    }
+    first > 1 && rows > 1
+}
+
 impl std::fmt::Display for Format {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Format::Yaml => write!(f, "yaml"),
             Format::Json => write!(f, "json"),
+            Format::Json5 => write!(f, "json5"),
             Format::Xml => write!(f, "xml"),
             Format::Toml => write!(f, "toml"),
             Format::Csv => write!(f, "csv"),
             Format::Tsv => write!(f, "tsv"),
+            Format::Properties => write!(f, "properties"),
+            Format::Table => write!(f, "table"),
+            Format::Markdown => write!(f, "markdown"),
+            Format::Cbor => write!(f, "cbor"),
         }
     }
 }
@@ -75,6 +235,12 @@ mod tests {
         assert_eq!(Format::from_extension(Path::new("foo.JSON")).unwrap(), Format::Json);
     }
 
+    #[test]
+    fn detect_json5() {
+        assert_eq!(Format::from_extension(Path::new("foo.json5")).unwrap(), Format::Json5);
+        assert_eq!(Format::from_str_name("JSON5").unwrap(), Format::Json5);
+    }
+
     #[test]
     fn no_extension_errors() {
         assert!(Format::from_extension(Path::new("foo")).is_err());
@@ -100,6 +266,67 @@ mod tests {
         assert_eq!(Format::from_extension(Path::new("foo.tsv")).unwrap(), Format::Tsv);
     }
 
+    #[test]
+    fn detect_properties() {
+        assert_eq!(
+            Format::from_extension(Path::new("app.properties")).unwrap(),
+            Format::Properties
+        );
+        // Read-only, so written out as JSON unless asked otherwise
+        assert_eq!(Format::Properties.default_output(), Format::Json);
+        assert_eq!(Format::Toml.default_output(), Format::Toml);
+    }
+
+    #[test]
+    fn extension_round_trips() {
+        for fmt in [Format::Yaml, Format::Json, Format::Toml, Format::Properties] {
+            let path = Path::new("f").with_extension(fmt.extension().unwrap());
+            assert_eq!(Format::from_extension(&path).unwrap(), fmt);
+        }
+        assert_eq!(Format::Table.extension(), None);
+    }
+
+    #[test]
+    fn detect_content() {
+        assert_eq!(Format::detect("{\"a\": 1}"), Format::Json);
+        assert_eq!(Format::detect("  [1, 2]"), Format::Json);
+        assert_eq!(Format::detect("[true]"), Format::Json);
+        assert_eq!(Format::detect("<root/>"), Format::Xml);
+        assert_eq!(Format::detect("a: 1\nb: [1, 2]\n"), Format::Yaml);
+        assert_eq!(Format::detect("- a, b\n- c, d\n"), Format::Yaml);
+        assert_eq!(Format::detect("plain text"), Format::Yaml);
+        assert_eq!(Format::detect(""), Format::Yaml);
+    }
+
+    #[test]
+    fn detect_bare_scalars() {
+        for json in ["42\n", "-1.5e3", "\"a: b\"\n", "true", "null\n"] {
+            assert_eq!(Format::detect(json), Format::Json, "{json}");
+        }
+        // Not a single JSON value: left to YAML
+        for yaml in ["yes", "- 1\n- 2\n", "1\n2\n", "2024-01-01", "nullable: 1", "0x1F"] {
+            assert_eq!(Format::detect(yaml), Format::Yaml, "{yaml}");
+        }
+    }
+
+    #[test]
+    fn detect_toml_content() {
+        let toml = "# config\ntitle = \"qf\"\n\n[server]\nport = 8080\n\n[[users]]\nname = 'a'\n";
+        assert_eq!(Format::detect(toml), Format::Toml);
+        assert_eq!(Format::detect("[package]\nname = \"qf\"\n"), Format::Toml);
+        assert_eq!(Format::detect("a.b = 1\n\"c d\" = true\n"), Format::Toml);
+    }
+
+    #[test]
+    fn detect_delimited_content() {
+        assert_eq!(Format::detect("name,age\nAlice,30\nBob,25\n"), Format::Csv);
+        assert_eq!(Format::detect("name,desc\nAlice,\"a, b\"\n"), Format::Csv);
+        assert_eq!(Format::detect("name\tage\nAlice\t30\n"), Format::Tsv);
+        // Ragged rows or a single line aren't enough to call it CSV
+        assert_eq!(Format::detect("a,b\nc\n"), Format::Yaml);
+        assert_eq!(Format::detect("a,b\n"), Format::Yaml);
+    }
+
     #[test]
     fn unknown_extension_errors() {
         assert!(Format::from_extension(Path::new("foo.xyz")).is_err());
@@ -113,6 +340,10 @@ mod tests {
         assert_eq!(Format::from_str_name("toml").unwrap(), Format::Toml);
         assert_eq!(Format::from_str_name("csv").unwrap(), Format::Csv);
         assert_eq!(Format::from_str_name("tsv").unwrap(), Format::Tsv);
+        assert_eq!(Format::from_str_name("properties").unwrap(), Format::Properties);
+        assert_eq!(Format::from_str_name("table").unwrap(), Format::Table);
+        assert_eq!(Format::from_str_name("markdown").unwrap(), Format::Markdown);
+        assert_eq!(Format::from_str_name("cbor").unwrap(), Format::Cbor);
         assert!(Format::from_str_name("xyz").is_err());
     }
 }
@@ -3,4 +3,5 @@ pub mod format;
 pub mod output;
 pub mod parser;
 pub mod query;
+pub mod schema;
 pub mod stream;
@@ -1,7 +1,10 @@
+use std::io::BufRead;
 use std::io::IsTerminal;
 use std::io::Read;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::OnceLock;
 
 use anyhow::{Context, Result};
 use clap::Parser;
@@ -9,8 +12,10 @@ use clap::Parser;
 use qf::error::QfError;
 use qf::format::Format;
 use qf::output;
+use qf::output::color::Theme;
 use qf::parser;
 use qf::query;
+use qf::query::env::Env;
 use qf::stream;
 
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -32,6 +37,23 @@ impl std::str::FromStr for ColorMode {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ErrorFormat {
+    Text,
+    Json,
+}
+
+impl std::str::FromStr for ErrorFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(ErrorFormat::Text),
+            "json" => Ok(ErrorFormat::Json),
+            other => Err(format!("invalid error format: {other} (expected text, json)")),
+        }
+    }
+}
+
 #[derive(Parser)]
 #[command(name = "qf", version, about = "A fast, universal data format query tool")]
 struct Cli {
@@ -39,7 +61,8 @@ struct Cli {
     #[arg(default_value = ".")]
     query: String,
 
-    /// Input file(s) (reads from stdin if omitted)
+    /// Input file(s) (reads from stdin if omitted); with --args/--jsonargs,
+    /// positional values for $ARGS.positional instead
     files: Vec<PathBuf>,
 
     /// Force input format [yaml, json, xml, toml, csv, tsv]
@@ -54,14 +77,32 @@ struct Cli {
     #[arg(short, long = "in-place")]
     in_place: bool,
 
+    /// With --in-place, give each rewritten file the extension of its
+    /// output format (e.g. config.yaml becomes config.json with -o json)
+    #[arg(long = "rename-extension", requires = "in_place")]
+    rename_extension: bool,
+
     /// Compact output (no pretty printing)
     #[arg(short, long)]
     compact: bool,
 
+    /// Pretty-print JSON, but write objects holding only scalars on one line
+    #[arg(long = "compact-objects")]
+    compact_objects: bool,
+
     /// Raw string output (no quotes for string values)
     #[arg(short, long)]
     raw: bool,
 
+    /// Like -r, but terminate each output with NUL instead of a newline
+    #[arg(long = "raw-output0")]
+    raw_output0: bool,
+
+    /// Write RFC 7464 JSON text sequences: each output is prefixed with an
+    /// ASCII record separator (0x1e); RS characters in the input are ignored
+    #[arg(long)]
+    seq: bool,
+
     /// Colorize output [auto, always, never]
     #[arg(long, default_value = "auto")]
     color: ColorMode,
@@ -70,6 +111,16 @@ struct Cli {
     #[arg(long)]
     no_color: bool,
 
+    /// Color theme for colorized output; $QF_COLORS (in JQ_COLORS format)
+    /// overrides individual colors
+    #[arg(long, value_name = "NAME", default_value = "default",
+          value_parser = clap::builder::PossibleValuesParser::new(Theme::NAMES))]
+    theme: String,
+
+    /// Disable colorized output (jq's name for --no-color)
+    #[arg(short = 'M', long = "monochrome-output")]
+    monochrome_output: bool,
+
     /// Slurp: read all inputs into an array
     #[arg(short = 's', long)]
     slurp: bool,
@@ -93,10 +144,197 @@ struct Cli {
     /// Read input as newline-delimited JSON (NDJSON/JSON Lines)
     #[arg(long)]
     jsonl: bool,
+
+    /// Flush output after each result in --stream/--jsonl mode
+    #[arg(long)]
+    unbuffered: bool,
+
+    /// With --jsonl or --stream, report malformed records on stderr and
+    /// carry on instead of stopping
+    #[arg(long = "skip-errors")]
+    skip_errors: bool,
+
+    /// Bind $NAME to the string VALUE
+    #[arg(long = "arg", num_args = 2, value_names = ["NAME", "VALUE"], action = clap::ArgAction::Append)]
+    arg: Vec<String>,
+
+    /// Bind $NAME to the JSON value parsed from TEXT
+    #[arg(long = "argjson", num_args = 2, value_names = ["NAME", "TEXT"], action = clap::ArgAction::Append)]
+    argjson: Vec<String>,
+
+    /// Bind $NAME to the contents of FILE as a string
+    #[arg(long = "rawfile", num_args = 2, value_names = ["NAME", "FILE"], action = clap::ArgAction::Append)]
+    rawfile: Vec<String>,
+
+    /// Bind $NAME to an array of the JSON values in FILE
+    #[arg(long = "slurpfile", num_args = 2, value_names = ["NAME", "FILE"], action = clap::ArgAction::Append)]
+    slurpfile: Vec<String>,
+
+    /// Treat the arguments after QUERY as strings for $ARGS.positional
+    #[arg(long, conflicts_with = "jsonargs")]
+    args: bool,
+
+    /// Treat the arguments after QUERY as JSON values for $ARGS.positional
+    #[arg(long)]
+    jsonargs: bool,
+
+    /// Pretty-print `tojson`/`@json` output with N spaces (default: compact)
+    #[arg(long = "tojson-indent", value_name = "N")]
+    tojson_indent: Option<usize>,
+
+    /// Process multiple files with N worker threads (0 = one per CPU)
+    #[arg(long, value_name = "N", default_value_t = 1)]
+    jobs: usize,
+
+    /// With multiple files, stop at the first file that fails
+    #[arg(long = "fail-fast")]
+    fail_fast: bool,
+
+    /// Evaluate map(f) over array elements in parallel
+    #[arg(long = "parallel-map")]
+    parallel_map: bool,
+
+    /// Text to write for null cells in CSV/TSV output
+    #[arg(long = "csv-null", value_name = "TEXT", default_value = "")]
+    csv_null: String,
+
+    /// Search DIR for modules loaded with `include`/`import` (repeatable;
+    /// default: the current directory)
+    #[arg(short = 'L', long = "library-path", value_name = "DIR")]
+    library_path: Vec<PathBuf>,
+
+    /// Fail if any expression produces more than N values
+    #[arg(long = "max-outputs", value_name = "N")]
+    max_outputs: Option<usize>,
+
+    /// Fail if function calls nest deeper than N
+    #[arg(long = "max-depth", value_name = "N", default_value_t = query::env::DEFAULT_MAX_DEPTH)]
+    max_depth: usize,
+
+    /// Indent YAML output by N spaces per level
+    #[arg(long = "yaml-indent", value_name = "N", default_value_t = 2,
+          value_parser = clap::value_parser!(u8).range(1..=16))]
+    yaml_indent: u8,
+
+    /// Write YAML collections that hold only scalars in flow style ([a, b])
+    #[arg(long = "yaml-flow")]
+    yaml_flow: bool,
+
+    /// Edit YAML by rewriting only the changed top-level scalar values,
+    /// keeping comments and layout (e.g. with -i '.version = "2"')
+    #[arg(long = "yaml-preserve")]
+    yaml_preserve: bool,
+
+    /// Mark YAML documents explicitly: `---` before each, `...` after the last
+    #[arg(long = "yaml-explicit")]
+    yaml_explicit: bool,
+
+    /// Name of the root element of XML output
+    #[arg(long = "xml-root", value_name = "NAME", default_value = "root")]
+    xml_root: String,
+
+    /// Read CSV/TSV input without a header row (rows become arrays)
+    #[arg(long = "csv-no-header")]
+    csv_no_header: bool,
+
+    /// Field delimiter for CSV/TSV input
+    #[arg(long = "csv-delimiter", value_name = "C")]
+    csv_delimiter: Option<char>,
+
+    /// Convert number- and boolean-looking CSV/TSV fields to real values
+    #[arg(long = "csv-infer-types")]
+    csv_infer_types: bool,
+
+    /// How to read JSON/YAML objects that repeat a key [last, first, error, array]
+    #[arg(long = "duplicate-keys", value_name = "POLICY")]
+    duplicate_keys: Option<parser::DuplicateKeys>,
+
+    /// Emit a draft JSON Schema describing the query output instead
+    #[arg(long = "infer-schema")]
+    infer_schema: bool,
+
+    /// Load the input once, then run each line of stdin as a query against
+    /// it; QUERY may be left out (`qf --repl data.json`)
+    #[arg(long,
+          conflicts_with_all = ["in_place", "stream", "jsonl", "raw_input", "infer_schema", "seq"])]
+    repl: bool,
+
+    /// How to report a failure on stderr [text, json]; json writes one
+    /// object with `kind`, `message` and, for syntax errors, `position`
+    #[arg(long = "error-format", value_name = "FORMAT", default_value = "text")]
+    error_format: ErrorFormat,
+}
+
+/// Stack for threads evaluating queries: enough that `--max-depth` is
+/// reached before a deeply recursive query could overflow it, up to a cap
+/// that any system can still give a thread.
+fn query_stack_size(cli: &Cli) -> usize {
+    const PER_LEVEL: usize = 64 << 10;
+    cli.max_depth.saturating_mul(PER_LEVEL).clamp(64 << 20, 1 << 30)
 }
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
+    let error_format = cli.error_format;
+    let stack_size = query_stack_size(&cli);
+    // `map(f)` with --parallel-map evaluates on the global pool
+    let _ = rayon::ThreadPoolBuilder::new().stack_size(stack_size).build_global();
+    let outcome = std::thread::Builder::new()
+        .stack_size(stack_size)
+        .spawn(move || run(cli))
+        .context("starting query thread")?
+        .join()
+        .unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+    match outcome {
+        Err(e) if error_format == ErrorFormat::Json => {
+            eprintln!("{}", error_json(&e));
+            std::process::exit(1);
+        }
+        result => result,
+    }
+}
+
+/// Report a failure that does not stop qf, such as one file of several.
+fn report_error(format: ErrorFormat, e: &anyhow::Error) {
+    match format {
+        ErrorFormat::Text => eprintln!("qf: {e:#}"),
+        ErrorFormat::Json => eprintln!("{}", error_json(e)),
+    }
+}
+
+/// A failure as a JSON object for `--error-format json`. Errors that did
+/// not come from the query engine or parsers are of kind `error`.
+fn error_json(e: &anyhow::Error) -> serde_json::Value {
+    let Some(qf_error) = e.downcast_ref::<QfError>() else {
+        return serde_json::json!({"kind": "error", "message": format!("{e:#}")});
+    };
+    // Context added on the way up, then the error's own message; its kind
+    // is reported on its own
+    let mut message: Vec<String> = e
+        .chain()
+        .take_while(|cause| !cause.is::<QfError>())
+        .map(ToString::to_string)
+        .collect();
+    message.push(qf_error.message());
+    let mut report = serde_json::json!({"kind": qf_error.kind(), "message": message.join(": ")});
+    if let QfError::SyntaxError { position, .. } = qf_error {
+        report["position"] = (*position).into();
+    }
+    report
+}
+
+fn run(mut cli: Cli) -> Result<()> {
+    let positional = positional_args(&mut cli)?;
+
+    // Queries come from stdin, so a lone positional argument is the input
+    // file; no file can be named `.`
+    if cli.repl && cli.query != "." {
+        let path = std::mem::replace(&mut cli.query, ".".into());
+        cli.files.insert(0, path.into());
+    }
+    if cli.repl && (cli.files.len() > 1 || (cli.files.is_empty() && !cli.null_input)) {
+        anyhow::bail!("--repl needs exactly one input file (or -n)");
+    }
 
     // For backward compat: treat first file arg as the single file
     let file = cli.files.first();
@@ -105,47 +343,100 @@ fn main() -> Result<()> {
     if cli.in_place && cli.files.is_empty() {
         anyhow::bail!("--in-place requires a file argument");
     }
+    if cli.in_place && cli.infer_schema {
+        anyhow::bail!("--infer-schema cannot be combined with --in-place");
+    }
+    if cli.in_place && cli.files.iter().any(|p| is_gzip_path(p)) {
+        anyhow::bail!("--in-place does not support gzip-compressed files");
+    }
 
     // Determine if we should colorize
     let colorize = should_colorize(&cli);
 
-    // Read input
-    let input = if cli.null_input {
-        String::new()
+    // Variable bindings are shared by every query path below
+    let mut env = build_env(&cli, positional)?;
+    let parse_opts = parse_options(&cli)?;
+
+    // Each file is edited on its own
+    if cli.in_place && cli.files.len() > 1 {
+        if cli.slurp || cli.raw_input || cli.stream || cli.jsonl {
+            anyhow::bail!(
+                "--in-place with several files cannot be combined with --slurp, --raw-input, \
+                 --stream or --jsonl"
+            );
+        }
+        return edit_files_in_place(&cli, &parse_opts, &env);
+    }
+
+    // Several independent files: run the query over each one
+    if cli.files.len() > 1
+        && !(cli.slurp || cli.in_place || cli.null_input || cli.raw_input || cli.stream || cli.jsonl)
+    {
+        return process_files(&cli, &parse_opts, &env, colorize);
+    }
+
+    if let [path] = cli.files.as_slice() {
+        env.set_input_filename(Some(path.display().to_string()));
+    }
+
+    // Read input as bytes; binary formats are decoded directly from these
+    let raw = if cli.null_input {
+        Vec::new()
+    } else if cli.raw_input && cli.files.len() > 1 {
+        // Raw input treats all files as one stream of text
+        let mut buf = Vec::new();
+        for path in &cli.files {
+            buf.extend(read_file(path)?);
+        }
+        buf
     } else if cli.slurp && cli.files.len() > 1 {
         // Multi-file slurp: handled specially below
-        String::new()
+        Vec::new()
     } else {
         match file {
-            Some(path) => {
-                std::fs::read_to_string(path)
-                    .with_context(|| format!("reading {}", path.display()))?
-            }
+            Some(path) => read_file(path)?,
             None => {
-                let mut buf = String::new();
+                let mut buf = Vec::new();
                 std::io::stdin()
-                    .read_to_string(&mut buf)
+                    .read_to_end(&mut buf)
                     .context("reading stdin")?;
-                buf
+                // No extension to go on, so sniff for the gzip magic instead
+                if buf.starts_with(&GZIP_MAGIC) {
+                    gunzip(&buf).context("decompressing stdin")?
+                } else {
+                    buf
+                }
             }
         }
     };
 
+    let raw = if cli.seq { strip_record_separators(raw) } else { raw };
+
     // Determine input format
     let in_fmt = match &cli.input_format {
         Some(f) => Format::from_str_name(f)?,
+        // Raw text is never parsed, so any file name will do; output is JSON
+        None if cli.raw_input => Format::Json,
         None => match file {
-            Some(path) => Format::from_extension(path)?,
+            Some(path) => format_from_path(path)?,
             None => {
                 if cli.null_input {
                     Format::Json
                 } else {
-                    detect_format(&input)?
+                    Format::detect(&String::from_utf8_lossy(&raw))
                 }
             }
         },
     };
 
+    // Text formats need the input as UTF-8
+    let (input, raw) = if in_fmt.is_binary() {
+        (String::new(), raw)
+    } else {
+        let text = String::from_utf8(raw).context("input is not valid UTF-8")?;
+        (text, Vec::new())
+    };
+
     // Determine output format
     // In streaming/jsonl mode, default to JSON output since individual records
     // often can't serialize back to CSV/XML/etc.
@@ -155,93 +446,82 @@ fn main() -> Result<()> {
             if cli.stream || cli.jsonl {
                 Format::Json
             } else {
-                in_fmt
+                in_fmt.default_output()
             }
         }
     };
 
+    let mut stdout = std::io::stdout().lock();
+
     // Handle null-input mode
     if cli.null_input {
         let value = serde_json::Value::Null;
-        let results = query::query(&value, &cli.query)?;
-        output_results(&results, out_fmt, &cli, colorize)?;
+        if cli.repl {
+            return repl(&mut stdout, &value, out_fmt, &cli, &env, colorize);
+        }
+        let results = query::query_with_env(&value, &cli.query, &env)?;
+        output_results(&mut stdout, &results, out_fmt, &cli, colorize)?;
         return Ok(());
     }
 
     // Handle slurp mode with multiple files
-    if cli.slurp && cli.files.len() > 1 {
+    if cli.slurp && cli.files.len() > 1 && !cli.raw_input {
         let mut all_values = Vec::new();
         for path in &cli.files {
-            let content = std::fs::read_to_string(path)
-                .with_context(|| format!("reading {}", path.display()))?;
+            let content = read_file(path)?;
             let fmt = match &cli.input_format {
                 Some(f) => Format::from_str_name(f)?,
-                None => Format::from_extension(path)?,
+                None => format_from_path(path)?,
             };
-            let val = parser::parse(&content, fmt)?;
+            let val = parser::parse_bytes_with(&content, fmt, &parse_opts)?;
             all_values.push(val);
         }
         let slurped = serde_json::Value::Array(all_values);
-        let results = query::query(&slurped, &cli.query)?;
-        output_results(&results, out_fmt, &cli, colorize)?;
+        let results = query::query_with_env(&slurped, &cli.query, &env)?;
+        output_results(&mut stdout, &results, out_fmt, &cli, colorize)?;
         return Ok(());
     }
 
     // Handle raw-input mode
     if cli.raw_input {
-        let lines: Vec<serde_json::Value> = input
-            .lines()
-            .map(|l| serde_json::Value::String(l.to_string()))
-            .collect();
-        let value = if cli.slurp {
-            serde_json::Value::Array(lines)
-        } else {
-            // Process each line separately
-            for line_val in &lines {
-                let results = query::query(line_val, &cli.query)?;
-                output_results(&results, out_fmt, &cli, colorize)?;
-            }
+        if cli.slurp {
+            // Like jq -Rs: the whole input as one string, newlines included
+            let value = serde_json::Value::String(input);
+            let results = query::query_with_env(&value, &cli.query, &env)?;
+            output_results(&mut stdout, &results, out_fmt, &cli, colorize)?;
             return Ok(());
-        };
-        let results = query::query(&value, &cli.query)?;
-        output_results(&results, out_fmt, &cli, colorize)?;
+        }
+        // Process each line separately
+        for line in input.lines() {
+            let line_val = serde_json::Value::String(line.to_string());
+            let results = query::query_with_env(&line_val, &cli.query, &env)?;
+            output_results(&mut stdout, &results, out_fmt, &cli, colorize)?;
+        }
         return Ok(());
     }
 
+    let sep = Separator::from_cli(&cli);
+
     // Handle JSONL (newline-delimited JSON) mode
     if cli.jsonl {
-        stream::stream_ndjson(&input, &cli.query, |result| {
-            let formatted = output::pretty::format_value_colored(
-                &result, out_fmt, cli.compact, cli.raw, colorize,
-            )
-            .map_err(|e| QfError::Runtime(e.to_string()))?;
-            print!("{formatted}");
-            if !formatted.ends_with('\n') && !cli.join_output {
-                println!();
-            }
-            Ok(())
-        })?;
-        return Ok(());
+        let program = query::Program::compile_with_env(&cli.query, &env)?;
+        let records = stream::ndjson_records_with(&input, &program, &parse_opts, &env);
+        return print_records(&mut stdout, records, out_fmt, &cli, colorize, sep);
     }
 
-    // Handle streaming mode
-    if cli.stream {
-        stream::stream_process(&input, in_fmt, &cli.query, |result| {
-            let formatted = output::pretty::format_value_colored(
-                &result, out_fmt, cli.compact, cli.raw, colorize,
-            )
-            .map_err(|e| QfError::Runtime(e.to_string()))?;
-            print!("{formatted}");
-            if !formatted.ends_with('\n') && !cli.join_output {
-                println!();
-            }
-            Ok(())
-        })?;
-        return Ok(());
+    // Handle streaming mode; a JSON text sequence is read record by record
+    if cli.stream || (cli.seq && in_fmt == Format::Json && !cli.slurp) {
+        let program = query::Program::compile_with_env(&cli.query, &env)?;
+        let records = stream::records_with(&input, in_fmt, &program, &parse_opts, &env);
+        return print_records(&mut stdout, records, out_fmt, &cli, colorize, sep);
     }
 
     // Parse
-    let value = parser::parse(&input, in_fmt)?;
+    let value = if in_fmt.is_binary() {
+        parser::parse_bytes_with(&raw, in_fmt, &parse_opts)?
+    } else {
+        parser::parse_with(&input, in_fmt, &parse_opts)?
+    };
 
     // Handle slurp with single file (wrap in array)
     let value = if cli.slurp && !cli.files.is_empty() {
@@ -250,29 +530,390 @@ fn main() -> Result<()> {
         value
     };
 
+    if cli.repl {
+        return repl(&mut stdout, &value, out_fmt, &cli, &env, colorize);
+    }
+
     // Query
-    let results = query::query(&value, &cli.query)?;
+    let results = query::query_with_env(&value, &cli.query, &env)?;
+    let results = if cli.infer_schema {
+        vec![qf::schema::infer_schema(&results)]
+    } else {
+        results
+    };
 
     // Output
+    let preserved = if cli.yaml_preserve {
+        Some(preserve_yaml(&input, &value, &results, in_fmt, out_fmt)?)
+    } else {
+        None
+    };
     if cli.in_place {
-        let formatted = format_results(&results, out_fmt, &cli, false)?;
-        let path = cli.files.first().unwrap();
-        let parent = path.parent().unwrap_or(std::path::Path::new("."));
-        let mut tmp = tempfile::NamedTempFile::new_in(parent)
-            .context("creating temporary file")?;
-        tmp.write_all(formatted.as_bytes())
-            .context("writing temporary file")?;
-        tmp.persist(path)
-            .context("replacing file with updated content")?;
+        let formatted = match preserved {
+            Some(text) => text.into_bytes(),
+            None => render_results(&results, out_fmt, &cli, false)?,
+        };
+        write_in_place(cli.files.first().unwrap(), out_fmt, &formatted, &cli)?;
+    } else if let Some(text) = preserved {
+        stdout.write_all(text.as_bytes()).context("writing output")?;
+        stdout.flush().context("writing output")?;
     } else {
-        output_results(&results, out_fmt, &cli, colorize)?;
+        output_results(&mut stdout, &results, out_fmt, &cli, colorize)?;
+    }
+
+    Ok(())
+}
+
+/// `--repl`: run each line of stdin as a query against `value`, reporting
+/// errors and carrying on. A prompt is shown when stdin is a terminal.
+fn repl(
+    out: &mut dyn Write,
+    value: &serde_json::Value,
+    out_fmt: Format,
+    cli: &Cli,
+    env: &Env,
+    colorize: bool,
+) -> Result<()> {
+    let stdin = std::io::stdin();
+    let interactive = stdin.is_terminal();
+    let mut lines = stdin.lock().lines();
+    loop {
+        if interactive {
+            eprint!("qf> ");
+        }
+        let Some(line) = lines.next() else {
+            break;
+        };
+        let line = line.context("reading query")?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let outcome = query::query_with_env(value, line, env)
+            .map_err(anyhow::Error::from)
+            .and_then(|results| output_results(out, &results, out_fmt, cli, colorize));
+        if let Err(e) = outcome {
+            report_error(cli.error_format, &e);
+        }
+    }
+    if interactive {
+        eprintln!();
+    }
+    Ok(())
+}
+
+/// `--yaml-preserve`: the original YAML `input` with the query's changes
+/// applied to its text.
+fn preserve_yaml(
+    input: &str,
+    value: &serde_json::Value,
+    results: &[serde_json::Value],
+    in_fmt: Format,
+    out_fmt: Format,
+) -> Result<String> {
+    if in_fmt != Format::Yaml || out_fmt != Format::Yaml {
+        anyhow::bail!("--yaml-preserve requires YAML input and output");
+    }
+    let [result] = results else {
+        anyhow::bail!("--yaml-preserve requires the query to produce exactly one value");
+    };
+    Ok(output::yaml_edit::update(input, value, result)?)
+}
+
+/// With `--args`/`--jsonargs`, take the arguments after the query out of
+/// `cli.files` as the values for `$ARGS.positional`; input is then read from
+/// stdin.
+fn positional_args(cli: &mut Cli) -> Result<Vec<serde_json::Value>> {
+    if !(cli.args || cli.jsonargs) {
+        return Ok(Vec::new());
+    }
+    std::mem::take(&mut cli.files)
+        .into_iter()
+        .map(|arg| {
+            let text = arg.to_string_lossy().into_owned();
+            if cli.jsonargs {
+                serde_json::from_str(&text)
+                    .with_context(|| format!("invalid JSON text passed to --jsonargs: {text}"))
+            } else {
+                Ok(serde_json::Value::String(text))
+            }
+        })
+        .collect()
+}
+
+/// Build the evaluation environment from `--arg`/`--argjson`/`--rawfile`/
+/// `--slurpfile`, exposing each binding as `$name` and collectively under
+/// `$ARGS.named`, with `positional` as `$ARGS.positional`.
+fn build_env(cli: &Cli, positional: Vec<serde_json::Value>) -> Result<Env> {
+    let mut named = serde_json::Map::new();
+    for pair in cli.arg.chunks(2) {
+        named.insert(pair[0].clone(), serde_json::Value::String(pair[1].clone()));
+    }
+    for pair in cli.argjson.chunks(2) {
+        let value: serde_json::Value = serde_json::from_str(&pair[1])
+            .with_context(|| format!("invalid JSON text passed to --argjson {}", pair[0]))?;
+        named.insert(pair[0].clone(), value);
+    }
+    for pair in cli.rawfile.chunks(2) {
+        let text = std::fs::read_to_string(&pair[1])
+            .with_context(|| format!("failed to read --rawfile {} {}", pair[0], pair[1]))?;
+        named.insert(pair[0].clone(), serde_json::Value::String(text));
     }
+    for pair in cli.slurpfile.chunks(2) {
+        let text = std::fs::read_to_string(&pair[1])
+            .with_context(|| format!("failed to read --slurpfile {} {}", pair[0], pair[1]))?;
+        let values = serde_json::Deserializer::from_str(&text)
+            .into_iter::<serde_json::Value>()
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|| format!("invalid JSON in --slurpfile {} {}", pair[0], pair[1]))?;
+        named.insert(pair[0].clone(), serde_json::Value::Array(values));
+    }
+
+    let mut env = Env::new();
+    for (name, value) in &named {
+        env.set_var(name.clone(), value.clone());
+    }
+    env.set_var(
+        "ARGS".into(),
+        serde_json::json!({ "positional": positional, "named": named }),
+    );
+    env.set_tojson_indent(cli.tojson_indent);
+    env.set_parallel_map(cli.parallel_map);
+    env.set_max_outputs(cli.max_outputs);
+    env.set_max_depth(Some(cli.max_depth));
+    env.set_library_path(cli.library_path.clone());
+    Ok(env)
+}
+
+/// Collect format-specific input options from the command line.
+fn parse_options(cli: &Cli) -> Result<parser::ParseOptions> {
+    let delimiter = match cli.csv_delimiter {
+        Some(c) if c.is_ascii() => Some(c as u8),
+        Some(c) => anyhow::bail!("--csv-delimiter must be a single ASCII character, got {c:?}"),
+        None => None,
+    };
+    Ok(parser::ParseOptions {
+        csv: parser::csv::CsvOptions {
+            delimiter,
+            headers: !cli.csv_no_header,
+            infer_types: cli.csv_infer_types,
+        },
+        duplicate_keys: cli.duplicate_keys,
+    })
+}
 
+/// Run the query over every input file on a pool of `--jobs` threads.
+///
+/// Output is buffered per file and written in argument order, each file as
+/// soon as it and every file before it are done. A failing file is reported
+/// on stderr and skipped, unless `--fail-fast` is set.
+fn process_files(
+    cli: &Cli,
+    parse_opts: &parser::ParseOptions,
+    env: &Env,
+    colorize: bool,
+) -> Result<()> {
+    use rayon::prelude::*;
+
+    let program = query::Program::compile_with_env(&cli.query, env)?;
+    // Lowest index of a failed file, so --fail-fast can skip anything after it
+    let first_failure = AtomicUsize::new(usize::MAX);
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(cli.jobs)
+        .stack_size(query_stack_size(cli))
+        .build()
+        .context("starting worker threads")?;
+
+    // Workers hand each file's output over as soon as it is rendered, and
+    // this thread writes it once every file before it has been written
+    let (tx, rx) = std::sync::mpsc::channel();
+    let stop = AtomicBool::new(false);
+    let failures = std::thread::scope(|scope| {
+        let (program, first_failure, stop, pool) = (&program, &first_failure, &stop, &pool);
+        scope.spawn(move || {
+            pool.install(|| {
+                cli.files.par_iter().enumerate().for_each_with(tx, |tx, (i, path)| {
+                    let skip = cli.fail_fast && i > first_failure.load(Ordering::Relaxed);
+                    if skip || stop.load(Ordering::Relaxed) {
+                        let _ = tx.send((i, None));
+                        return;
+                    }
+                    let result = process_file(path, program, cli, parse_opts, env, colorize)
+                        .with_context(|| format!("processing {}", path.display()));
+                    if result.is_err() {
+                        first_failure.fetch_min(i, Ordering::Relaxed);
+                    }
+                    let _ = tx.send((i, Some(result)));
+                })
+            })
+        });
+        let mut writer = InOrder::new(std::io::stdout().lock(), cli);
+        let written = rx.into_iter().try_for_each(|(i, output)| writer.push(i, output));
+        if written.is_err() {
+            // Nobody is left to write what the workers still have to do
+            stop.store(true, Ordering::Relaxed);
+        }
+        written.map(|()| writer.failures)
+    })?;
+
+    if failures > 0 {
+        anyhow::bail!("{failures} of {} files failed", cli.files.len());
+    }
+    Ok(())
+}
+
+/// Writes each file's output in file order as it comes in, holding back only
+/// the files that finish before one ahead of them.
+struct InOrder<W> {
+    out: W,
+    /// Index of the next file to write.
+    next: usize,
+    /// Outputs of files that finished before `next`; `None` when skipped.
+    pending: std::collections::BTreeMap<usize, Option<Result<Vec<u8>>>>,
+    fail_fast: bool,
+    error_format: ErrorFormat,
+    failures: usize,
+}
+
+impl<W: Write> InOrder<W> {
+    fn new(out: W, cli: &Cli) -> Self {
+        InOrder {
+            out,
+            next: 0,
+            pending: Default::default(),
+            fail_fast: cli.fail_fast,
+            error_format: cli.error_format,
+            failures: 0,
+        }
+    }
+
+    /// Take the output of file `i`, then write everything now in order.
+    fn push(&mut self, i: usize, output: Option<Result<Vec<u8>>>) -> Result<()> {
+        self.pending.insert(i, output);
+        while let Some(output) = self.pending.remove(&self.next) {
+            self.next += 1;
+            match output {
+                Some(Ok(bytes)) => {
+                    self.out.write_all(&bytes).context("writing output")?;
+                    self.out.flush().context("writing output")?;
+                }
+                Some(Err(e)) if self.fail_fast => return Err(e),
+                Some(Err(e)) => {
+                    report_error(self.error_format, &e);
+                    self.failures += 1;
+                }
+                // Skipped because an earlier file failed under --fail-fast
+                None => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+fn process_file(
+    path: &Path,
+    program: &query::Program,
+    cli: &Cli,
+    parse_opts: &parser::ParseOptions,
+    env: &Env,
+    colorize: bool,
+) -> Result<Vec<u8>> {
+    let (in_fmt, out_fmt) = file_formats(path, cli)?;
+    let mut bytes = read_file(path)?;
+    if cli.seq {
+        bytes = strip_record_separators(bytes);
+    }
+    let value = parser::parse_bytes_with(&bytes, in_fmt, parse_opts)?;
+    let mut env = env.clone();
+    env.set_input_filename(Some(path.display().to_string()));
+    let results = program.run_with_env(&value, &env)?;
+    render_results(&results, out_fmt, cli, colorize)
+}
+
+/// `--in-place` with several files: each one is queried and rewritten on
+/// its own, in its own format. Stops at the first file that fails, leaving
+/// it and the files after it untouched.
+fn edit_files_in_place(cli: &Cli, parse_opts: &parser::ParseOptions, env: &Env) -> Result<()> {
+    let program = query::Program::compile_with_env(&cli.query, env)?;
+    for path in &cli.files {
+        edit_file_in_place(path, &program, cli, parse_opts, env)
+            .with_context(|| format!("editing {}", path.display()))?;
+    }
+    Ok(())
+}
+
+fn edit_file_in_place(
+    path: &Path,
+    program: &query::Program,
+    cli: &Cli,
+    parse_opts: &parser::ParseOptions,
+    env: &Env,
+) -> Result<()> {
+    let (in_fmt, out_fmt) = file_formats(path, cli)?;
+    let mut bytes = read_file(path)?;
+    if cli.seq {
+        bytes = strip_record_separators(bytes);
+    }
+    let value = parser::parse_bytes_with(&bytes, in_fmt, parse_opts)?;
+    let mut env = env.clone();
+    env.set_input_filename(Some(path.display().to_string()));
+    let results = program.run_with_env(&value, &env)?;
+    let formatted = if cli.yaml_preserve {
+        let text = std::str::from_utf8(&bytes).context("input is not valid UTF-8")?;
+        preserve_yaml(text, &value, &results, in_fmt, out_fmt)?.into_bytes()
+    } else {
+        render_results(&results, out_fmt, cli, false)?
+    };
+    write_in_place(path, out_fmt, &formatted, cli)
+}
+
+/// The input and output formats for `path`: from the flags if given,
+/// otherwise from its extension (output defaulting to the input format, and
+/// in place always so).
+fn file_formats(path: &Path, cli: &Cli) -> Result<(Format, Format)> {
+    let in_fmt = match &cli.input_format {
+        Some(f) => Format::from_str_name(f)?,
+        None => format_from_path(path)?,
+    };
+    let out_fmt = match &cli.output_format {
+        Some(f) => Format::from_str_name(f)?,
+        None if cli.in_place => in_fmt,
+        None => in_fmt.default_output(),
+    };
+    Ok((in_fmt, out_fmt))
+}
+
+/// Write the edited contents of `path`. With `--rename-extension`, they go
+/// to a file named for `out_fmt` instead, and `path` is removed; an existing
+/// file of that name is never overwritten.
+fn write_in_place(path: &Path, out_fmt: Format, bytes: &[u8], cli: &Cli) -> Result<()> {
+    if !cli.rename_extension || format_from_path(path).is_ok_and(|f| f == out_fmt) {
+        return replace_file(path, bytes);
+    }
+    let Some(ext) = out_fmt.extension() else {
+        let name = cli.output_format.as_deref().unwrap_or_default();
+        anyhow::bail!("--rename-extension: {name} output has no file extension");
+    };
+    let target = path.with_extension(ext);
+    if target.exists() {
+        anyhow::bail!("--rename-extension: {} already exists", target.display());
+    }
+    replace_file(&target, bytes)?;
+    std::fs::remove_file(path).with_context(|| format!("removing {}", path.display()))
+}
+
+/// Atomically replace the contents of `path` with `bytes`, via a temporary
+/// file in the same directory.
+fn replace_file(path: &Path, bytes: &[u8]) -> Result<()> {
+    let parent = path.parent().unwrap_or(Path::new("."));
+    let mut tmp = tempfile::NamedTempFile::new_in(parent).context("creating temporary file")?;
+    tmp.write_all(bytes).context("writing temporary file")?;
+    tmp.persist(path).context("replacing file with updated content")?;
     Ok(())
 }
 
 fn should_colorize(cli: &Cli) -> bool {
-    if cli.no_color {
+    if cli.no_color || cli.monochrome_output {
         return false;
     }
     if std::env::var("NO_COLOR").is_ok() {
@@ -285,53 +926,414 @@ fn should_colorize(cli: &Cli) -> bool {
     }
 }
 
+/// The theme named by `--theme` with `$QF_COLORS` applied on top. An
+/// invalid `$QF_COLORS` is reported once and ignored, as jq does.
+fn color_theme(cli: &Cli) -> &'static Theme {
+    static THEME: OnceLock<Theme> = OnceLock::new();
+    THEME.get_or_init(|| {
+        let mut theme = Theme::named(&cli.theme).unwrap_or_default();
+        if let Ok(spec) = std::env::var("QF_COLORS") {
+            if let Err(e) = theme.apply_spec(&spec) {
+                eprintln!("qf: ignoring $QF_COLORS: {e}");
+            }
+        }
+        theme
+    })
+}
+
+/// What follows each output value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Separator {
+    /// A newline, unless the formatted value already ends with one
+    Newline,
+    /// Nothing; outputs are concatenated (`-j`)
+    Nothing,
+    /// A NUL byte; every output must be a string (`--raw-output0`)
+    Nul,
+    /// An RS byte before and a newline after (`--seq`)
+    Seq,
+}
+
+impl Separator {
+    fn from_cli(cli: &Cli) -> Self {
+        if cli.raw_output0 {
+            Separator::Nul
+        } else if cli.seq {
+            Separator::Seq
+        } else if cli.join_output {
+            Separator::Nothing
+        } else {
+            Separator::Newline
+        }
+    }
+
+    /// `--raw-output0` implies `-r`: its outputs are bare strings.
+    fn implies_raw(self) -> bool {
+        self == Separator::Nul
+    }
+}
+
+/// Format one result and append it to `buf` followed by `sep`.
+fn push_result(
+    buf: &mut String,
+    result: &serde_json::Value,
+    out_fmt: Format,
+    cli: &Cli,
+    colorize: bool,
+    sep: Separator,
+) -> Result<()> {
+    if sep == Separator::Nul && !result.is_string() {
+        anyhow::bail!(
+            "--raw-output0 requires string outputs, got {}",
+            query::eval::value_type(result)
+        );
+    }
+    let opts = output::pretty::OutputOptions {
+        compact: cli.compact,
+        compact_objects: cli.compact_objects,
+        raw: cli.raw || sep.implies_raw(),
+        colorize,
+        csv_null: cli.csv_null.clone(),
+        yaml: output::yaml::YamlStyle {
+            indent: cli.yaml_indent.into(),
+            flow_leaves: cli.yaml_flow,
+        },
+        xml: output::xml::XmlStyle {
+            root: cli.xml_root.clone(),
+        },
+        theme: if colorize { color_theme(cli) } else { Theme::shared_default() },
+    };
+    let formatted = output::pretty::format_value_with(result, out_fmt, &opts)?;
+    if sep == Separator::Seq {
+        buf.push(RECORD_SEPARATOR as char);
+    }
+    buf.push_str(&formatted);
+    match sep {
+        Separator::Newline | Separator::Seq if !formatted.ends_with('\n') => buf.push('\n'),
+        Separator::Newline | Separator::Seq | Separator::Nothing => {}
+        Separator::Nul => buf.push('\0'),
+    }
+    Ok(())
+}
+
+/// Write results in a text format to `out`, each one as soon as it is
+/// formatted.
 fn format_results(
+    out: &mut dyn Write,
     results: &[serde_json::Value],
     out_fmt: Format,
     cli: &Cli,
     colorize: bool,
-) -> Result<String, anyhow::Error> {
-    let mut buf = String::new();
+) -> Result<()> {
+    let sep = Separator::from_cli(cli);
     let is_yaml = out_fmt == Format::Yaml;
+    let explicit = is_yaml && cli.yaml_explicit;
+    let raw = cli.raw || sep.implies_raw();
+    let mut documents = 0;
 
     for (i, result) in results.iter().enumerate() {
-        if is_yaml && i > 0 {
+        let mut buf = String::new();
+        // Raw strings are plain lines of text, not YAML documents
+        let is_document = !(raw && result.is_string());
+        if is_document && (explicit || (is_yaml && i > 0)) {
             buf.push_str("---\n");
         }
-        let formatted = output::pretty::format_value_colored(
-            result, out_fmt, cli.compact, cli.raw, colorize,
-        )?;
-        buf.push_str(&formatted);
-        if !formatted.ends_with('\n') {
-            if !cli.join_output {
-                buf.push('\n');
+        push_result(&mut buf, result, out_fmt, cli, colorize, sep)?;
+        documents += usize::from(is_document);
+        out.write_all(buf.as_bytes()).context("writing output")?;
+    }
+    if explicit && documents > 0 {
+        out.write_all(b"...\n").context("writing output")?;
+    }
+    Ok(())
+}
+
+/// Print each result of a record stream as soon as it is produced, flushing
+/// each one with `--unbuffered`. With `--skip-errors`, malformed records are
+/// reported and counted instead.
+fn print_records(
+    out: &mut dyn Write,
+    records: stream::Records,
+    out_fmt: Format,
+    cli: &Cli,
+    colorize: bool,
+    sep: Separator,
+) -> Result<()> {
+    let mut skipped = 0;
+    let records = if cli.skip_errors {
+        records.skip_errors(|line, e| {
+            match line {
+                Some(line) => eprintln!("qf: skipping line {line}: {e}"),
+                None => eprintln!("qf: skipping record: {e}"),
             }
+            skipped += 1;
+        })
+    } else {
+        records
+    };
+    for result in records {
+        let mut buf = String::new();
+        push_result(&mut buf, &result?, out_fmt, cli, colorize, sep)?;
+        out.write_all(buf.as_bytes()).context("writing output")?;
+        // Outputs without a trailing newline (-j, --raw-output0) would
+        // otherwise sit in the buffer
+        if cli.unbuffered {
+            out.flush().context("writing output")?;
         }
     }
-
-    Ok(buf)
+    out.flush().context("writing output")?;
+    if skipped > 0 {
+        eprintln!("qf: skipped {skipped} malformed record(s)");
+    }
+    Ok(())
 }
 
 fn output_results(
+    out: &mut dyn Write,
     results: &[serde_json::Value],
     out_fmt: Format,
     cli: &Cli,
     colorize: bool,
 ) -> Result<()> {
-    let formatted = format_results(results, out_fmt, cli, colorize)?;
-    print!("{formatted}");
+    write_results(out, results, out_fmt, cli, colorize)?;
+    out.flush().context("writing output")?;
     Ok(())
 }
 
-/// Try to detect format from content when no file extension is available.
-fn detect_format(input: &str) -> Result<Format, QfError> {
-    let trimmed = input.trim_start();
-    if trimmed.starts_with('{') || trimmed.starts_with('[') {
-        Ok(Format::Json)
-    } else if trimmed.starts_with('<') {
-        Ok(Format::Xml)
+/// Write results to `out`: binary formats as a sequence of encoded items,
+/// text formats through `format_results`.
+fn write_results(
+    out: &mut dyn Write,
+    results: &[serde_json::Value],
+    out_fmt: Format,
+    cli: &Cli,
+    colorize: bool,
+) -> Result<()> {
+    if out_fmt.is_binary() {
+        for result in results {
+            let bytes = output::pretty::format_value_bytes(result, out_fmt)?;
+            out.write_all(&bytes).context("writing output")?;
+        }
+        return Ok(());
+    }
+    format_results(out, results, out_fmt, cli, colorize)
+}
+
+/// Render results as bytes, for output that is not written straight out.
+fn render_results(
+    results: &[serde_json::Value],
+    out_fmt: Format,
+    cli: &Cli,
+    colorize: bool,
+) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    write_results(&mut buf, results, out_fmt, cli, colorize)?;
+    Ok(buf)
+}
+
+/// The byte that starts each text in an RFC 7464 JSON text sequence.
+const RECORD_SEPARATOR: u8 = 0x1e;
+
+/// Blank out the record separators of a JSON text sequence so the texts
+/// parse as plain whitespace-separated JSON.
+fn strip_record_separators(mut bytes: Vec<u8>) -> Vec<u8> {
+    for b in bytes.iter_mut().filter(|b| **b == RECORD_SEPARATOR) {
+        *b = b'\n';
+    }
+    bytes
+}
+
+/// The two leading bytes of every gzip stream.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+fn is_gzip_path(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("gz"))
+}
+
+/// Detect the format from a path's extension, looking through a trailing
+/// `.gz` to the inner extension (`data.json.gz` is JSON).
+fn format_from_path(path: &Path) -> Result<Format, QfError> {
+    if is_gzip_path(path) {
+        Format::from_extension(&path.with_extension(""))
     } else {
-        // Default to YAML — it's a superset of many plain text formats
-        Ok(Format::Yaml)
+        Format::from_extension(path)
+    }
+}
+
+/// Read a file, transparently decompressing it if it has a `.gz` suffix.
+fn read_file(path: &Path) -> Result<Vec<u8>> {
+    let bytes = std::fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+    if is_gzip_path(path) {
+        gunzip(&bytes).with_context(|| format!("decompressing {}", path.display()))
+    } else {
+        Ok(bytes)
+    }
+}
+
+fn gunzip(bytes: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    flate2::read::MultiGzDecoder::new(bytes).read_to_end(&mut out)?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// The options `qf FLAGS .` is run with.
+    fn cli(flags: &[&str]) -> Cli {
+        Cli::parse_from(["qf"].iter().chain(flags).chain(&["."]))
+    }
+
+    fn render(flags: &[&str], results: &[serde_json::Value]) -> Result<String> {
+        format_text(results, Format::Json, &cli(flags))
+    }
+
+    fn format_text(results: &[serde_json::Value], out_fmt: Format, cli: &Cli) -> Result<String> {
+        let mut out = Vec::new();
+        format_results(&mut out, results, out_fmt, cli, false)?;
+        Ok(String::from_utf8(out)?)
+    }
+
+    #[test]
+    fn yaml_document_markers() {
+        let results = [json!({"a": 1}), json!("b")];
+        let plain = format_text(&results, Format::Yaml, &cli(&[])).unwrap();
+        assert_eq!(plain, "a: 1\n---\nb\n");
+        let explicit = cli(&["--yaml-explicit"]);
+        let out = format_text(&results, Format::Yaml, &explicit).unwrap();
+        assert_eq!(out, "---\na: 1\n---\nb\n...\n");
+        // A single result only gets markers when asked for
+        let single = format_text(&results[..1], Format::Yaml, &cli(&[])).unwrap();
+        assert_eq!(single, "a: 1\n");
+        let single = format_text(&results[..1], Format::Yaml, &explicit).unwrap();
+        assert_eq!(single, "---\na: 1\n...\n");
+        // Other formats ignore the flag
+        let json = format_text(&results[..1], Format::Json, &explicit).unwrap();
+        assert_eq!(json, "{\n  \"a\": 1\n}\n");
+    }
+
+    #[test]
+    fn monochrome_overrides_color_always() {
+        assert!(!should_colorize(&cli(&["--color", "always", "-M"])));
+        assert!(!should_colorize(&cli(&["--color", "always", "--monochrome-output"])));
+    }
+
+    #[test]
+    fn separator_from_flags() {
+        let sep = |flags: &[&str]| Separator::from_cli(&cli(flags));
+        assert_eq!(sep(&[]), Separator::Newline);
+        assert_eq!(sep(&["-r"]), Separator::Newline);
+        assert_eq!(sep(&["-j"]), Separator::Nothing);
+        assert_eq!(sep(&["-j", "-r"]), Separator::Nothing);
+        assert_eq!(sep(&["--raw-output0"]), Separator::Nul);
+        assert_eq!(sep(&["-j", "--raw-output0"]), Separator::Nul);
+        assert_eq!(sep(&["--seq"]), Separator::Seq);
+        assert_eq!(sep(&["--seq", "-j"]), Separator::Seq);
+    }
+
+    #[test]
+    fn seq_frames_every_output() {
+        let out = render(&["--seq", "-c"], &[json!({"a": 1}), json!("b")]).unwrap();
+        assert_eq!(out, "\x1e{\"a\":1}\n\x1e\"b\"\n");
+        let raw = render(&["--seq", "-r"], &[json!("b")]).unwrap();
+        assert_eq!(raw, "\x1eb\n");
+    }
+
+    #[test]
+    fn seq_input_separators_become_whitespace() {
+        let bytes = strip_record_separators(b"\x1e{\"a\":1}\n\x1e2\x1e".to_vec());
+        assert_eq!(bytes, b"\n{\"a\":1}\n\n2\n");
+    }
+
+    #[test]
+    fn default_quotes_strings_one_per_line() {
+        let out = render(&["-c"], &[json!("a"), json!(1), json!({"k": "v"})]).unwrap();
+        assert_eq!(out, "\"a\"\n1\n{\"k\":\"v\"}\n");
+    }
+
+    #[test]
+    fn raw_unquotes_strings_one_per_line() {
+        let out = render(&["-r", "-c"], &[json!("a"), json!(1), json!("b")]).unwrap();
+        assert_eq!(out, "a\n1\nb\n");
+    }
+
+    #[test]
+    fn join_concatenates_outputs() {
+        let results = [json!("a"), json!(1), json!("b")];
+        assert_eq!(render(&["-j"], &results).unwrap(), "\"a\"1\"b\"");
+        assert_eq!(render(&["-j", "-r"], &results).unwrap(), "a1b");
+    }
+
+    #[test]
+    fn join_leaves_non_strings_as_json() {
+        let out = render(&["-j", "-c"], &[json!({"k": "v"}), json!([1])]).unwrap();
+        assert_eq!(out, "{\"k\":\"v\"}[1]");
+    }
+
+    #[test]
+    fn nul_terminates_every_string() {
+        let results = [json!("a"), json!("b c")];
+        assert_eq!(render(&["--raw-output0"], &results).unwrap(), "a\0b c\0");
+        assert_eq!(render(&["--raw-output0", "-j"], &results).unwrap(), "a\0b c\0");
+    }
+
+    #[test]
+    fn nul_rejects_non_strings() {
+        assert!(render(&["--raw-output0"], &[json!("a"), json!(1)]).is_err());
+        // Results before the bad one have already been written
+        let mut out = Vec::new();
+        let results = [json!("a"), json!(1)];
+        let nul = cli(&["--raw-output0"]);
+        assert!(format_results(&mut out, &results, Format::Json, &nul, false).is_err());
+        assert_eq!(out, b"a\0");
+    }
+
+    #[test]
+    fn output_results_writes_to_any_writer() {
+        let mut out = Vec::new();
+        let results = [json!({"a": 1}), json!("x")];
+        output_results(&mut out, &results, Format::Json, &cli(&["-c"]), false).unwrap();
+        assert_eq!(out, b"{\"a\":1}\n\"x\"\n");
+    }
+
+    #[test]
+    #[cfg(feature = "cbor")]
+    fn output_results_writes_binary_values_in_turn() {
+        let mut out = Vec::new();
+        output_results(&mut out, &[json!(1), json!(2)], Format::Cbor, &cli(&[]), false).unwrap();
+        assert_eq!(out, [0x01, 0x02]);
+    }
+
+    #[test]
+    fn raw_strings_skip_yaml_markers() {
+        let results = [json!("x"), json!("y"), json!({"a": 1})];
+        let out = format_text(&results, Format::Yaml, &cli(&["-r"])).unwrap();
+        assert_eq!(out, "x\ny\n---\na: 1\n");
+        let out = format_text(&results, Format::Yaml, &cli(&["-r", "--yaml-explicit"])).unwrap();
+        assert_eq!(out, "x\ny\n---\na: 1\n...\n");
+        // Without -r the strings are documents of their own
+        let out = format_text(&results[..2], Format::Yaml, &cli(&[])).unwrap();
+        assert_eq!(out, "x\n---\ny\n");
+    }
+
+    #[test]
+    fn files_are_written_once_the_ones_before_them_are() {
+        let mut writer = InOrder::new(Vec::new(), &cli(&[]));
+        writer.push(1, Some(Ok(b"b".to_vec()))).unwrap();
+        assert!(writer.out.is_empty());
+        writer.push(0, Some(Ok(b"a".to_vec()))).unwrap();
+        assert_eq!(writer.out, b"ab");
+        writer.push(3, None).unwrap();
+        writer.push(2, Some(Err(anyhow::anyhow!("bad")))).unwrap();
+        writer.push(4, Some(Ok(b"e".to_vec()))).unwrap();
+        assert_eq!((writer.out.as_slice(), writer.failures), (&b"abe"[..], 1));
+        // With --fail-fast the first failure in file order is the error
+        let mut writer = InOrder::new(Vec::new(), &cli(&["--fail-fast"]));
+        writer.push(1, Some(Err(anyhow::anyhow!("second")))).unwrap();
+        let err = writer.push(0, Some(Err(anyhow::anyhow!("first")))).unwrap_err();
+        assert_eq!(err.to_string(), "first");
     }
 }
@@ -1,40 +1,154 @@
+use std::sync::OnceLock;
+
 use serde_json::Value;
 
-// ANSI color codes
+use crate::error::QfError;
+
 const RESET: &str = "\x1b[0m";
-const BOLD_BLUE: &str = "\x1b[1;34m";
-const GREEN: &str = "\x1b[0;32m";
-const CYAN: &str = "\x1b[0;36m";
-const YELLOW: &str = "\x1b[0;33m";
-const RED: &str = "\x1b[0;31m";
-const BOLD_WHITE: &str = "\x1b[1;37m";
+
+/// The ANSI escape sequence used for each kind of token.
+///
+/// XML and TOML reuse the same slots: tags and keys take `object_key`,
+/// attribute names `number`, comments `null` and TOML table headers
+/// `object`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Theme {
+    pub null: String,
+    pub false_value: String,
+    pub true_value: String,
+    pub number: String,
+    pub string: String,
+    pub array: String,
+    pub object: String,
+    pub object_key: String,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme::from_codes(["0;31", "0;33", "0;33", "0;36", "0;32", "1;37", "1;37", "1;34"])
+    }
+}
+
+impl Theme {
+    /// The default theme, built once and shared.
+    pub fn shared_default() -> &'static Theme {
+        static DEFAULT: OnceLock<Theme> = OnceLock::new();
+        DEFAULT.get_or_init(Theme::default)
+    }
+
+    /// Names accepted by [`Theme::named`].
+    pub const NAMES: [&'static str; 2] = ["default", "solarized"];
+
+    /// A built-in theme by name.
+    pub fn named(name: &str) -> Option<Theme> {
+        match name {
+            "default" => Some(Theme::default()),
+            "solarized" => Some(Theme::solarized()),
+            _ => None,
+        }
+    }
+
+    /// The Solarized palette, in 256-color codes.
+    pub fn solarized() -> Theme {
+        Theme::from_codes([
+            "38;5;240",
+            "38;5;136",
+            "38;5;136",
+            "38;5;61",
+            "38;5;37",
+            "1;38;5;245",
+            "1;38;5;245",
+            "1;38;5;33",
+        ])
+    }
+
+    fn from_codes(codes: [&str; 8]) -> Theme {
+        let [null, false_value, true_value, number, string, array, object, object_key] =
+            codes.map(sgr);
+        Theme {
+            null,
+            false_value,
+            true_value,
+            number,
+            string,
+            array,
+            object,
+            object_key,
+        }
+    }
+
+    /// Override colors from a `JQ_COLORS`-style spec: colon-separated SGR
+    /// codes for null, false, true, numbers, strings, arrays, objects and
+    /// object keys, in that order. Missing trailing fields keep their
+    /// current color; `38;5;N` and `38;2;R;G;B` select 256-color and
+    /// truecolor codes.
+    pub fn apply_spec(&mut self, spec: &str) -> Result<(), QfError> {
+        let fields = spec.split(':').collect::<Vec<_>>();
+        if fields.len() > 8 {
+            return Err(QfError::Runtime(format!(
+                "too many colors in {spec:?} (at most 8)"
+            )));
+        }
+        for code in &fields {
+            if code.is_empty() || !code.chars().all(|c| c.is_ascii_digit() || c == ';') {
+                return Err(QfError::Runtime(format!("invalid color {code:?}")));
+            }
+        }
+        let slots = [
+            &mut self.null,
+            &mut self.false_value,
+            &mut self.true_value,
+            &mut self.number,
+            &mut self.string,
+            &mut self.array,
+            &mut self.object,
+            &mut self.object_key,
+        ];
+        for (slot, code) in slots.into_iter().zip(fields) {
+            *slot = sgr(code);
+        }
+        Ok(())
+    }
+}
+
+/// The escape sequence selecting SGR `code`.
+fn sgr(code: &str) -> String {
+    format!("\x1b[{code}m")
+}
 
 /// Colorize a JSON value into a pretty-printed string with ANSI color codes.
-pub fn colorize_json(value: &Value) -> String {
+pub fn colorize_json(value: &Value, theme: &Theme) -> String {
+    colorize_json_with(value, theme, false)
+}
+
+/// Like [`colorize_json`], keeping objects of scalars on one line when
+/// `compact_objects` is set, as `--compact-objects` lays them out.
+pub fn colorize_json_with(value: &Value, theme: &Theme, compact_objects: bool) -> String {
     let mut buf = String::new();
-    write_value(value, &mut buf, 0);
+    write_value(value, &mut buf, 0, theme, compact_objects);
     buf
 }
 
-fn write_value(value: &Value, buf: &mut String, indent: usize) {
+fn write_value(value: &Value, buf: &mut String, indent: usize, theme: &Theme, flat: bool) {
+    let scalar = |v: &Value| !v.is_array() && !v.is_object();
     match value {
         Value::Null => {
-            buf.push_str(RED);
+            buf.push_str(&theme.null);
             buf.push_str("null");
             buf.push_str(RESET);
         }
         Value::Bool(b) => {
-            buf.push_str(YELLOW);
+            buf.push_str(if *b { &theme.true_value } else { &theme.false_value });
             buf.push_str(if *b { "true" } else { "false" });
             buf.push_str(RESET);
         }
         Value::Number(n) => {
-            buf.push_str(CYAN);
+            buf.push_str(&theme.number);
             buf.push_str(&n.to_string());
             buf.push_str(RESET);
         }
         Value::String(s) => {
-            buf.push_str(GREEN);
+            buf.push_str(&theme.string);
             buf.push('"');
             buf.push_str(&escape_json_string(s));
             buf.push('"');
@@ -42,62 +156,82 @@ fn write_value(value: &Value, buf: &mut String, indent: usize) {
         }
         Value::Array(arr) => {
             if arr.is_empty() {
-                buf.push_str(BOLD_WHITE);
+                buf.push_str(&theme.array);
                 buf.push_str("[]");
                 buf.push_str(RESET);
                 return;
             }
-            buf.push_str(BOLD_WHITE);
+            buf.push_str(&theme.array);
             buf.push('[');
             buf.push_str(RESET);
             buf.push('\n');
             for (i, item) in arr.iter().enumerate() {
                 write_indent(buf, indent + 1);
-                write_value(item, buf, indent + 1);
+                write_value(item, buf, indent + 1, theme, flat);
                 if i < arr.len() - 1 {
                     buf.push(',');
                 }
                 buf.push('\n');
             }
             write_indent(buf, indent);
-            buf.push_str(BOLD_WHITE);
+            buf.push_str(&theme.array);
             buf.push(']');
             buf.push_str(RESET);
         }
+        Value::Object(map) if flat && map.values().all(scalar) => {
+            buf.push_str(&theme.object);
+            buf.push('{');
+            buf.push_str(RESET);
+            for (i, (key, val)) in map.iter().enumerate() {
+                if i > 0 {
+                    buf.push(',');
+                }
+                write_key(key, buf, theme);
+                buf.push(':');
+                write_value(val, buf, indent, theme, flat);
+            }
+            buf.push_str(&theme.object);
+            buf.push('}');
+            buf.push_str(RESET);
+        }
         Value::Object(map) => {
             if map.is_empty() {
-                buf.push_str(BOLD_WHITE);
+                buf.push_str(&theme.object);
                 buf.push_str("{}");
                 buf.push_str(RESET);
                 return;
             }
-            buf.push_str(BOLD_WHITE);
+            buf.push_str(&theme.object);
             buf.push('{');
             buf.push_str(RESET);
             buf.push('\n');
             let len = map.len();
             for (i, (key, val)) in map.iter().enumerate() {
                 write_indent(buf, indent + 1);
-                buf.push_str(BOLD_BLUE);
-                buf.push('"');
-                buf.push_str(&escape_json_string(key));
-                buf.push('"');
-                buf.push_str(RESET);
+                write_key(key, buf, theme);
                 buf.push_str(": ");
-                write_value(val, buf, indent + 1);
+                write_value(val, buf, indent + 1, theme, flat);
                 if i < len - 1 {
                     buf.push(',');
                 }
                 buf.push('\n');
             }
             write_indent(buf, indent);
-            buf.push_str(BOLD_WHITE);
+            buf.push_str(&theme.object);
             buf.push('}');
             buf.push_str(RESET);
         }
     }
 }
 
+fn write_key(key: &str, buf: &mut String, theme: &Theme) {
+    buf.push_str(&theme.object_key);
+    buf.push('"');
+    buf.push_str(&escape_json_string(key));
+    buf.push('"');
+    buf.push_str(RESET);
+}
+
 fn write_indent(buf: &mut String, level: usize) {
     for _ in 0..level {
         buf.push_str("  ");
@@ -123,21 +257,21 @@ fn escape_json_string(s: &str) -> String {
 }
 
 /// Colorize YAML output by post-processing the serde_yaml string.
-pub fn colorize_yaml(yaml: &str) -> String {
+pub fn colorize_yaml(yaml: &str, theme: &Theme) -> String {
     let mut buf = String::with_capacity(yaml.len() * 2);
     for line in yaml.lines() {
-        colorize_yaml_line(line, &mut buf);
+        colorize_yaml_line(line, &mut buf, theme);
         buf.push('\n');
     }
     buf
 }
 
-fn colorize_yaml_line(line: &str, buf: &mut String) {
+fn colorize_yaml_line(line: &str, buf: &mut String, theme: &Theme) {
     let trimmed = line.trim_start();
 
     // Comment lines
     if trimmed.starts_with('#') {
-        buf.push_str(RED);
+        buf.push_str(&theme.null);
         buf.push_str(line);
         buf.push_str(RESET);
         return;
@@ -145,21 +279,20 @@ fn colorize_yaml_line(line: &str, buf: &mut String) {
 
     // Document separator
     if trimmed == "---" || trimmed == "..." {
-        buf.push_str(BOLD_WHITE);
+        buf.push_str(&theme.array);
         buf.push_str(line);
         buf.push_str(RESET);
         return;
     }
 
     // List item prefix
-    if trimmed.starts_with("- ") {
+    if let Some(rest) = trimmed.strip_prefix("- ") {
         let indent = &line[..line.len() - trimmed.len()];
         buf.push_str(indent);
-        buf.push_str(BOLD_WHITE);
+        buf.push_str(&theme.array);
         buf.push_str("- ");
         buf.push_str(RESET);
-        let rest = &trimmed[2..];
-        colorize_yaml_value_or_key(rest, buf);
+        colorize_yaml_value_or_key(rest, buf, theme);
         return;
     }
 
@@ -169,19 +302,19 @@ fn colorize_yaml_line(line: &str, buf: &mut String) {
         let key = &trimmed[..colon_pos];
         let after_colon = &trimmed[colon_pos + 1..];
         buf.push_str(indent);
-        buf.push_str(BOLD_BLUE);
+        buf.push_str(&theme.object_key);
         buf.push_str(key);
         buf.push_str(RESET);
         buf.push(':');
         if !after_colon.is_empty() {
             buf.push(' ');
-            colorize_yaml_scalar(after_colon.trim_start(), buf);
+            colorize_yaml_scalar(after_colon.trim_start(), buf, theme);
         }
         return;
     }
 
     // Plain scalar or list continuation
-    colorize_yaml_scalar(trimmed, buf);
+    colorize_yaml_scalar(trimmed, buf, theme);
 }
 
 fn find_yaml_colon(s: &str) -> Option<usize> {
@@ -208,53 +341,249 @@ fn find_yaml_colon(s: &str) -> Option<usize> {
     None
 }
 
-fn colorize_yaml_value_or_key(s: &str, buf: &mut String) {
+fn colorize_yaml_value_or_key(s: &str, buf: &mut String, theme: &Theme) {
     if let Some(colon_pos) = find_yaml_colon(s) {
         let key = &s[..colon_pos];
         let after_colon = &s[colon_pos + 1..];
-        buf.push_str(BOLD_BLUE);
+        buf.push_str(&theme.object_key);
         buf.push_str(key);
         buf.push_str(RESET);
         buf.push(':');
         if !after_colon.is_empty() {
             buf.push(' ');
-            colorize_yaml_scalar(after_colon.trim_start(), buf);
+            colorize_yaml_scalar(after_colon.trim_start(), buf, theme);
         }
     } else {
-        colorize_yaml_scalar(s, buf);
+        colorize_yaml_scalar(s, buf, theme);
     }
 }
 
-fn colorize_yaml_scalar(s: &str, buf: &mut String) {
+fn colorize_yaml_scalar(s: &str, buf: &mut String, theme: &Theme) {
     match s {
         "null" | "~" => {
-            buf.push_str(RED);
+            buf.push_str(&theme.null);
             buf.push_str(s);
             buf.push_str(RESET);
         }
         "true" | "false" => {
-            buf.push_str(YELLOW);
+            buf.push_str(if s == "true" { &theme.true_value } else { &theme.false_value });
             buf.push_str(s);
             buf.push_str(RESET);
         }
         _ if s.starts_with('\'') || s.starts_with('"') => {
-            buf.push_str(GREEN);
+            buf.push_str(&theme.string);
             buf.push_str(s);
             buf.push_str(RESET);
         }
         _ if looks_numeric(s) => {
-            buf.push_str(CYAN);
+            buf.push_str(&theme.number);
             buf.push_str(s);
             buf.push_str(RESET);
         }
         _ => {
-            buf.push_str(GREEN);
+            buf.push_str(&theme.string);
             buf.push_str(s);
             buf.push_str(RESET);
         }
     }
 }
 
+/// Colorize XML output: tag names, attribute names and attribute values.
+/// Text content is left as is.
+pub fn colorize_xml(xml: &str, theme: &Theme) -> String {
+    let mut buf = String::with_capacity(xml.len() * 2);
+    let mut rest = xml;
+    while let Some(start) = rest.find('<') {
+        buf.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = if rest.starts_with("<!--") {
+            rest.find("-->").map(|i| i + 3)
+        } else {
+            rest.find('>').map(|i| i + 1)
+        }
+        .unwrap_or(rest.len());
+        let tag = &rest[..end];
+        if tag.starts_with("<?") || tag.starts_with("<!") {
+            push_colored(&mut buf, &theme.null, tag);
+        } else {
+            colorize_xml_tag(tag, &mut buf, theme);
+        }
+        rest = &rest[end..];
+    }
+    buf.push_str(rest);
+    buf
+}
+
+fn colorize_xml_tag(tag: &str, buf: &mut String, theme: &Theme) {
+    let close = if tag.ends_with("/>") {
+        "/>"
+    } else if tag.ends_with('>') {
+        ">"
+    } else {
+        ""
+    };
+    let inner = &tag[..tag.len() - close.len()];
+    let name_end = inner.find(char::is_whitespace).unwrap_or(inner.len());
+    push_colored(buf, &theme.object_key, &inner[..name_end]);
+
+    let mut attrs = &inner[name_end..];
+    while !attrs.is_empty() {
+        let trimmed = attrs.trim_start();
+        buf.push_str(&attrs[..attrs.len() - trimmed.len()]);
+        attrs = trimmed;
+        let Some(eq) = attrs.find('=') else {
+            buf.push_str(attrs);
+            break;
+        };
+        push_colored(buf, &theme.number, &attrs[..eq]);
+        buf.push('=');
+        attrs = &attrs[eq + 1..];
+        let value_end = match attrs.chars().next() {
+            Some(q @ ('"' | '\'')) => attrs[1..].find(q).map_or(attrs.len(), |i| i + 2),
+            _ => attrs.find(char::is_whitespace).unwrap_or(attrs.len()),
+        };
+        push_colored(buf, &theme.string, &attrs[..value_end]);
+        attrs = &attrs[value_end..];
+    }
+    push_colored(buf, &theme.object_key, close);
+}
+
+/// Colorize TOML output line by line: table headers, keys and scalar values.
+/// Lines inside a multi-line string belong to its value and are colored as
+/// a string, their text unchanged.
+pub fn colorize_toml(toml: &str, theme: &Theme) -> String {
+    let mut buf = String::with_capacity(toml.len() * 2);
+    // Delimiter of the multi-line string the current line starts inside
+    let mut open = None;
+    for line in toml.lines() {
+        open = match open {
+            None => {
+                colorize_toml_line(line, &mut buf, theme);
+                toml_open_string(line)
+            }
+            Some(delim) => match find_toml_close(line, delim) {
+                Some(end) => {
+                    push_colored(&mut buf, &theme.string, &line[..end]);
+                    buf.push_str(&line[end..]);
+                    toml_open_string(&line[end..])
+                }
+                None => {
+                    push_colored(&mut buf, &theme.string, line);
+                    Some(delim)
+                }
+            },
+        };
+        buf.push('\n');
+    }
+    buf
+}
+
+/// The delimiter of a multi-line string that `line` opens and leaves open.
+fn toml_open_string(mut line: &str) -> Option<&'static str> {
+    loop {
+        let rest = &line[line.find(['"', '\'', '#'])?..];
+        if rest.starts_with('#') {
+            return None;
+        }
+        let delim = ["\"\"\"", "'''", "\"", "'"].into_iter().find(|d| rest.starts_with(d))?;
+        let body = &rest[delim.len()..];
+        match find_toml_close(body, delim) {
+            Some(end) => line = &body[end..],
+            None if delim.len() == 3 => return Some(delim),
+            None => return None,
+        }
+    }
+}
+
+/// Byte offset just past the `delim` closing a string whose text starts
+/// `s`. Basic strings skip escaped characters, and up to two quotes right
+/// before a multi-line closer belong to the string.
+fn find_toml_close(s: &str, delim: &str) -> Option<usize> {
+    let (bytes, delim) = (s.as_bytes(), delim.as_bytes());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'\\' && delim[0] == b'"' {
+            i += 2;
+            continue;
+        }
+        if bytes[i..].starts_with(delim) {
+            let mut end = i + delim.len();
+            while delim.len() == 3 && end < bytes.len() && end < i + 5 && bytes[end] == delim[0] {
+                end += 1;
+            }
+            return Some(end);
+        }
+        i += 1;
+    }
+    None
+}
+
+fn colorize_toml_line(line: &str, buf: &mut String, theme: &Theme) {
+    let trimmed = line.trim_start();
+    let indent = &line[..line.len() - trimmed.len()];
+    buf.push_str(indent);
+
+    // Table and array-of-tables headers
+    if trimmed.starts_with('[') && trimmed.ends_with(']') && !trimmed.contains(',') {
+        push_colored(buf, &theme.object, trimmed);
+        return;
+    }
+
+    match find_toml_equals(trimmed) {
+        Some(eq) => {
+            let key = trimmed[..eq].trim_end();
+            let value = trimmed[eq + 1..].trim_start();
+            push_colored(buf, &theme.object_key, key);
+            // The spacing around `=` is kept as written
+            buf.push_str(&trimmed[key.len()..trimmed.len() - value.len()]);
+            colorize_toml_value(value, buf, theme);
+        }
+        // Items of a multi-line array
+        None => colorize_toml_value(trimmed, buf, theme),
+    }
+}
+
+fn find_toml_equals(s: &str) -> Option<usize> {
+    let mut quote = None;
+    for (i, c) in s.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '=') => return Some(i),
+            (None, _) => {}
+        }
+    }
+    None
+}
+
+fn colorize_toml_value(s: &str, buf: &mut String, theme: &Theme) {
+    let (value, comma) = match s.strip_suffix(',') {
+        Some(v) => (v, ","),
+        None => (s, ""),
+    };
+    match value {
+        "true" => push_colored(buf, &theme.true_value, value),
+        "false" => push_colored(buf, &theme.false_value, value),
+        _ if value.starts_with('"') || value.starts_with('\'') => {
+            push_colored(buf, &theme.string, value)
+        }
+        _ if looks_numeric(value) => push_colored(buf, &theme.number, value),
+        // Brackets, inline tables, dates
+        _ => buf.push_str(value),
+    }
+    buf.push_str(comma);
+}
+
+fn push_colored(buf: &mut String, color: &str, text: &str) {
+    if text.is_empty() {
+        return;
+    }
+    buf.push_str(color);
+    buf.push_str(text);
+    buf.push_str(RESET);
+}
+
 fn looks_numeric(s: &str) -> bool {
     if s.is_empty() {
         return false;
@@ -289,7 +618,7 @@ mod tests {
     #[test]
     fn colorize_simple_object() {
         let val = json!({"name": "test", "count": 42});
-        let out = colorize_json(&val);
+        let out = colorize_json(&val, &Theme::default());
         assert!(out.contains("\x1b[1;34m\"name\"\x1b[0m"));
         assert!(out.contains("\x1b[0;32m\"test\"\x1b[0m"));
         assert!(out.contains("\x1b[0;36m42\x1b[0m"));
@@ -298,7 +627,7 @@ mod tests {
     #[test]
     fn colorize_null_and_bool() {
         let val = json!({"flag": true, "empty": null});
-        let out = colorize_json(&val);
+        let out = colorize_json(&val, &Theme::default());
         assert!(out.contains("\x1b[0;33mtrue\x1b[0m"));
         assert!(out.contains("\x1b[0;31mnull\x1b[0m"));
     }
@@ -306,7 +635,7 @@ mod tests {
     #[test]
     fn colorize_empty_containers() {
         let val = json!({"arr": [], "obj": {}});
-        let out = colorize_json(&val);
+        let out = colorize_json(&val, &Theme::default());
         assert!(out.contains("[]"));
         assert!(out.contains("{}"));
     }
@@ -314,13 +643,79 @@ mod tests {
     #[test]
     fn colorize_yaml_basic() {
         let yaml = "name: test\ncount: 42\nflag: true\nempty: null\n";
-        let out = colorize_yaml(yaml);
+        let out = colorize_yaml(yaml, &Theme::default());
         assert!(out.contains("\x1b[1;34mname\x1b[0m:"));
         assert!(out.contains("\x1b[0;36m42\x1b[0m"));
         assert!(out.contains("\x1b[0;33mtrue\x1b[0m"));
         assert!(out.contains("\x1b[0;31mnull\x1b[0m"));
     }
 
+    #[test]
+    fn colorize_xml_tags_and_attributes() {
+        let xml = "<?xml version=\"1.0\"?><root id=\"1\"><item>a &lt; b</item><empty/></root>";
+        let out = colorize_xml(xml, &Theme::default());
+        assert!(out.starts_with("\x1b[0;31m<?xml version=\"1.0\"?>\x1b[0m"));
+        assert!(out.contains(
+            "\x1b[1;34m<root\x1b[0m \x1b[0;36mid\x1b[0m=\x1b[0;32m\"1\"\x1b[0m\x1b[1;34m>\x1b[0m"
+        ));
+        assert!(out.contains("\x1b[1;34m<item\x1b[0m\x1b[1;34m>\x1b[0ma &lt; b"));
+        assert!(out.contains("\x1b[1;34m</item\x1b[0m\x1b[1;34m>\x1b[0m"));
+        assert!(out.contains("\x1b[1;34m<empty\x1b[0m\x1b[1;34m/>\x1b[0m"));
+    }
+
+    #[test]
+    fn colorize_toml_basic() {
+        let toml = "title = \"x = y\"\nports = [\n    80,\n]\n\n[server]\nenabled = true\n";
+        let out = colorize_toml(toml, &Theme::default());
+        assert!(out.contains("\x1b[1;34mtitle\x1b[0m = \x1b[0;32m\"x = y\"\x1b[0m\n"));
+        assert!(out.contains("    \x1b[0;36m80\x1b[0m,\n"));
+        assert!(out.contains("\x1b[1;37m[server]\x1b[0m\n"));
+        assert!(out.contains("\x1b[1;34menabled\x1b[0m = \x1b[0;33mtrue\x1b[0m"));
+    }
+
+    #[test]
+    fn colorize_toml_multi_line_strings() {
+        let toml = "a = \"\"\"\nk=v\n[t]\nsay \\\"\"\" = 1\nend\"\"\"\"\nb = [\n    '''\n\
+                    x = 'y'\n''',\n]\nc=2\n";
+        let out = colorize_toml(toml, &Theme::default());
+        let ansi = regex::Regex::new("\x1b\\[[0-9;]*m").unwrap();
+        assert_eq!(ansi.replace_all(&out, ""), toml);
+        // The strings' lines are colored as strings, not as keys or headers
+        assert!(out.contains("\x1b[0;32mk=v\x1b[0m\n\x1b[0;32m[t]\x1b[0m\n"));
+        assert!(out.contains("\x1b[0;32mend\"\"\"\"\x1b[0m\n"));
+        assert!(out.contains("\x1b[0;32mx = 'y'\x1b[0m\n\x1b[0;32m'''\x1b[0m,\n"));
+        assert!(out.contains("\x1b[1;34mc\x1b[0m=\x1b[0;36m2\x1b[0m\n"));
+    }
+
+    #[test]
+    fn theme_from_jq_colors_spec() {
+        let mut theme = Theme::default();
+        theme.apply_spec("1;30:0;39:0;39:38;5;214:38;2;0;255;0").unwrap();
+        assert_eq!(theme.null, "\x1b[1;30m");
+        assert_eq!(theme.number, "\x1b[38;5;214m");
+        assert_eq!(theme.string, "\x1b[38;2;0;255;0m");
+        // Fields past the end of the spec keep their colors
+        assert_eq!(theme.object_key, Theme::default().object_key);
+
+        let out = colorize_json(&json!({"n": 1, "s": "x", "z": null}), &theme);
+        assert!(out.contains("\x1b[38;5;214m1\x1b[0m"));
+        assert!(out.contains("\x1b[38;2;0;255;0m\"x\"\x1b[0m"));
+        assert!(out.contains("\x1b[1;30mnull\x1b[0m"));
+
+        for bad in ["0;31:red", "0;31::0;32", "1:1:1:1:1:1:1:1:1"] {
+            assert!(Theme::default().apply_spec(bad).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn named_themes() {
+        assert_eq!(Theme::named("default"), Some(Theme::default()));
+        let solarized = Theme::named("solarized").unwrap();
+        let out = colorize_yaml("a: 1\n", &solarized);
+        assert_eq!(out, "\x1b[1;38;5;33ma\x1b[0m: \x1b[38;5;61m1\x1b[0m\n");
+        assert!(Theme::named("nope").is_none());
+    }
+
     #[test]
     fn escape_special_chars() {
         let s = "hello \"world\"\nnewline";
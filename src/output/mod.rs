@@ -1,4 +1,7 @@
 pub mod color;
 pub mod pretty;
+pub mod xml;
+pub mod yaml;
+pub mod yaml_edit;
 
 pub use pretty::format_value;
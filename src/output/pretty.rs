@@ -3,6 +3,10 @@ use serde_json::Value;
 use crate::error::QfError;
 use crate::format::Format;
 
+use super::color::Theme;
+use super::xml::XmlStyle;
+use super::yaml::YamlStyle;
+
 /// Format a Value as a string in the given format.
 pub fn format_value(
     value: &Value,
@@ -21,6 +25,65 @@ pub fn format_value_colored(
     raw: bool,
     colorize: bool,
 ) -> Result<String, QfError> {
+    let opts = OutputOptions {
+        compact,
+        raw,
+        colorize,
+        ..OutputOptions::default()
+    };
+    format_value_with(value, format, &opts)
+}
+
+/// Settings that shape how a value is rendered, beyond the format itself.
+#[derive(Debug, Clone)]
+pub struct OutputOptions<'a> {
+    /// Single-line JSON instead of pretty-printed.
+    pub compact: bool,
+    /// Pretty-print JSON arrays, but keep objects of scalars on one line.
+    pub compact_objects: bool,
+    /// Print strings without quotes. Only applies to a string value itself:
+    /// an array of strings is still rendered in the output format.
+    pub raw: bool,
+    /// Emit ANSI colors (JSON, YAML, XML and TOML only).
+    pub colorize: bool,
+    /// Text written for null CSV/TSV cells; empty by default.
+    pub csv_null: String,
+    /// Indentation and flow style for YAML.
+    pub yaml: YamlStyle,
+    /// Root element name for XML.
+    pub xml: XmlStyle,
+    /// Colors used when `colorize` is set.
+    pub theme: &'a Theme,
+}
+
+impl Default for OutputOptions<'_> {
+    fn default() -> Self {
+        OutputOptions {
+            compact: false,
+            compact_objects: false,
+            raw: false,
+            colorize: false,
+            csv_null: String::new(),
+            yaml: YamlStyle::default(),
+            xml: XmlStyle::default(),
+            theme: Theme::shared_default(),
+        }
+    }
+}
+
+/// Format a Value as a string in the given format using `opts`.
+pub fn format_value_with(
+    value: &Value,
+    format: Format,
+    opts: &OutputOptions,
+) -> Result<String, QfError> {
+    let OutputOptions {
+        compact,
+        raw,
+        colorize,
+        ..
+    } = *opts;
+
     // Raw mode: if the value is a string, output it without quotes
     if raw {
         if let Value::String(s) = value {
@@ -29,23 +92,63 @@ pub fn format_value_colored(
     }
 
     if colorize && !compact {
+        let theme = opts.theme;
         match format {
-            Format::Json => return Ok(super::color::colorize_json(value)),
+            Format::Json | Format::Json5 => {
+                return Ok(super::color::colorize_json_with(value, theme, opts.compact_objects));
+            }
             Format::Yaml => {
-                let yaml = format_yaml(value)?;
-                return Ok(super::color::colorize_yaml(&yaml));
+                let yaml = format_yaml(value, opts.yaml)?;
+                return Ok(super::color::colorize_yaml(&yaml, theme));
             }
+            Format::Xml => {
+                let xml = super::xml::to_string(value, &opts.xml, compact)?;
+                return Ok(super::color::colorize_xml(&xml, theme));
+            }
+            Format::Toml => return Ok(super::color::colorize_toml(&format_toml(value)?, theme)),
             _ => {} // fall through to non-colorized for other formats
         }
     }
 
     match format {
-        Format::Json => format_json(value, compact),
-        Format::Yaml => format_yaml(value),
-        Format::Xml => format_xml(value),
+        // JSON is valid JSON5, so there is no separate writer
+        Format::Json | Format::Json5 if opts.compact_objects && !compact => {
+            let mut out = String::new();
+            write_compact_objects(value, 0, &mut out)?;
+            Ok(out)
+        }
+        Format::Json | Format::Json5 => format_json(value, compact),
+        Format::Yaml => format_yaml(value, opts.yaml),
+        Format::Xml => super::xml::to_string(value, &opts.xml, compact),
         Format::Toml => format_toml(value),
-        Format::Csv => format_delimited(value, b','),
-        Format::Tsv => format_delimited(value, b'\t'),
+        Format::Csv => format_delimited(value, b',', opts),
+        Format::Tsv => format_delimited(value, b'\t', opts),
+        Format::Properties => Err(QfError::UnsupportedFormat(format!(
+            "{format} is an input-only format"
+        ))),
+        Format::Table => Ok(format_table(value)),
+        Format::Markdown => Ok(format_markdown(value)),
+        Format::Cbor => Err(QfError::UnsupportedFormat(format!(
+            "{format} is a binary format; use format_value_bytes"
+        ))),
+    }
+}
+
+/// Format a Value as raw bytes, serializing binary formats directly and
+/// encoding text formats as UTF-8.
+pub fn format_value_bytes(value: &Value, format: Format) -> Result<Vec<u8>, QfError> {
+    match format {
+        #[cfg(feature = "cbor")]
+        Format::Cbor => {
+            let mut buf = Vec::new();
+            ciborium::into_writer(value, &mut buf).map_err(|e| QfError::Parse(e.to_string()))?;
+            Ok(buf)
+        }
+        #[cfg(not(feature = "cbor"))]
+        Format::Cbor => Err(QfError::UnsupportedFormat(
+            "cbor (qf was built without the `cbor` feature)".to_string(),
+        )),
+        _ => format_value(value, format, false, false).map(String::into_bytes),
     }
 }
 
@@ -58,12 +161,45 @@ fn format_json(value: &Value, compact: bool) -> Result<String, QfError> {
     result.map_err(|e| QfError::Parse(e.to_string()))
 }
 
-fn format_yaml(value: &Value) -> Result<String, QfError> {
-    serde_yaml::to_string(value).map_err(|e| QfError::Parse(e.to_string()))
+/// Write `value` as pretty JSON in which objects holding only scalars stay
+/// on a single line, so an array of records prints one record per line.
+fn write_compact_objects(value: &Value, level: usize, out: &mut String) -> Result<(), QfError> {
+    let scalar = |v: &Value| !v.is_array() && !v.is_object();
+    match value {
+        Value::Array(arr) if !arr.is_empty() => {
+            out.push('[');
+            for (i, item) in arr.iter().enumerate() {
+                out.push_str(if i == 0 { "\n" } else { ",\n" });
+                out.push_str(&"  ".repeat(level + 1));
+                write_compact_objects(item, level + 1, out)?;
+            }
+            out.push('\n');
+            out.push_str(&"  ".repeat(level));
+            out.push(']');
+        }
+        Value::Object(map) if !map.values().all(scalar) => {
+            out.push('{');
+            for (i, (key, val)) in map.iter().enumerate() {
+                out.push_str(if i == 0 { "\n" } else { ",\n" });
+                out.push_str(&"  ".repeat(level + 1));
+                out.push_str(&format_json(&Value::String(key.clone()), true)?);
+                out.push_str(": ");
+                write_compact_objects(val, level + 1, out)?;
+            }
+            out.push('\n');
+            out.push_str(&"  ".repeat(level));
+            out.push('}');
+        }
+        _ => out.push_str(&format_json(value, true)?),
+    }
+    Ok(())
 }
 
-fn format_xml(value: &Value) -> Result<String, QfError> {
-    quick_xml::se::to_string(value).map_err(|e| QfError::Parse(e.to_string()))
+fn format_yaml(value: &Value, style: YamlStyle) -> Result<String, QfError> {
+    if style != YamlStyle::default() {
+        return super::yaml::to_string(value, style);
+    }
+    serde_yaml::to_string(value).map_err(|e| QfError::Parse(e.to_string()))
 }
 
 fn format_toml(value: &Value) -> Result<String, QfError> {
@@ -99,7 +235,7 @@ fn json_to_toml(value: &Value) -> Result<toml::Value, QfError> {
     }
 }
 
-fn format_delimited(value: &Value, delimiter: u8) -> Result<String, QfError> {
+fn format_delimited(value: &Value, delimiter: u8, opts: &OutputOptions) -> Result<String, QfError> {
     let rows = match value {
         Value::Array(arr) => arr,
         _ => return Err(QfError::Parse("CSV/TSV output requires an array of objects".to_string())),
@@ -109,10 +245,11 @@ fn format_delimited(value: &Value, delimiter: u8) -> Result<String, QfError> {
         return Ok(String::new());
     }
 
-    let headers: Vec<String> = match &rows[0] {
-        Value::Object(map) => map.keys().cloned().collect(),
-        _ => return Err(QfError::Parse("CSV/TSV output requires an array of objects".to_string())),
-    };
+    if !rows.iter().all(Value::is_object) {
+        return Err(QfError::Parse("CSV/TSV output requires an array of objects".to_string()));
+    }
+    // Every key seen in any row, so heterogeneous rows don't drop columns
+    let headers = union_keys(rows);
 
     let mut wtr = csv::WriterBuilder::new()
         .delimiter(delimiter)
@@ -122,15 +259,15 @@ fn format_delimited(value: &Value, delimiter: u8) -> Result<String, QfError> {
         .map_err(|e| QfError::Parse(e.to_string()))?;
 
     for row in rows {
-        let obj = row.as_object().ok_or_else(|| {
-            QfError::Parse("CSV/TSV output requires an array of objects".to_string())
-        })?;
         let fields: Vec<String> = headers
             .iter()
-            .map(|h| match obj.get(h) {
+            .map(|h| match row.get(h) {
                 Some(Value::String(s)) => s.clone(),
-                Some(Value::Null) | None => String::new(),
-                Some(v) => v.to_string(),
+                Some(Value::Null) => opts.csv_null.clone(),
+                // Missing keys are blank regardless of the null rendering
+                None => String::new(),
+                // Nested arrays/objects are JSON-encoded; the writer quotes them
+                Some(v) => serde_json::to_string(v).unwrap_or_default(),
             })
             .collect();
         wtr.write_record(&fields)
@@ -143,6 +280,132 @@ fn format_delimited(value: &Value, delimiter: u8) -> Result<String, QfError> {
     String::from_utf8(bytes).map_err(|e| QfError::Parse(e.to_string()))
 }
 
+/// Render an array of objects as an aligned text table, one column per key.
+///
+/// Columns are the first row's keys, then any keys later rows add. Objects
+/// keep their keys sorted, so each row contributes its new keys in sorted
+/// order. Anything that is not an array of objects is listed one value per
+/// line instead.
+pub fn format_table(value: &Value) -> String {
+    let rows = match value {
+        Value::Array(arr) if !arr.is_empty() && arr.iter().all(Value::is_object) => arr,
+        Value::Array(arr) => return arr.iter().map(|v| table_cell(v) + "\n").collect(),
+        other => return table_cell(other) + "\n",
+    };
+
+    let headers = union_keys(rows);
+    let cells: Vec<Vec<String>> = rows
+        .iter()
+        .map(|row| {
+            headers
+                .iter()
+                .map(|h| row.get(h).map(table_cell).unwrap_or_default())
+                .collect()
+        })
+        .collect();
+
+    let widths: Vec<usize> = headers
+        .iter()
+        .enumerate()
+        .map(|(i, h)| {
+            cells
+                .iter()
+                .map(|r| r[i].chars().count())
+                .chain(std::iter::once(h.chars().count()))
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+
+    let render = |fields: &[String]| {
+        let line: Vec<String> = fields
+            .iter()
+            .zip(&widths)
+            .map(|(f, w)| format!("{f:<w$}"))
+            .collect();
+        line.join("  ").trim_end().to_string() + "\n"
+    };
+
+    let mut out = render(&headers);
+    let separator: Vec<String> = widths.iter().map(|w| "-".repeat(*w)).collect();
+    out.push_str(&render(&separator));
+    for row in &cells {
+        out.push_str(&render(row));
+    }
+    out
+}
+
+/// Render an array of objects as a GitHub-flavored Markdown table.
+///
+/// Columns are ordered as in [`format_table`]: the first row's keys, then
+/// keys later rows add. Other values become a single `value` column with
+/// one row per element.
+pub fn format_markdown(value: &Value) -> String {
+    let (headers, rows): (Vec<String>, Vec<Vec<String>>) = match value {
+        Value::Array(arr) if !arr.is_empty() && arr.iter().all(Value::is_object) => {
+            let headers = union_keys(arr);
+            let rows = arr
+                .iter()
+                .map(|row| {
+                    headers
+                        .iter()
+                        .map(|h| row.get(h).map(table_cell).unwrap_or_default())
+                        .collect()
+                })
+                .collect();
+            (headers, rows)
+        }
+        Value::Array(arr) => (
+            vec!["value".to_string()],
+            arr.iter().map(|v| vec![table_cell(v)]).collect(),
+        ),
+        other => (vec!["value".to_string()], vec![vec![table_cell(other)]]),
+    };
+
+    let render = |fields: &[String]| {
+        let cells: Vec<String> = fields.iter().map(|f| escape_markdown_cell(f)).collect();
+        format!("| {} |\n", cells.join(" | "))
+    };
+
+    let mut out = render(&headers);
+    out.push_str(&format!("|{}\n", " --- |".repeat(headers.len())));
+    for row in &rows {
+        out.push_str(&render(row));
+    }
+    out
+}
+
+fn escape_markdown_cell(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('|', "\\|")
+        .replace("\r\n", "<br>")
+        .replace('\n', "<br>")
+}
+
+/// Collect object keys across rows: each row adds the keys no earlier row
+/// had, in the (sorted) order its map holds them.
+pub(crate) fn union_keys(rows: &[Value]) -> Vec<String> {
+    let mut keys: Vec<String> = Vec::new();
+    for row in rows {
+        if let Value::Object(map) = row {
+            for k in map.keys() {
+                if !keys.contains(k) {
+                    keys.push(k.clone());
+                }
+            }
+        }
+    }
+    keys
+}
+
+fn table_cell(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -163,6 +426,44 @@ mod tests {
         assert!(!out.contains('\n'));
     }
 
+    #[test]
+    fn json_compact_objects() {
+        let val = json!({"rows": [{"a": 1, "b": "x"}, {"a": 2, "b": null}], "empty": [], "n": 1});
+        let opts = OutputOptions {
+            compact_objects: true,
+            ..OutputOptions::default()
+        };
+        let out = format_value_with(&val, Format::Json, &opts).unwrap();
+        assert_eq!(
+            out,
+            "{\n  \"empty\": [],\n  \"n\": 1,\n  \"rows\": [\n    {\"a\":1,\"b\":\"x\"},\n    \
+             {\"a\":2,\"b\":null}\n  ]\n}"
+        );
+        let reparsed: Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(reparsed, val);
+        // Without nested arrays or objects the output matches serde_json
+        let flat = json!([1, "a", [], {}]);
+        let out = format_value_with(&flat, Format::Json, &opts).unwrap();
+        assert_eq!(out, serde_json::to_string_pretty(&flat).unwrap());
+    }
+
+    #[test]
+    fn json_compact_objects_colored() {
+        let val = json!({"rows": [{"a": 1, "b": "x"}, {"c": {"d": true}}], "n": null});
+        let plain = OutputOptions {
+            compact_objects: true,
+            ..OutputOptions::default()
+        };
+        let colored = OutputOptions {
+            colorize: true,
+            ..plain.clone()
+        };
+        let out = format_value_with(&val, Format::Json, &colored).unwrap();
+        assert!(out.contains("\x1b["));
+        let stripped = regex::Regex::new("\x1b\\[[0-9;]*m").unwrap().replace_all(&out, "");
+        assert_eq!(stripped, format_value_with(&val, Format::Json, &plain).unwrap());
+    }
+
     #[test]
     fn yaml_output() {
         let val = json!({"name": "test", "count": 3});
@@ -184,4 +485,75 @@ mod tests {
         let out = format_value(&val, Format::Json, false, true).unwrap();
         assert_eq!(out, "42");
     }
+
+    #[test]
+    fn table_aligns_columns() {
+        let val = json!([
+            {"name": "alice", "age": 30},
+            {"name": "bob", "age": 4, "admin": true}
+        ]);
+        let out = format_table(&val);
+        assert_eq!(
+            out,
+            "age  name   admin\n\
+             ---  -----  -----\n\
+             30   alice\n\
+             4    bob    true\n"
+        );
+    }
+
+    #[test]
+    fn table_falls_back_to_single_column() {
+        assert_eq!(format_table(&json!([1, "two", null])), "1\ntwo\n\n");
+        assert_eq!(format_table(&json!("solo")), "solo\n");
+    }
+
+    #[test]
+    fn markdown_table() {
+        let val = json!([{"name": "a", "age": 1}]);
+        assert_eq!(
+            format_value(&val, Format::Markdown, false, false).unwrap(),
+            "| age | name |\n| --- | --- |\n| 1 | a |\n"
+        );
+        // Keys a later row adds come after the first row's
+        let val = json!([{"b": 1}, {"a": 2, "b": 3}]);
+        assert_eq!(format_markdown(&val), "| b | a |\n| --- | --- |\n| 1 |  |\n| 3 | 2 |\n");
+    }
+
+    #[test]
+    fn markdown_escapes_pipes() {
+        let val = json!([{"cmd": "a | b", "note": "line1\nline2"}]);
+        assert_eq!(
+            format_markdown(&val),
+            "| cmd | note |\n| --- | --- |\n| a \\| b | line1<br>line2 |\n"
+        );
+    }
+
+    #[test]
+    fn csv_nested_cells_are_json_encoded() {
+        let val = json!([{"id": 1, "tags": ["a", "b"], "meta": {"k": "v"}}]);
+        let out = format_value(&val, Format::Csv, false, false).unwrap();
+        assert_eq!(
+            out,
+            "id,meta,tags\n1,\"{\"\"k\"\":\"\"v\"\"}\",\"[\"\"a\"\",\"\"b\"\"]\"\n"
+        );
+    }
+
+    #[test]
+    fn csv_header_is_union_of_keys() {
+        let val = json!([{"a": 1}, {"a": 2, "b": "x"}, {"c": true}]);
+        let out = format_value(&val, Format::Csv, false, false).unwrap();
+        assert_eq!(out, "a,b,c\n1,,\n2,x,\n,,true\n");
+    }
+
+    #[test]
+    fn csv_null_rendering() {
+        let val = json!([{"a": null, "b": 1}, {"b": 2}]);
+        let opts = OutputOptions {
+            csv_null: "NULL".into(),
+            ..OutputOptions::default()
+        };
+        let out = format_value_with(&val, Format::Csv, &opts).unwrap();
+        assert_eq!(out, "a,b\nNULL,1\n,2\n");
+    }
 }
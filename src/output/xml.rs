@@ -0,0 +1,224 @@
+use quick_xml::escape::escape;
+use serde_json::Value;
+
+use crate::error::QfError;
+
+/// Settings for XML output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XmlStyle {
+    /// Name of the element wrapping the whole document.
+    pub root: String,
+}
+
+impl Default for XmlStyle {
+    fn default() -> Self {
+        XmlStyle {
+            root: "root".into(),
+        }
+    }
+}
+
+/// Serialize `value` as an XML document, the reverse of how XML is read:
+/// `@name` keys become attributes, `$text` the element's text and other
+/// keys child elements, with arrays written as repeated elements. A
+/// top-level array becomes `<item>` children of the root.
+///
+/// Nested elements are indented one per line, or with `compact` the whole
+/// document is written on one line.
+pub fn to_string(value: &Value, style: &XmlStyle, compact: bool) -> Result<String, QfError> {
+    let mut out = String::from("<?xml version=\"1.0\"?>");
+    let writer = Writer { pretty: !compact };
+    writer.newline(0, &mut out);
+    writer.item(&style.root, value, 0, &mut out)?;
+    Ok(out)
+}
+
+struct Writer {
+    pretty: bool,
+}
+
+impl Writer {
+    /// Write `<name>` for `value`, which must not be an array.
+    fn element(
+        &self,
+        name: &str,
+        value: &Value,
+        level: usize,
+        out: &mut String,
+    ) -> Result<(), QfError> {
+        check_name(name)?;
+        out.push('<');
+        out.push_str(name);
+        let Value::Object(map) = value else {
+            return self.close_text(name, value, out);
+        };
+        let mut text = None;
+        let mut children = Vec::new();
+        for (key, val) in map {
+            if let Some(attr) = key.strip_prefix('@') {
+                check_name(attr)?;
+                let value = scalar_text(val, key)?;
+                out.push_str(&format!(" {attr}=\"{}\"", escape(value.as_str())));
+            } else if key == "$text" {
+                text = Some(val);
+            } else {
+                children.push((key, val));
+            }
+        }
+        if children.is_empty() {
+            return match text {
+                Some(text) => self.close_text(name, text, out),
+                None => {
+                    out.push_str("/>");
+                    Ok(())
+                }
+            };
+        }
+        out.push('>');
+        if let Some(text) = text {
+            self.newline(level + 1, out);
+            out.push_str(&escape(scalar_text(text, "$text")?.as_str()));
+        }
+        for (key, val) in children {
+            match val {
+                Value::Array(items) => {
+                    for item in items {
+                        self.newline(level + 1, out);
+                        self.item(key, item, level + 1, out)?;
+                    }
+                }
+                _ => {
+                    self.newline(level + 1, out);
+                    self.element(key, val, level + 1, out)?;
+                }
+            }
+        }
+        self.newline(level, out);
+        out.push_str(&format!("</{name}>"));
+        Ok(())
+    }
+
+    /// Write `<name>` for any value: arrays get `<item>` children.
+    fn item(
+        &self,
+        name: &str,
+        value: &Value,
+        level: usize,
+        out: &mut String,
+    ) -> Result<(), QfError> {
+        let Value::Array(items) = value else {
+            return self.element(name, value, level, out);
+        };
+        check_name(name)?;
+        if items.is_empty() {
+            out.push_str(&format!("<{name}/>"));
+            return Ok(());
+        }
+        out.push_str(&format!("<{name}>"));
+        for item in items {
+            self.newline(level + 1, out);
+            self.item("item", item, level + 1, out)?;
+        }
+        self.newline(level, out);
+        out.push_str(&format!("</{name}>"));
+        Ok(())
+    }
+
+    /// Finish an open tag whose content is the scalar `value`.
+    fn close_text(&self, name: &str, value: &Value, out: &mut String) -> Result<(), QfError> {
+        if value.is_null() {
+            out.push_str("/>");
+            return Ok(());
+        }
+        out.push('>');
+        out.push_str(&escape(scalar_text(value, name)?.as_str()));
+        out.push_str(&format!("</{name}>"));
+        Ok(())
+    }
+
+    fn newline(&self, level: usize, out: &mut String) {
+        if self.pretty {
+            out.push('\n');
+            out.push_str(&"  ".repeat(level));
+        }
+    }
+}
+
+/// The text of a scalar attribute or element value.
+fn scalar_text(value: &Value, name: &str) -> Result<String, QfError> {
+    match value {
+        Value::String(s) => Ok(s.clone()),
+        Value::Null => Ok(String::new()),
+        Value::Bool(_) | Value::Number(_) => Ok(value.to_string()),
+        _ => Err(QfError::Encode(format!("xml: {name} must be a scalar to be written as text"))),
+    }
+}
+
+fn check_name(name: &str) -> Result<(), QfError> {
+    let valid = name
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_alphabetic() || c == '_' || c == ':')
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '_' | ':' | '-' | '.'));
+    if valid {
+        Ok(())
+    } else {
+        Err(QfError::Encode(format!("xml: {name:?} is not a valid element name")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn pretty_indents_nested_elements() {
+        let val =
+            json!({"name": "api", "port": 80, "tags": ["a", "b"], "meta": {"@id": 7, "x": null}});
+        assert_eq!(
+            to_string(&val, &XmlStyle::default(), false).unwrap(),
+            "<?xml version=\"1.0\"?>\n<root>\n  <meta id=\"7\">\n    <x/>\n  </meta>\n  \
+             <name>api</name>\n  <port>80</port>\n  <tags>a</tags>\n  <tags>b</tags>\n</root>"
+        );
+    }
+
+    #[test]
+    fn custom_root_and_compact() {
+        let style = XmlStyle {
+            root: "config".into(),
+        };
+        let val = json!({"a": {"$text": "x < y", "@k": "\"q\""}});
+        assert_eq!(
+            to_string(&val, &style, true).unwrap(),
+            "<?xml version=\"1.0\"?><config><a k=\"&quot;q&quot;\">x &lt; y</a></config>"
+        );
+        assert_eq!(
+            to_string(&json!([1, [2]]), &style, true).unwrap(),
+            "<?xml version=\"1.0\"?><config><item>1</item><item><item>2</item></item></config>"
+        );
+        let out = to_string(&json!("hi"), &style, true).unwrap();
+        assert_eq!(out, "<?xml version=\"1.0\"?><config>hi</config>");
+    }
+
+    #[test]
+    fn round_trips_through_the_reader() {
+        let input = "<root><a id=\"1\">x</a><a>y</a><b><c>z</c></b><e/></root>";
+        let val = crate::parser::xml::parse(input).unwrap();
+        let out = to_string(&val, &XmlStyle::default(), false).unwrap();
+        assert_eq!(crate::parser::xml::parse(&out).unwrap(), val);
+    }
+
+    #[test]
+    fn rejects_invalid_names() {
+        let style = XmlStyle::default();
+        let err = to_string(&json!({"has space": 1}), &style, false).unwrap_err();
+        assert!(matches!(err, QfError::Encode(_)), "{err:?}");
+        assert!(to_string(&json!({"1a": 1}), &style, false).is_err());
+        // Attributes and text must be scalars
+        assert!(to_string(&json!({"a": {"@x": [1]}}), &style, false).is_err());
+        assert!(to_string(&json!({"a": {"$text": {}}}), &style, false).is_err());
+    }
+}
@@ -0,0 +1,253 @@
+use serde_json::Value;
+
+use crate::error::QfError;
+
+/// Layout settings for YAML output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct YamlStyle {
+    /// Spaces per nesting level.
+    pub indent: usize,
+    /// Write collections that hold only scalars in flow style (`[1, 2]`).
+    pub flow_leaves: bool,
+}
+
+impl Default for YamlStyle {
+    fn default() -> Self {
+        YamlStyle {
+            indent: 2,
+            flow_leaves: false,
+        }
+    }
+}
+
+/// Serialize `value` as a YAML document laid out according to `style`.
+///
+/// The block layout matches `serde_yaml` (sequences under a key are not
+/// indented, mapping entries in a sequence item line up after `- `), so the
+/// default style produces the same text; scalars are always rendered by
+/// `serde_yaml` itself.
+pub fn to_string(value: &Value, style: YamlStyle) -> Result<String, QfError> {
+    let mut out = String::new();
+    let writer = Writer { style };
+    match value {
+        Value::Object(map) if !map.is_empty() => writer.mapping(value, 0, false, &mut out)?,
+        Value::Array(arr) if !arr.is_empty() => writer.sequence(value, 0, false, &mut out)?,
+        _ => writer.inline_value(value, 0, &mut out)?,
+    }
+    Ok(out)
+}
+
+struct Writer {
+    style: YamlStyle,
+}
+
+impl Writer {
+    /// Write the entries of a non-empty mapping at column `col`. With
+    /// `inline_first`, the first entry continues the current line.
+    fn mapping(
+        &self,
+        value: &Value,
+        col: usize,
+        inline_first: bool,
+        out: &mut String,
+    ) -> Result<(), QfError> {
+        let Value::Object(map) = value else { unreachable!() };
+        for (i, (key, val)) in map.iter().enumerate() {
+            if i > 0 || !inline_first {
+                pad(out, col);
+            }
+            out.push_str(&key_text(key)?);
+            out.push(':');
+            match val {
+                Value::Object(m) if !m.is_empty() && !self.as_flow(val) => {
+                    out.push('\n');
+                    self.mapping(val, col + self.style.indent, false, out)?;
+                }
+                Value::Array(a) if !a.is_empty() && !self.as_flow(val) => {
+                    out.push('\n');
+                    self.sequence(val, col, false, out)?;
+                }
+                _ => {
+                    out.push(' ');
+                    self.inline_value(val, col, out)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the items of a non-empty sequence with their dashes at `col`.
+    fn sequence(
+        &self,
+        value: &Value,
+        col: usize,
+        inline_first: bool,
+        out: &mut String,
+    ) -> Result<(), QfError> {
+        let Value::Array(arr) = value else { unreachable!() };
+        for (i, item) in arr.iter().enumerate() {
+            if i > 0 || !inline_first {
+                pad(out, col);
+            }
+            out.push_str("- ");
+            match item {
+                Value::Object(m) if !m.is_empty() && !self.as_flow(item) => {
+                    self.mapping(item, col + 2, true, out)?;
+                }
+                Value::Array(a) if !a.is_empty() && !self.as_flow(item) => {
+                    self.sequence(item, col + 2, true, out)?;
+                }
+                _ => self.inline_value(item, col, out)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Write a scalar, empty or flow collection after a `key: ` or `- `
+    /// owned by column `col`, ending the line.
+    fn inline_value(&self, value: &Value, col: usize, out: &mut String) -> Result<(), QfError> {
+        if value.is_array() || value.is_object() {
+            out.push_str(&flow(value)?);
+            out.push('\n');
+            return Ok(());
+        }
+        let text = scalar(value)?;
+        let mut lines = text.lines();
+        out.push_str(lines.next().unwrap_or_default());
+        out.push('\n');
+        // Block scalar (`|`) content: serde_yaml indents it two spaces
+        for line in lines {
+            if !line.is_empty() {
+                pad(out, col + self.style.indent);
+                out.push_str(line.strip_prefix("  ").unwrap_or(line));
+            }
+            out.push('\n');
+        }
+        Ok(())
+    }
+
+    fn as_flow(&self, value: &Value) -> bool {
+        self.style.flow_leaves && is_leaf_collection(value)
+    }
+}
+
+/// A collection whose members are all scalars.
+fn is_leaf_collection(value: &Value) -> bool {
+    let scalar = |v: &Value| !v.is_array() && !v.is_object();
+    match value {
+        Value::Array(arr) => arr.iter().all(scalar),
+        Value::Object(map) => map.values().all(scalar),
+        _ => false,
+    }
+}
+
+fn pad(out: &mut String, col: usize) {
+    out.extend(std::iter::repeat_n(' ', col));
+}
+
+/// A scalar as serde_yaml writes it, without the trailing newline.
+fn scalar(value: &Value) -> Result<String, QfError> {
+    let text = serde_yaml::to_string(value).map_err(|e| QfError::Parse(e.to_string()))?;
+    Ok(text.trim_end_matches('\n').to_string())
+}
+
+/// A mapping key; keys that serde_yaml would write as block scalars are
+/// double-quoted instead.
+fn key_text(key: &str) -> Result<String, QfError> {
+    let text = scalar(&Value::String(key.to_string()))?;
+    if text.contains('\n') {
+        return serde_json::to_string(key).map_err(|e| QfError::Parse(e.to_string()));
+    }
+    Ok(text)
+}
+
+/// A collection in flow style: `[a, b]` / `{k: v}`.
+fn flow(value: &Value) -> Result<String, QfError> {
+    match value {
+        Value::Array(arr) => {
+            let items = arr.iter().map(flow).collect::<Result<Vec<_>, _>>()?;
+            Ok(format!("[{}]", items.join(", ")))
+        }
+        Value::Object(map) => {
+            let entries = map
+                .iter()
+                .map(|(k, v)| Ok(format!("{}: {}", flow_string(k)?, flow(v)?)))
+                .collect::<Result<Vec<_>, QfError>>()?;
+            Ok(format!("{{{}}}", entries.join(", ")))
+        }
+        Value::String(s) => flow_string(s),
+        _ => scalar(value),
+    }
+}
+
+/// A string inside a flow collection, where `,[]{}` also need quoting.
+fn flow_string(s: &str) -> Result<String, QfError> {
+    let text = scalar(&Value::String(s.to_string()))?;
+    if text.contains('\n') || s.contains([',', '[', ']', '{', '}']) {
+        return serde_json::to_string(s).map_err(|e| QfError::Parse(e.to_string()));
+    }
+    Ok(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn sample() -> Value {
+        json!({
+            "a": {"b": [1, {"c": "x\ny", "d": [[1, 2], []]}], "e": {}},
+            "f": "multi\nline\n",
+            "g": "true",
+            "k y": 1,
+            "l": [{"m": [1]}]
+        })
+    }
+
+    #[test]
+    fn default_style_matches_serde_yaml() {
+        for value in [sample(), json!([[1, 2], [3]]), json!("a\nb"), json!({}), json!(1)] {
+            assert_eq!(
+                to_string(&value, YamlStyle::default()).unwrap(),
+                serde_yaml::to_string(&value).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn four_space_indent() {
+        let value = json!({"a": {"b": [1, {"c": "x\ny"}]}, "s": [[1, 2]]});
+        let two = to_string(&value, YamlStyle::default()).unwrap();
+        let style = YamlStyle {
+            indent: 4,
+            ..YamlStyle::default()
+        };
+        let four = to_string(&value, style).unwrap();
+        assert_eq!(
+            two,
+            "a:\n  b:\n  - 1\n  - c: |-\n      x\n      y\ns:\n- - 1\n  - 2\n"
+        );
+        assert_eq!(
+            four,
+            "a:\n    b:\n    - 1\n    - c: |-\n          x\n          y\ns:\n- - 1\n  - 2\n"
+        );
+        let reparsed: Value = serde_yaml::from_str(&four).unwrap();
+        assert_eq!(reparsed, value);
+    }
+
+    #[test]
+    fn flow_leaves() {
+        let value = json!({"tags": ["a", "b,c"], "meta": {"x": 1}, "rows": [[1, 2], {"k": [3]}]});
+        let style = YamlStyle {
+            flow_leaves: true,
+            ..YamlStyle::default()
+        };
+        let out = to_string(&value, style).unwrap();
+        assert_eq!(
+            out,
+            "meta: {x: 1}\nrows:\n- [1, 2]\n- k: [3]\ntags: [a, \"b,c\"]\n"
+        );
+        let reparsed: Value = serde_yaml::from_str(&out).unwrap();
+        assert_eq!(reparsed, value);
+    }
+}
@@ -0,0 +1,180 @@
+use serde_json::Value;
+
+use crate::error::QfError;
+
+/// Rewrite `text`, whose parsed form is `before`, so that it parses as
+/// `after`, keeping comments and layout.
+///
+/// Only changes to the values of existing top-level keys are supported,
+/// where the old and new values are both scalars that fit on one line;
+/// anything else is an error rather than a full re-serialization.
+pub fn update(text: &str, before: &Value, after: &Value) -> Result<String, QfError> {
+    let (Value::Object(old), Value::Object(new)) = (before, after) else {
+        return Err(unsupported("the document must stay a mapping"));
+    };
+    if old.len() != new.len() || old.keys().any(|k| !new.contains_key(k)) {
+        return Err(unsupported("keys cannot be added or removed"));
+    }
+    let mut out = text.to_string();
+    for (key, value) in new {
+        let old_value = &old[key];
+        if old_value == value {
+            continue;
+        }
+        if !is_scalar(old_value) || !is_scalar(value) {
+            return Err(unsupported(&format!("{key:?} is not a scalar")));
+        }
+        out = set_scalar(&out, key, value)?;
+    }
+    Ok(out)
+}
+
+/// Replace the value on the line of top-level `key`, keeping any comment
+/// after it.
+fn set_scalar(text: &str, key: &str, value: &Value) -> Result<String, QfError> {
+    let rendered = render_scalar(value)?;
+    let mut lines: Vec<&str> = text.split_inclusive('\n').collect();
+    let (i, value_at) = lines
+        .iter()
+        .enumerate()
+        .find_map(|(i, line)| key_line(line, key).map(|at| (i, at)))
+        .ok_or_else(|| unsupported(&format!("cannot find top-level key {key:?}")))?;
+    let line = lines[i];
+    let body = line.trim_end_matches(['\n', '\r']);
+    let ending = &line[body.len()..];
+    let rest = &body[value_at..];
+    let (old_value, comment) = split_comment(rest);
+    let old_value = old_value.trim();
+    if old_value.is_empty() || old_value.starts_with(['|', '>', '&', '*', '!']) {
+        return Err(unsupported(&format!("{key:?} does not hold a plain one-line value")));
+    }
+    if lines.get(i + 1).is_some_and(|next| is_continuation(next)) {
+        return Err(unsupported(&format!("{key:?} spans several lines")));
+    }
+    let leading = &rest[..rest.len() - rest.trim_start().len()];
+    let gap = if comment.is_empty() {
+        ""
+    } else {
+        let before_comment = rest[..rest.len() - comment.len()].trim_start();
+        &before_comment[before_comment.trim_end().len()..]
+    };
+    let edited = format!("{}{leading}{rendered}{gap}{comment}{ending}", &body[..value_at]);
+    lines[i] = &edited;
+    Ok(lines.concat())
+}
+
+/// If `line` holds top-level `key`, the byte offset just past its colon.
+fn key_line(line: &str, key: &str) -> Option<usize> {
+    if line.starts_with([' ', '\t', '#', '-', '%']) || line.starts_with("...") {
+        return None;
+    }
+    let colon = match line.chars().next()? {
+        quote @ ('"' | '\'') => {
+            let close = line[1..].find(quote)? + 1;
+            close + line[close..].find(':')?
+        }
+        _ => line.find(": ").or_else(|| {
+            let trimmed = line.trim_end();
+            trimmed.ends_with(':').then(|| trimmed.len() - 1)
+        })?,
+    };
+    let parsed: serde_yaml::Value = serde_yaml::from_str(line[..colon].trim()).ok()?;
+    let found = match parsed {
+        serde_yaml::Value::String(s) => s,
+        serde_yaml::Value::Number(n) => n.to_string(),
+        serde_yaml::Value::Bool(b) => b.to_string(),
+        serde_yaml::Value::Null => "null".to_string(),
+        _ => return None,
+    };
+    (found == key).then_some(colon + 1)
+}
+
+/// Split a value from a trailing `# comment`, which must follow whitespace
+/// and lie outside quotes.
+fn split_comment(rest: &str) -> (&str, &str) {
+    let mut quote = None;
+    let mut prev = ' ';
+    for (i, c) in rest.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') if rest[..i].trim().is_empty() => quote = Some(c),
+            (Some(q), c) if c == q && prev != '\\' => quote = None,
+            (None, '#') if prev.is_whitespace() => return (&rest[..i], &rest[i..]),
+            _ => {}
+        }
+        prev = c;
+    }
+    (rest, "")
+}
+
+/// Whether `line` continues a plain scalar from the line before it.
+fn is_continuation(line: &str) -> bool {
+    let trimmed = line.trim();
+    line.starts_with([' ', '\t']) && !trimmed.is_empty() && !trimmed.starts_with('#')
+}
+
+fn is_scalar(value: &Value) -> bool {
+    !value.is_array() && !value.is_object()
+}
+
+/// A scalar as serde_yaml writes it, which must fit on one line.
+fn render_scalar(value: &Value) -> Result<String, QfError> {
+    let text = serde_yaml::to_string(value).map_err(|e| QfError::Parse(e.to_string()))?;
+    let text = text.trim_end_matches('\n');
+    if text.contains('\n') {
+        return Err(unsupported("new values must fit on one line"));
+    }
+    Ok(text.to_string())
+}
+
+fn unsupported(reason: &str) -> QfError {
+    QfError::Runtime(format!("--yaml-preserve cannot apply this edit: {reason}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn edit(text: &str, query: &str) -> Result<String, QfError> {
+        let before = crate::parser::yaml::parse(text).unwrap();
+        let after = crate::query::query(&before, query).unwrap().remove(0);
+        let out = update(text, &before, &after)?;
+        assert_eq!(crate::parser::yaml::parse(&out).unwrap(), after);
+        Ok(out)
+    }
+
+    #[test]
+    fn keeps_comments_and_layout() {
+        let text =
+            "# service config\nname: api   # the name\nversion: \"1.0\"\n\nports:\n  - 80 # http\n";
+        assert_eq!(
+            edit(text, r#".version = "2.0""#).unwrap(),
+            "# service config\nname: api   # the name\nversion: '2.0'\n\nports:\n  - 80 # http\n"
+        );
+        assert_eq!(
+            edit(text, ".name = \"web\"").unwrap(),
+            "# service config\nname: web   # the name\nversion: \"1.0\"\n\nports:\n  - 80 # http\n"
+        );
+    }
+
+    #[test]
+    fn quoted_keys_and_values() {
+        let text = "'a b': \"x # not a comment\" # note\nc: 1\n";
+        assert_eq!(
+            edit(text, r#"(.["a b"] = 5) | .c = true"#).unwrap(),
+            "'a b': 5 # note\nc: true\n"
+        );
+    }
+
+    #[test]
+    fn unsupported_edits() {
+        let text = "a: 1\nb:\n  c: 2\nd: |\n  text\ne: plain\n  continued\n";
+        for query in [".b.c = 3", ".x = 1", "del(.a)", ".d = \"y\"", ".e = 1", ".a = [1]"] {
+            let err = edit(text, query).unwrap_err();
+            assert!(matches!(err, QfError::Runtime(_)), "{query}: {err:?}");
+            assert!(err.to_string().contains("--yaml-preserve"), "{query}: {err}");
+        }
+        assert!(edit("a: 1\n", ".a = \"x\\ny\"").is_err());
+        assert!(update("a: 1\n", &json!({"a": 1}), &json!([1])).is_err());
+    }
+}
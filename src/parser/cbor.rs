@@ -0,0 +1,94 @@
+use base64::Engine;
+use serde_json::Value;
+
+use crate::error::QfError;
+
+/// Parse a CBOR document into a serde_json::Value.
+///
+/// Byte strings become base64 strings, tags are dropped in favour of the
+/// tagged value, and non-string map keys are rendered as their JSON text.
+pub fn parse(input: &[u8]) -> Result<Value, QfError> {
+    let value: ciborium::Value =
+        ciborium::from_reader(input).map_err(|e| QfError::Parse(e.to_string()))?;
+    cbor_to_json(value)
+}
+
+fn cbor_to_json(value: ciborium::Value) -> Result<Value, QfError> {
+    use ciborium::Value as C;
+
+    match value {
+        C::Null => Ok(Value::Null),
+        C::Bool(b) => Ok(Value::Bool(b)),
+        C::Integer(i) => {
+            let n = i128::from(i);
+            if let Ok(v) = i64::try_from(n) {
+                Ok(Value::from(v))
+            } else if let Ok(v) = u64::try_from(n) {
+                Ok(Value::from(v))
+            } else {
+                Ok(serde_json::Number::from_f64(n as f64).map_or(Value::Null, Value::Number))
+            }
+        }
+        C::Float(f) => Ok(serde_json::Number::from_f64(f).map_or(Value::Null, Value::Number)),
+        C::Text(s) => Ok(Value::String(s)),
+        C::Bytes(b) => Ok(Value::String(
+            base64::engine::general_purpose::STANDARD.encode(b),
+        )),
+        C::Tag(_, inner) => cbor_to_json(*inner),
+        C::Array(items) => items
+            .into_iter()
+            .map(cbor_to_json)
+            .collect::<Result<Vec<_>, _>>()
+            .map(Value::Array),
+        C::Map(entries) => {
+            let mut map = serde_json::Map::new();
+            for (k, v) in entries {
+                let key = match cbor_to_json(k)? {
+                    Value::String(s) => s,
+                    other => other.to_string(),
+                };
+                map.insert(key, cbor_to_json(v)?);
+            }
+            Ok(Value::Object(map))
+        }
+        other => Err(QfError::Parse(format!("unsupported CBOR value: {other:?}"))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::Format;
+    use crate::output::pretty::format_value_bytes;
+    use serde_json::json;
+
+    #[test]
+    fn round_trip() {
+        let val = json!({
+            "name": "qf",
+            "tags": ["a", "b"],
+            "n": -3,
+            "ratio": 0.5,
+            "ok": true,
+            "none": null
+        });
+        let bytes = format_value_bytes(&val, Format::Cbor).unwrap();
+        assert_eq!(parse(&bytes).unwrap(), val);
+    }
+
+    #[test]
+    fn bytes_and_integer_keys() {
+        let cbor = ciborium::Value::Map(vec![(
+            ciborium::Value::Integer(1.into()),
+            ciborium::Value::Bytes(b"hi".to_vec()),
+        )]);
+        let mut buf = Vec::new();
+        ciborium::into_writer(&cbor, &mut buf).unwrap();
+        assert_eq!(parse(&buf).unwrap(), json!({"1": "aGk="}));
+    }
+
+    #[test]
+    fn invalid_input() {
+        assert!(parse(&[0xff, 0x00]).is_err());
+    }
+}
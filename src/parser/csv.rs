@@ -2,34 +2,131 @@ use serde_json::Value;
 
 use crate::error::QfError;
 
+/// Options controlling how CSV/TSV input is read.
+#[derive(Debug, Clone, Copy)]
+pub struct CsvOptions {
+    /// Field delimiter; `None` uses the format's default (`,` or tab).
+    pub delimiter: Option<u8>,
+    /// Treat the first row as column names. Headerless input becomes an
+    /// array of arrays.
+    pub headers: bool,
+    /// Convert number- and boolean-looking fields to JSON numbers/bools.
+    pub infer_types: bool,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        CsvOptions {
+            delimiter: None,
+            headers: true,
+            infer_types: false,
+        }
+    }
+}
+
 pub fn parse(input: &str) -> Result<Value, QfError> {
-    parse_delimited(input, b',')
+    parse_with(input, &CsvOptions::default())
 }
 
-pub(crate) fn parse_delimited(input: &str, delimiter: u8) -> Result<Value, QfError> {
-    let mut rdr = csv::ReaderBuilder::new()
-        .delimiter(delimiter)
-        .from_reader(input.as_bytes());
+pub fn parse_with(input: &str, opts: &CsvOptions) -> Result<Value, QfError> {
+    parse_delimited(input, b',', opts)
+}
+
+/// Parse delimited text, using `default_delimiter` unless `opts` overrides it.
+pub(crate) fn parse_delimited(
+    input: &str,
+    default_delimiter: u8,
+    opts: &CsvOptions,
+) -> Result<Value, QfError> {
+    let mut rdr = reader(input, default_delimiter, opts);
+    let headers = read_headers(&mut rdr, opts)?;
 
-    let headers: Vec<String> = rdr
+    let mut rows = Vec::new();
+    for result in rdr.records() {
+        let record = result.map_err(|e| QfError::Parse(e.to_string()))?;
+        rows.push(record_to_value(headers.as_deref(), &record, opts));
+    }
+
+    Ok(Value::Array(rows))
+}
+
+pub(crate) fn reader<'a>(
+    input: &'a str,
+    default_delimiter: u8,
+    opts: &CsvOptions,
+) -> csv::Reader<&'a [u8]> {
+    csv::ReaderBuilder::new()
+        .delimiter(opts.delimiter.unwrap_or(default_delimiter))
+        .has_headers(opts.headers)
+        .from_reader(input.as_bytes())
+}
+
+/// Column names, or `None` when the input has no header row.
+pub(crate) fn read_headers(
+    rdr: &mut csv::Reader<&[u8]>,
+    opts: &CsvOptions,
+) -> Result<Option<Vec<String>>, QfError> {
+    if !opts.headers {
+        return Ok(None);
+    }
+    let headers = rdr
         .headers()
         .map_err(|e| QfError::Parse(e.to_string()))?
         .iter()
         .map(|h| h.to_string())
         .collect();
+    Ok(Some(headers))
+}
 
-    let mut rows = Vec::new();
-    for result in rdr.records() {
-        let record = result.map_err(|e| QfError::Parse(e.to_string()))?;
-        let obj: serde_json::Map<String, Value> = headers
-            .iter()
-            .zip(record.iter())
-            .map(|(h, v)| (h.clone(), Value::String(v.to_string())))
-            .collect();
-        rows.push(Value::Object(obj));
+/// Convert a record to an object keyed by `headers`, or an array without them.
+pub(crate) fn record_to_value(
+    headers: Option<&[String]>,
+    record: &csv::StringRecord,
+    opts: &CsvOptions,
+) -> Value {
+    let field = |v: &str| {
+        if opts.infer_types {
+            infer_field(v)
+        } else {
+            Value::String(v.to_string())
+        }
+    };
+    match headers {
+        Some(headers) => Value::Object(
+            headers
+                .iter()
+                .zip(record.iter())
+                .map(|(h, v)| (h.clone(), field(v)))
+                .collect(),
+        ),
+        None => Value::Array(record.iter().map(field).collect()),
     }
+}
 
-    Ok(Value::Array(rows))
+/// Interpret a field as a bool or number when it looks like one.
+fn infer_field(s: &str) -> Value {
+    match s {
+        "true" => return Value::Bool(true),
+        "false" => return Value::Bool(false),
+        _ => {}
+    }
+    // Keep zero-padded codes like "007" as strings
+    let digits = s.trim_start_matches(['-', '+']);
+    if digits.len() > 1 && digits.starts_with('0') && digits.as_bytes()[1].is_ascii_digit() {
+        return Value::String(s.to_string());
+    }
+    if let Ok(n) = s.parse::<i64>() {
+        return Value::from(n);
+    }
+    // Leave things like "inf", "NaN" or "1_000" alone; only plain decimals
+    let numeric = !digits.is_empty()
+        && s.chars().all(|c| c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E'));
+    if numeric {
+        if let Some(n) = s.parse::<f64>().ok().and_then(serde_json::Number::from_f64) {
+            return Value::Number(n);
+        }
+    }
+    Value::String(s.to_string())
 }
 
 #[cfg(test)]
@@ -69,4 +166,40 @@ mod tests {
         let val = parse(input).unwrap();
         assert_eq!(val.as_array().unwrap().len(), 1);
     }
+
+    #[test]
+    fn headerless_rows_are_arrays() {
+        let opts = CsvOptions {
+            headers: false,
+            ..CsvOptions::default()
+        };
+        let val = parse_with("a,b\n1,2\n", &opts).unwrap();
+        assert_eq!(val, serde_json::json!([["a", "b"], ["1", "2"]]));
+    }
+
+    #[test]
+    fn custom_delimiter() {
+        let opts = CsvOptions {
+            delimiter: Some(b';'),
+            ..CsvOptions::default()
+        };
+        let val = parse_with("x;y\n1;2\n", &opts).unwrap();
+        assert_eq!(val[0]["y"], "2");
+    }
+
+    #[test]
+    fn infer_types() {
+        let opts = CsvOptions {
+            infer_types: true,
+            ..CsvOptions::default()
+        };
+        let input = "n,f,b,s,e,z\n42,-1.5,true,NYC,,007\n";
+        let val = parse_with(input, &opts).unwrap();
+        assert_eq!(
+            val[0],
+            serde_json::json!({"n": 42, "f": -1.5, "b": true, "s": "NYC", "e": "", "z": "007"})
+        );
+        assert_eq!(infer_field("inf"), "inf");
+        assert_eq!(infer_field("1e3"), 1000.0);
+    }
 }
@@ -0,0 +1,195 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use serde::de::{self, DeserializeSeed, Deserializer, EnumAccess, MapAccess, SeqAccess};
+use serde::de::{VariantAccess, Visitor};
+use serde_json::{Map, Number, Value};
+
+use super::DuplicateKeys;
+
+/// Deserializes any self-describing input into a `Value`, resolving
+/// repeated object keys according to the policy.
+///
+/// YAML tags are dropped and non-string keys are stringified, as in
+/// `yaml::parse`.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ValueSeed(pub DuplicateKeys);
+
+impl<'de> DeserializeSeed<'de> for ValueSeed {
+    type Value = Value;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Value, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for ValueSeed {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("any value")
+    }
+
+    fn visit_bool<E>(self, b: bool) -> Result<Value, E> {
+        Ok(Value::Bool(b))
+    }
+
+    fn visit_i64<E>(self, i: i64) -> Result<Value, E> {
+        Ok(Value::Number(i.into()))
+    }
+
+    fn visit_u64<E>(self, u: u64) -> Result<Value, E> {
+        Ok(Value::Number(u.into()))
+    }
+
+    fn visit_f64<E>(self, f: f64) -> Result<Value, E> {
+        Ok(Number::from_f64(f).map(Value::Number).unwrap_or(Value::Null))
+    }
+
+    fn visit_str<E>(self, s: &str) -> Result<Value, E> {
+        Ok(Value::String(s.to_string()))
+    }
+
+    fn visit_string<E>(self, s: String) -> Result<Value, E> {
+        Ok(Value::String(s))
+    }
+
+    fn visit_unit<E>(self) -> Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_none<E>(self) -> Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Value, D::Error> {
+        self.deserialize(deserializer)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Value, A::Error> {
+        let mut items = Vec::new();
+        while let Some(item) = seq.next_element_seed(self)? {
+            items.push(item);
+        }
+        Ok(Value::Array(items))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Value, A::Error> {
+        let mut obj = Map::new();
+        // Keys whose values have already been gathered into an array
+        let mut collected = HashSet::new();
+        while let Some(key) = map.next_key_seed(KeySeed)? {
+            let value = map.next_value_seed(self)?;
+            let Some(slot) = obj.get_mut(&key) else {
+                obj.insert(key, value);
+                continue;
+            };
+            match self.0 {
+                DuplicateKeys::Last => *slot = value,
+                DuplicateKeys::First => {}
+                DuplicateKeys::Error => {
+                    return Err(de::Error::custom(format!("duplicate key {key:?}")));
+                }
+                DuplicateKeys::Array => {
+                    if collected.insert(key) {
+                        *slot = Value::Array(vec![slot.take(), value]);
+                    } else if let Value::Array(items) = slot {
+                        items.push(value);
+                    }
+                }
+            }
+        }
+        Ok(Value::Object(obj))
+    }
+
+    /// A YAML tagged value; the tag is dropped.
+    fn visit_enum<A: EnumAccess<'de>>(self, data: A) -> Result<Value, A::Error> {
+        let (de::IgnoredAny, variant) = data.variant()?;
+        variant.newtype_variant_seed(self)
+    }
+}
+
+/// An object key; scalar YAML keys become their string form.
+struct KeySeed;
+
+impl<'de> DeserializeSeed<'de> for KeySeed {
+    type Value = String;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<String, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl Visitor<'_> for KeySeed {
+    type Value = String;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a scalar map key")
+    }
+
+    fn visit_bool<E>(self, b: bool) -> Result<String, E> {
+        Ok(b.to_string())
+    }
+
+    fn visit_i64<E>(self, i: i64) -> Result<String, E> {
+        Ok(i.to_string())
+    }
+
+    fn visit_u64<E>(self, u: u64) -> Result<String, E> {
+        Ok(u.to_string())
+    }
+
+    fn visit_f64<E>(self, f: f64) -> Result<String, E> {
+        Ok(f.to_string())
+    }
+
+    fn visit_str<E>(self, s: &str) -> Result<String, E> {
+        Ok(s.to_string())
+    }
+
+    fn visit_string<E>(self, s: String) -> Result<String, E> {
+        Ok(s)
+    }
+
+    fn visit_unit<E>(self) -> Result<String, E> {
+        Ok("null".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn parse_json(input: &str, policy: DuplicateKeys) -> Result<Value, serde_json::Error> {
+        ValueSeed(policy).deserialize(&mut serde_json::Deserializer::from_str(input))
+    }
+
+    #[test]
+    fn resolves_repeated_keys() {
+        let input = r#"{"a": 1, "b": {"c": 1, "c": [2]}, "a": 2, "a": 3}"#;
+        assert_eq!(
+            parse_json(input, DuplicateKeys::Last).unwrap(),
+            json!({"a": 3, "b": {"c": [2]}})
+        );
+        assert_eq!(
+            parse_json(input, DuplicateKeys::First).unwrap(),
+            json!({"a": 1, "b": {"c": 1}})
+        );
+        assert_eq!(
+            parse_json(input, DuplicateKeys::Array).unwrap(),
+            json!({"a": [1, 2, 3], "b": {"c": [1, [2]]}})
+        );
+        let err = parse_json(input, DuplicateKeys::Error).unwrap_err();
+        assert!(err.to_string().contains("duplicate key \"c\""));
+    }
+
+    #[test]
+    fn yaml_tags_and_scalar_keys() {
+        let input = "1: !Tag {x: 1}\ntrue: [a]\n";
+        let value = ValueSeed(DuplicateKeys::Error)
+            .deserialize(serde_yaml::Deserializer::from_str(input))
+            .unwrap();
+        assert_eq!(value, json!({"1": {"x": 1}, "true": ["a"]}));
+    }
+}
@@ -1,12 +1,34 @@
+use serde::de::DeserializeSeed;
 use serde_json::Value;
 
 use crate::error::QfError;
 
+use super::duplicates::ValueSeed;
+use super::DuplicateKeys;
+
 /// Parse a JSON string into a serde_json::Value.
 pub fn parse(input: &str) -> Result<Value, QfError> {
     serde_json::from_str(input).map_err(|e| QfError::Parse(e.to_string()))
 }
 
+/// Like [`parse`], resolving repeated object keys by `policy`.
+pub fn parse_with(input: &str, policy: Option<DuplicateKeys>) -> Result<Value, QfError> {
+    let Some(policy) = policy else {
+        return parse(input);
+    };
+    parse_value_with(input, policy).map_err(|e| QfError::Parse(e.to_string()))
+}
+
+/// [`parse_with`] for a policy that is set, keeping serde_json's error so
+/// callers can place it within a larger input.
+pub(crate) fn parse_value_with(
+    input: &str,
+    policy: DuplicateKeys,
+) -> Result<Value, serde_json::Error> {
+    let mut de = serde_json::Deserializer::from_str(input);
+    ValueSeed(policy).deserialize(&mut de).and_then(|value| de.end().map(|()| value))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -31,6 +53,18 @@ mod tests {
         assert_eq!(val["a"]["b"]["c"], true);
     }
 
+    #[test]
+    fn duplicate_key_policies() {
+        let input = r#"{"a":1,"a":2}"#;
+        assert_eq!(parse(input).unwrap(), serde_json::json!({"a": 2}));
+        assert_eq!(
+            parse_with(input, Some(DuplicateKeys::Array)).unwrap(),
+            serde_json::json!({"a": [1, 2]})
+        );
+        assert!(parse_with(input, Some(DuplicateKeys::Error)).is_err());
+        assert!(parse_with("{} x", Some(DuplicateKeys::Error)).is_err());
+    }
+
     #[test]
     fn invalid_json_errors() {
         assert!(parse("{not json}").is_err());
@@ -0,0 +1,37 @@
+use serde_json::Value;
+
+use crate::error::QfError;
+
+/// Parse a JSON5 string into a serde_json::Value.
+pub fn parse(input: &str) -> Result<Value, QfError> {
+    json5::from_str(input).map_err(|e| QfError::Parse(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn comments_and_unquoted_keys() {
+        let input = "// settings\n{\n  name: 'qf', /* inline */\n  port: 8080,\n}\n";
+        assert_eq!(parse(input).unwrap(), json!({"name": "qf", "port": 8080}));
+    }
+
+    #[test]
+    fn trailing_commas() {
+        let val = parse("{list: [1, 2, 3,], nested: {a: true,},}").unwrap();
+        assert_eq!(val, json!({"list": [1, 2, 3], "nested": {"a": true}}));
+    }
+
+    #[test]
+    fn single_quoted_strings() {
+        let val = parse(r#"{'key': 'it\'s "quoted"'}"#).unwrap();
+        assert_eq!(val["key"], r#"it's "quoted""#);
+    }
+
+    #[test]
+    fn invalid_input_errors() {
+        assert!(parse("{a: }").is_err());
+    }
+}
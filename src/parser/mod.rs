@@ -1,5 +1,10 @@
+#[cfg(feature = "cbor")]
+pub mod cbor;
 pub mod csv;
+mod duplicates;
 pub mod json;
+pub mod json5;
+pub mod properties;
 pub mod toml;
 pub mod tsv;
 pub mod xml;
@@ -10,15 +15,93 @@ use serde_json::Value;
 use crate::error::QfError;
 use crate::format::Format;
 
+/// Format-specific options for reading input.
+#[derive(Debug, Clone, Default)]
+pub struct ParseOptions {
+    pub csv: csv::CsvOptions,
+    /// How JSON and YAML objects with a repeated key are read; `None` keeps
+    /// each format's own rule (JSON keeps the last value, YAML rejects it).
+    pub duplicate_keys: Option<DuplicateKeys>,
+}
+
+/// What to do when an object repeats a key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DuplicateKeys {
+    /// Keep the last value.
+    Last,
+    /// Keep the first value.
+    First,
+    /// Reject the document.
+    Error,
+    /// Collect every value, in order, into an array.
+    Array,
+}
+
+impl std::str::FromStr for DuplicateKeys {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "last" => Ok(DuplicateKeys::Last),
+            "first" => Ok(DuplicateKeys::First),
+            "error" => Ok(DuplicateKeys::Error),
+            "array" => Ok(DuplicateKeys::Array),
+            _ => Err(format!(
+                "invalid duplicate key policy '{s}' (expected last, first, error or array)"
+            )),
+        }
+    }
+}
+
 /// Parse input text into a serde_json::Value based on format.
 pub fn parse(input: &str, format: Format) -> Result<Value, QfError> {
+    parse_with(input, format, &ParseOptions::default())
+}
+
+/// Like [`parse`], honouring `opts` for formats that take options.
+pub fn parse_with(input: &str, format: Format, opts: &ParseOptions) -> Result<Value, QfError> {
     match format {
-        Format::Yaml => yaml::parse(input),
-        Format::Json => json::parse(input),
+        Format::Yaml => yaml::parse_with(input, opts.duplicate_keys),
+        Format::Json => json::parse_with(input, opts.duplicate_keys),
+        Format::Json5 => json5::parse(input),
         Format::Xml => xml::parse(input),
         Format::Toml => toml::parse(input),
-        Format::Csv => csv::parse(input),
-        Format::Tsv => tsv::parse(input),
+        Format::Csv => csv::parse_with(input, &opts.csv),
+        Format::Tsv => tsv::parse_with(input, &opts.csv),
+        Format::Properties => properties::parse(input),
+        Format::Table | Format::Markdown => Err(QfError::UnsupportedFormat(format!(
+            "{format} is an output-only format"
+        ))),
+        Format::Cbor => Err(QfError::UnsupportedFormat(format!(
+            "{format} is a binary format; use parse_bytes"
+        ))),
+    }
+}
+
+/// Parse raw input bytes, decoding binary formats directly and text formats
+/// as UTF-8.
+pub fn parse_bytes(input: &[u8], format: Format) -> Result<Value, QfError> {
+    parse_bytes_with(input, format, &ParseOptions::default())
+}
+
+/// Like [`parse_bytes`], honouring `opts` for formats that take options.
+pub fn parse_bytes_with(
+    input: &[u8],
+    format: Format,
+    opts: &ParseOptions,
+) -> Result<Value, QfError> {
+    match format {
+        #[cfg(feature = "cbor")]
+        Format::Cbor => cbor::parse(input),
+        #[cfg(not(feature = "cbor"))]
+        Format::Cbor => Err(QfError::UnsupportedFormat(
+            "cbor (qf was built without the `cbor` feature)".to_string(),
+        )),
+        _ => {
+            let text = std::str::from_utf8(input)
+                .map_err(|e| QfError::Parse(format!("input is not valid UTF-8: {e}")))?;
+            parse_with(text, format, opts)
+        }
     }
 }
 
@@ -0,0 +1,158 @@
+use serde_json::Value;
+
+use crate::error::QfError;
+
+/// Parse a Java-style `.properties` file into a flat JSON object.
+///
+/// Dotted keys such as `a.b.c` are kept as-is rather than nested, matching
+/// `.properties` semantics; a query can split them later if needed.
+pub fn parse(input: &str) -> Result<Value, QfError> {
+    let mut map = serde_json::Map::new();
+    for line in logical_lines(input) {
+        let (key, value) = split_entry(&line);
+        map.insert(unescape(key)?, Value::String(unescape(value)?));
+    }
+    Ok(Value::Object(map))
+}
+
+/// Join physical lines ending in an unescaped backslash into logical lines,
+/// dropping blank lines and `#`/`!` comments.
+fn logical_lines(input: &str) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut current: Option<String> = None;
+
+    for raw in input.lines() {
+        let trimmed = raw.trim_start();
+        let line = match current.take() {
+            Some(mut acc) => {
+                acc.push_str(trimmed);
+                acc
+            }
+            None => {
+                if trimmed.is_empty() || trimmed.starts_with('#') || trimmed.starts_with('!') {
+                    continue;
+                }
+                trimmed.to_string()
+            }
+        };
+
+        let trailing = line.chars().rev().take_while(|&c| c == '\\').count();
+        if trailing % 2 == 1 {
+            current = Some(line[..line.len() - 1].to_string());
+        } else {
+            lines.push(line);
+        }
+    }
+    if let Some(last) = current {
+        lines.push(last);
+    }
+    lines
+}
+
+/// Split a logical line at the first unescaped `=`, `:` or whitespace.
+fn split_entry(line: &str) -> (&str, &str) {
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match c {
+            '\\' => escaped = true,
+            '=' | ':' => return (&line[..i], line[i + 1..].trim_start()),
+            c if c.is_whitespace() => {
+                let rest = line[i..].trim_start();
+                let rest = rest
+                    .strip_prefix('=')
+                    .or_else(|| rest.strip_prefix(':'))
+                    .unwrap_or(rest);
+                return (&line[..i], rest.trim_start());
+            }
+            _ => {}
+        }
+    }
+    (line, "")
+}
+
+fn unescape(s: &str) -> Result<String, QfError> {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => out.push('\t'),
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some('f') => out.push('\x0c'),
+            Some('u') => {
+                let hex: String = chars.by_ref().take(4).collect();
+                let code = u32::from_str_radix(&hex, 16)
+                    .ok()
+                    .filter(|_| hex.len() == 4)
+                    .ok_or_else(|| QfError::Parse(format!("invalid unicode escape: \\u{hex}")))?;
+                out.push(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER));
+            }
+            Some(other) => out.push(other),
+            None => {}
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn simple_pairs() {
+        let input = "name=qf\nversion: 1.0\nauthor  Pedro\n";
+        let val = parse(input).unwrap();
+        assert_eq!(val["name"], "qf");
+        assert_eq!(val["version"], "1.0");
+        assert_eq!(val["author"], "Pedro");
+    }
+
+    #[test]
+    fn comments_and_blank_lines() {
+        let input = "# comment\n! also a comment\n\nkey = value\n";
+        let val = parse(input).unwrap();
+        assert_eq!(val.as_object().unwrap().len(), 1);
+        assert_eq!(val["key"], "value");
+    }
+
+    #[test]
+    fn dotted_keys_stay_flat() {
+        let val = parse("a.b.c=1\n").unwrap();
+        assert_eq!(val["a.b.c"], "1");
+    }
+
+    #[test]
+    fn line_continuations() {
+        let input = "fruits = apple, \\\n         banana, \\\n         cherry\nnext=1\n";
+        let val = parse(input).unwrap();
+        assert_eq!(val["fruits"], "apple, banana, cherry");
+        assert_eq!(val["next"], "1");
+    }
+
+    #[test]
+    fn escaped_backslash_is_not_continuation() {
+        let val = parse("path=C:\\\\\nother=x\n").unwrap();
+        assert_eq!(val["path"], "C:\\");
+        assert_eq!(val["other"], "x");
+    }
+
+    #[test]
+    fn unicode_and_char_escapes() {
+        let val = parse("greeting=caf\\u00e9\\tbar\nkey\\=with\\:seps=v\n").unwrap();
+        assert_eq!(val["greeting"], "café\tbar");
+        assert_eq!(val["key=with:seps"], "v");
+    }
+
+    #[test]
+    fn invalid_unicode_escape() {
+        assert!(parse("bad=\\u12").is_err());
+    }
+}
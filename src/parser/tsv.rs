@@ -2,8 +2,14 @@ use serde_json::Value;
 
 use crate::error::QfError;
 
+use super::csv::CsvOptions;
+
 pub fn parse(input: &str) -> Result<Value, QfError> {
-    super::csv::parse_delimited(input, b'\t')
+    parse_with(input, &CsvOptions::default())
+}
+
+pub fn parse_with(input: &str, opts: &CsvOptions) -> Result<Value, QfError> {
+    super::csv::parse_delimited(input, b'\t', opts)
 }
 
 #[cfg(test)]
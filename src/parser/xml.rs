@@ -1,14 +1,108 @@
-use serde_json::Value;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use serde_json::{Map, Value};
 
 use crate::error::QfError;
 
+/// Parse an XML document into the object of its root element.
+///
+/// Each element becomes an object: attributes under `@name`, text under
+/// `$text` and child elements under their tag. Sibling elements that share
+/// a tag become an array in document order, so `<r><i>1</i><i>2</i></r>`
+/// is `{"i": [{"$text": "1"}, {"$text": "2"}]}` while a single `<i>` stays
+/// an object.
 pub fn parse(input: &str) -> Result<Value, QfError> {
-    quick_xml::de::from_str(input).map_err(|e| QfError::Parse(e.to_string()))
+    let mut reader = Reader::from_str(input);
+    reader.config_mut().trim_text(true);
+    // Open elements, innermost last: tag, fields so far, text so far
+    let mut stack: Vec<(String, Map<String, Value>, String)> = Vec::new();
+    loop {
+        let event = reader.read_event().map_err(parse_error)?;
+        match event {
+            Event::Start(start) => {
+                let name = tag_name(start.name().as_ref());
+                stack.push((name, attributes(&start)?, String::new()));
+            }
+            Event::Empty(start) => {
+                let element = Value::Object(attributes(&start)?);
+                match stack.last_mut() {
+                    Some((_, parent, _)) => {
+                        add_child(parent, tag_name(start.name().as_ref()), element);
+                    }
+                    None => return Ok(element),
+                }
+            }
+            Event::Text(text) => {
+                if let Some((_, _, buf)) = stack.last_mut() {
+                    buf.push_str(&text.unescape().map_err(parse_error)?);
+                }
+            }
+            Event::CData(data) => {
+                if let Some((_, _, buf)) = stack.last_mut() {
+                    buf.push_str(&String::from_utf8_lossy(&data));
+                }
+            }
+            Event::End(_) => {
+                let (name, mut fields, text) = stack.pop().expect("reader checks end tags");
+                if !text.is_empty() {
+                    fields.insert("$text".into(), Value::String(text));
+                }
+                match stack.last_mut() {
+                    Some((_, parent, _)) => add_child(parent, name, Value::Object(fields)),
+                    None => return Ok(Value::Object(fields)),
+                }
+            }
+            Event::Eof => {
+                return Err(QfError::Parse(match stack.last() {
+                    Some((name, ..)) => format!("unexpected end of input inside <{name}>"),
+                    None => "no root element".into(),
+                }));
+            }
+            // Declarations, comments, processing instructions and doctypes
+            _ => {}
+        }
+    }
+}
+
+fn attributes(start: &BytesStart) -> Result<Map<String, Value>, QfError> {
+    let mut fields = Map::new();
+    for attr in start.attributes() {
+        let attr = attr.map_err(parse_error)?;
+        let value = attr.unescape_value().map_err(parse_error)?;
+        fields.insert(
+            format!("@{}", tag_name(attr.key.as_ref())),
+            Value::String(value.into_owned()),
+        );
+    }
+    Ok(fields)
+}
+
+/// Add a child element, turning repeated tags into an array.
+fn add_child(parent: &mut Map<String, Value>, name: String, element: Value) {
+    match parent.get_mut(&name) {
+        Some(Value::Array(items)) => items.push(element),
+        Some(existing) => {
+            let first = existing.take();
+            *existing = Value::Array(vec![first, element]);
+        }
+        None => {
+            parent.insert(name, element);
+        }
+    }
+}
+
+fn tag_name(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).into_owned()
+}
+
+fn parse_error(e: impl std::fmt::Display) -> QfError {
+    QfError::Parse(e.to_string())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use serde_json::json;
 
     #[test]
     fn simple_elements() {
@@ -34,8 +128,37 @@ mod tests {
         assert_eq!(val["parent"]["child"]["$text"], "value");
     }
 
+    #[test]
+    fn single_and_repeated_children() {
+        let val = parse("<root><item>1</item></root>").unwrap();
+        assert_eq!(val, json!({"item": {"$text": "1"}}));
+        let val = parse("<root><item>1</item><item>2</item></root>").unwrap();
+        assert_eq!(val, json!({"item": [{"$text": "1"}, {"$text": "2"}]}));
+        // Document order is kept even with other elements in between
+        let input = "<r><a>x</a><b/><a id=\"2\">y</a><a/></r>";
+        assert_eq!(
+            parse(input).unwrap(),
+            json!({"a": [{"$text": "x"}, {"$text": "y", "@id": "2"}, {}], "b": {}})
+        );
+        // Deeper levels work the same way
+        let val = parse("<r><list><n>1</n><n>2</n></list></r>").unwrap();
+        assert_eq!(val["list"]["n"][1]["$text"], "2");
+    }
+
+    #[test]
+    fn text_entities_and_cdata() {
+        let input =
+            "<?xml version=\"1.0\"?><!-- c --><r><t>a &amp; b</t><c><![CDATA[<x>]]></c></r>";
+        assert_eq!(
+            parse(input).unwrap(),
+            json!({"t": {"$text": "a & b"}, "c": {"$text": "<x>"}})
+        );
+    }
+
     #[test]
     fn malformed_xml() {
         assert!(parse("<root><unclosed>").is_err());
+        assert!(parse("<a></b>").is_err());
+        assert!(parse("").is_err());
     }
 }
@@ -1,7 +1,11 @@
+use serde::de::DeserializeSeed;
 use serde_json::Value;
 
 use crate::error::QfError;
 
+use super::duplicates::ValueSeed;
+use super::DuplicateKeys;
+
 /// Parse a YAML string into a serde_json::Value.
 ///
 /// We parse via serde_yaml then convert to serde_json::Value so the rest
@@ -12,6 +16,17 @@ pub fn parse(input: &str) -> Result<Value, QfError> {
     yaml_to_json(yaml_value)
 }
 
+/// Like [`parse`], resolving repeated mapping keys by `policy` instead of
+/// rejecting the document.
+pub fn parse_with(input: &str, policy: Option<DuplicateKeys>) -> Result<Value, QfError> {
+    let Some(policy) = policy else {
+        return parse(input);
+    };
+    ValueSeed(policy)
+        .deserialize(serde_yaml::Deserializer::from_str(input))
+        .map_err(|e| QfError::Parse(e.to_string()))
+}
+
 fn yaml_to_json(yaml: serde_yaml::Value) -> Result<Value, QfError> {
     match yaml {
         serde_yaml::Value::Null => Ok(Value::Null),
@@ -56,6 +71,19 @@ fn yaml_to_json(yaml: serde_yaml::Value) -> Result<Value, QfError> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn duplicate_key_policies() {
+        let input = "a: 1\na: 2\n";
+        assert!(parse(input).is_err());
+        let last = parse_with(input, Some(DuplicateKeys::Last)).unwrap();
+        assert_eq!(last, serde_json::json!({"a": 2}));
+        assert_eq!(
+            parse_with(input, Some(DuplicateKeys::Array)).unwrap(),
+            serde_json::json!({"a": [1, 2]})
+        );
+        assert!(parse_with(input, Some(DuplicateKeys::Error)).is_err());
+    }
+
     #[test]
     fn parse_simple() {
         let val = parse("name: hello\ncount: 42").unwrap();
@@ -86,6 +114,26 @@ mod tests {
         assert!(val["empty"].is_null());
     }
 
+    #[test]
+    fn parse_bare_scalars() {
+        use serde_json::json;
+        let cases = [
+            ("42", json!(42)),
+            ("-1.5", json!(-1.5)),
+            ("true", json!(true)),
+            ("null", json!(null)),
+            ("~", json!(null)),
+            ("\"42\"", json!("42")),
+            // YAML 1.2: yes/no/on/off are plain strings, not booleans
+            ("yes", json!("yes")),
+            ("no", json!("no")),
+            ("off", json!("off")),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(parse(input).unwrap(), expected, "{input}");
+        }
+    }
+
     #[test]
     fn invalid_yaml_errors() {
         assert!(parse("key: [unterminated").is_err());
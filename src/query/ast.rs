@@ -19,8 +19,8 @@ pub enum Expr {
     /// Optional index: `.[N]?`
     OptionalIndex(Box<Expr>, Box<Expr>),
 
-    /// Slice: `.[M:N]`
-    Slice(Box<Expr>, Option<Box<Expr>>, Option<Box<Expr>>),
+    /// Slice: `.[M:N]`, or `.[M:N:S]` taking every S-th item
+    Slice(Box<Expr>, Option<Box<Expr>>, Option<Box<Expr>>, Option<Box<Expr>>),
 
     /// Iterator: `.[]`
     Iterate(Box<Expr>),
@@ -132,6 +132,26 @@ pub enum Expr {
     Optional(Box<Expr>),
 }
 
+/// A function definition on its own, as a module provides it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Def {
+    pub name: String,
+    pub params: Vec<String>,
+    pub body: Expr,
+}
+
+/// A module directive heading a program or module.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Directive {
+    /// `include "path";`: the module's definitions, unprefixed
+    Include(String),
+    /// `import "path" as name;`: the module's definitions as `name::f`
+    Import { path: String, alias: String },
+    /// `import "path" as $name;`: the JSON values in `path.json` as
+    /// `$name::name`
+    ImportData { path: String, alias: String },
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum BinOp {
     Add,
@@ -175,4 +195,7 @@ pub enum Pattern {
     Variable(String),
     Array(Vec<Pattern>),
     Object(Vec<(String, Pattern)>),
+    /// Destructuring alternatives: `[$a] ?// {$a}` binds the first pattern
+    /// that fits the value
+    Alternative(Vec<Pattern>),
 }
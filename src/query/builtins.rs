@@ -1,12 +1,16 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+
 use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 use regex::Regex;
 use serde_json::Value;
 
 use crate::error::QfError;
+use crate::format::Format;
 
 use super::ast::Expr;
 use super::env::Env;
-use super::eval::{eval, eval_one, is_truthy, value_type};
+use super::eval::{eval, eval_each, eval_one, is_truthy, recurse_each, value_type};
 
 pub fn call_builtin(
     name: &str,
@@ -19,36 +23,76 @@ pub fn call_builtin(
         ("length", 0) => Ok(vec![length(input)?]),
         ("utf8bytelength", 0) => match input {
             Value::String(s) => Ok(vec![Value::Number(s.len().into())]),
-            _ => Ok(vec![length(input)?]),
-        },
-        ("keys" | "keys_unsorted", 0) => Ok(vec![keys(input, name == "keys")?]),
-        ("values", 0) => match input {
-            Value::Object(m) => Ok(vec![Value::Array(m.values().cloned().collect())]),
-            Value::Array(_) => Ok(vec![input.clone()]),
             _ => Err(QfError::TypeError(format!(
-                "{} is not iterable",
+                "{} only strings have UTF-8 byte length",
                 value_type(input)
             ))),
         },
+        ("keys" | "keys_unsorted", 0) => Ok(vec![keys(input, name == "keys")?]),
+        // Type selectors: pass the input through if it matches, else nothing
+        ("values", 0) => Ok(select_if(input, !input.is_null())),
+        ("nulls", 0) => Ok(select_if(input, input.is_null())),
+        ("booleans", 0) => Ok(select_if(input, input.is_boolean())),
+        ("numbers", 0) => Ok(select_if(input, input.is_number())),
+        ("strings", 0) => Ok(select_if(input, input.is_string())),
+        ("arrays", 0) => Ok(select_if(input, input.is_array())),
+        ("objects", 0) => Ok(select_if(input, input.is_object())),
+        ("iterables", 0) => Ok(select_if(input, input.is_array() || input.is_object())),
+        ("scalars", 0) => Ok(select_if(input, !(input.is_array() || input.is_object()))),
         ("has", 1) => {
             let key = eval_one(&args[0], input, env)?;
-            match (input, &key) {
-                (Value::Object(m), Value::String(k)) => Ok(vec![Value::Bool(m.contains_key(k))]),
-                (Value::Array(a), Value::Number(n)) => {
-                    let i = n.as_i64().unwrap_or(-1);
-                    Ok(vec![Value::Bool(i >= 0 && (i as usize) < a.len())])
-                }
-                _ => Err(QfError::TypeError(format!(
-                    "has() requires object/string or array/number"
-                ))),
-            }
+            Ok(vec![Value::Bool(has_key(input, &key)?)])
         }
+        // `in(xs)` is `has` with the roles swapped, as in jq
         ("in", 1) => {
             let container = eval_one(&args[0], input, env)?;
-            match (&container, input) {
-                (Value::Object(m), Value::String(k)) => Ok(vec![Value::Bool(m.contains_key(k))]),
-                _ => Ok(vec![Value::Bool(false)]),
+            Ok(vec![Value::Bool(has_key(&container, input)?)])
+        }
+        ("IN", 1) => {
+            let found = eval(&args[0], input, env)?.iter().any(|v| values_equal(v, input));
+            Ok(vec![Value::Bool(found)])
+        }
+        // SQL-style helpers: rows are keyed by `f | tostring` without sorting
+        ("INDEX", 1 | 2) => {
+            let (rows, f) = match args {
+                [f] => (rows(input, env)?, f),
+                [stream, f] => (eval(stream, input, env)?, f),
+                _ => unreachable!(),
+            };
+            let mut index = serde_json::Map::new();
+            for row in rows {
+                for key in row_keys(f, &row, env)? {
+                    index.insert(key, row.clone());
+                }
+            }
+            Ok(vec![Value::Object(index)])
+        }
+        ("GROUP_BY", 1) => {
+            let mut groups = serde_json::Map::new();
+            for row in rows(input, env)? {
+                for key in row_keys(&args[0], &row, env)? {
+                    let group = groups.entry(key).or_insert_with(|| Value::Array(Vec::new()));
+                    if let Value::Array(items) = group {
+                        items.push(row.clone());
+                    }
+                }
+            }
+            Ok(vec![Value::Object(groups)])
+        }
+        ("UNIQUE_BY", 1) => {
+            let mut seen = std::collections::HashSet::new();
+            let mut unique = Vec::new();
+            for row in rows(input, env)? {
+                // Every key is marked seen, even once the row is kept
+                let mut new = false;
+                for key in row_keys(&args[0], &row, env)? {
+                    new |= seen.insert(key);
+                }
+                if new {
+                    unique.push(row);
+                }
             }
+            Ok(vec![Value::Array(unique)])
         }
         ("type", 0) => Ok(vec![Value::String(value_type(input).to_string())]),
         ("infinite", 0) => Ok(vec![json_f64(f64::INFINITY)]),
@@ -99,6 +143,10 @@ pub fn call_builtin(
                 v => Err(QfError::UserError(v.to_string())),
             }
         }
+        ("stderr", 0) => {
+            eprint!("{}", input);
+            Ok(vec![input.clone()])
+        }
         ("debug", 0) => {
             eprintln!("[\"DEBUG:\",{}]", input);
             Ok(vec![input.clone()])
@@ -111,6 +159,29 @@ pub fn call_builtin(
 
         // ── Map / transform ────────────────────────────────
         ("map", 1) => match input {
+            Value::Array(arr) if env.parallel_map() => {
+                use rayon::prelude::*;
+                use std::sync::atomic::{AtomicUsize, Ordering};
+                // The error is the lowest failing element's, as in a sequential
+                // map, so elements after a known failure need not run
+                let first_failure = AtomicUsize::new(usize::MAX);
+                let per_item: Vec<_> = arr
+                    .par_iter()
+                    .enumerate()
+                    .map(|(i, item)| {
+                        if i > first_failure.load(Ordering::Relaxed) {
+                            return None;
+                        }
+                        let result = eval(&args[0], item, env);
+                        if result.is_err() {
+                            first_failure.fetch_min(i, Ordering::Relaxed);
+                        }
+                        Some(result)
+                    })
+                    .collect();
+                let per_item = per_item.into_iter().flatten().collect::<Result<Vec<_>, _>>()?;
+                Ok(vec![Value::Array(per_item.into_iter().flatten().collect())])
+            }
             Value::Array(arr) => {
                 let mut results = Vec::new();
                 for item in arr {
@@ -136,9 +207,9 @@ pub fn call_builtin(
                 }
                 Ok(vec![Value::Array(results)])
             }
-            _ => Err(QfError::TypeError(format!(
-                "map_values requires object or array"
-            ))),
+            _ => Err(QfError::TypeError(
+                "map_values requires object or array".into(),
+            )),
         },
         ("to_entries", 0) => match input {
             Value::Object(map) => {
@@ -164,7 +235,7 @@ pub fn call_builtin(
                         .or_else(|| item.get("name"))
                         .and_then(|v| match v {
                             Value::String(s) => Some(s.clone()),
-                            Value::Number(n) => Some(n.to_string()),
+                            Value::Number(n) => Some(format_number(n)),
                             _ => None,
                         })
                         .unwrap_or_default();
@@ -184,6 +255,31 @@ pub fn call_builtin(
             let mapped = call_builtin("map", args, &entries[0], env)?;
             call_builtin("from_entries", &[], &mapped[0], env)
         }
+        // A header row of every key, then each object's values in that order
+        ("tabulate", 0) => match input {
+            // No rows, so no columns to head either
+            Value::Array(rows) if rows.is_empty() => Ok(vec![Value::Array(vec![])]),
+            Value::Array(rows) => {
+                if let Some(row) = rows.iter().find(|row| !row.is_object()) {
+                    return Err(QfError::TypeError(format!(
+                        "tabulate requires an array of objects, got {}",
+                        value_type(row)
+                    )));
+                }
+                let keys = crate::output::pretty::union_keys(rows);
+                let mut table = vec![Value::Array(
+                    keys.iter().map(|k| Value::String(k.clone())).collect(),
+                )];
+                table.extend(rows.iter().map(|row| {
+                    Value::Array(keys.iter().map(|k| row[k].clone()).collect())
+                }));
+                Ok(vec![Value::Array(table)])
+            }
+            _ => Err(QfError::TypeError(format!(
+                "tabulate requires an array of objects, got {}",
+                value_type(input)
+            ))),
+        },
         ("transpose", 0) => match input {
             Value::Array(arr) => {
                 if arr.is_empty() {
@@ -240,6 +336,8 @@ pub fn call_builtin(
             }
             _ => Err(QfError::TypeError("any requires array".into())),
         },
+        ("any", 2) => Ok(vec![Value::Bool(short_circuit(&args[0], &args[1], true, input, env)?)]),
+        ("all", 2) => Ok(vec![Value::Bool(!short_circuit(&args[0], &args[1], false, input, env)?)]),
         ("all", 0) => match input {
             Value::Array(arr) => Ok(vec![Value::Bool(arr.iter().all(is_truthy))]),
             _ => Err(QfError::TypeError("all requires array".into())),
@@ -268,6 +366,7 @@ pub fn call_builtin(
             let mut results = Vec::new();
             for i in 0..end {
                 results.push(Value::Number(i.into()));
+                env.check_outputs(results.len())?;
             }
             Ok(results)
         },
@@ -277,6 +376,7 @@ pub fn call_builtin(
             let mut results = Vec::new();
             for i in start..end {
                 results.push(Value::Number(i.into()));
+                env.check_outputs(results.len())?;
             }
             Ok(results)
         },
@@ -290,11 +390,13 @@ pub fn call_builtin(
             if step > 0.0 {
                 while i < end {
                     results.push(json_f64(i));
+                    env.check_outputs(results.len())?;
                     i += step;
                 }
             } else {
                 while i > end {
                     results.push(json_f64(i));
+                    env.check_outputs(results.len())?;
                     i += step;
                 }
             }
@@ -305,20 +407,14 @@ pub fn call_builtin(
         ("sort", 0) => match input {
             Value::Array(arr) => {
                 let mut sorted = arr.clone();
-                sorted.sort_by(|a, b| compare_values(a, b));
+                sorted.sort_by(compare_values);
                 Ok(vec![Value::Array(sorted)])
             }
             _ => Err(QfError::TypeError("sort requires array".into())),
         },
         ("sort_by", 1) => match input {
             Value::Array(arr) => {
-                let mut indexed: Vec<(Value, Value)> = arr
-                    .iter()
-                    .map(|item| {
-                        let key = eval_one(&args[0], item, env).unwrap_or(Value::Null);
-                        (key, item.clone())
-                    })
-                    .collect();
+                let mut indexed = keyed_by(arr, &args[0], env)?;
                 indexed.sort_by(|a, b| compare_values(&a.0, &b.0));
                 Ok(vec![Value::Array(
                     indexed.into_iter().map(|(_, v)| v).collect(),
@@ -328,13 +424,7 @@ pub fn call_builtin(
         },
         ("group_by", 1) => match input {
             Value::Array(arr) => {
-                let mut keyed: Vec<(Value, Value)> = arr
-                    .iter()
-                    .map(|item| {
-                        let key = eval_one(&args[0], item, env).unwrap_or(Value::Null);
-                        (key, item.clone())
-                    })
-                    .collect();
+                let mut keyed = keyed_by(arr, &args[0], env)?;
                 keyed.sort_by(|a, b| compare_values(&a.0, &b.0));
 
                 let mut groups: Vec<Value> = Vec::new();
@@ -342,7 +432,7 @@ pub fn call_builtin(
                 let mut current_group: Vec<Value> = Vec::new();
 
                 for (key, val) in keyed {
-                    if current_key.as_ref() == Some(&key) {
+                    if current_key.as_ref().is_some_and(|k| compare_values(k, &key).is_eq()) {
                         current_group.push(val);
                     } else {
                         if !current_group.is_empty() {
@@ -362,8 +452,8 @@ pub fn call_builtin(
         ("unique", 0) => match input {
             Value::Array(arr) => {
                 let mut sorted = arr.clone();
-                sorted.sort_by(|a, b| compare_values(a, b));
-                sorted.dedup();
+                sorted.sort_by(compare_values);
+                sorted.dedup_by(|a, b| compare_values(a, b).is_eq());
                 Ok(vec![Value::Array(sorted)])
             }
             _ => Err(QfError::TypeError("unique requires array".into())),
@@ -372,11 +462,10 @@ pub fn call_builtin(
             Value::Array(arr) => {
                 let mut seen = Vec::new();
                 let mut result = Vec::new();
-                for item in arr {
-                    let key = eval_one(&args[0], item, env)?;
-                    if !seen.contains(&key) {
+                for (key, item) in keyed_by(arr, &args[0], env)? {
+                    if !seen.iter().any(|k| compare_values(k, &key).is_eq()) {
                         seen.push(key);
-                        result.push(item.clone());
+                        result.push(item);
                     }
                 }
                 Ok(vec![Value::Array(result)])
@@ -414,32 +503,27 @@ pub fn call_builtin(
             Value::Array(_) => Ok(vec![Value::Null]),
             _ => Err(QfError::TypeError("max requires array".into())),
         },
+        // Ties go the way jq's minmax_by breaks them, which is also what
+        // Iterator::min_by/max_by do: the first of equal minima and the last
+        // of equal maxima
         ("min_by", 1) => match input {
             Value::Array(arr) if !arr.is_empty() => {
-                let m = arr
-                    .iter()
-                    .min_by(|a, b| {
-                        let ka = eval_one(&args[0], a, env).unwrap_or(Value::Null);
-                        let kb = eval_one(&args[0], b, env).unwrap_or(Value::Null);
-                        compare_values(&ka, &kb)
-                    })
+                let (_, m) = keyed_by(arr, &args[0], env)?
+                    .into_iter()
+                    .min_by(|a, b| compare_values(&a.0, &b.0))
                     .unwrap();
-                Ok(vec![m.clone()])
+                Ok(vec![m])
             }
             Value::Array(_) => Ok(vec![Value::Null]),
             _ => Err(QfError::TypeError("min_by requires array".into())),
         },
         ("max_by", 1) => match input {
             Value::Array(arr) if !arr.is_empty() => {
-                let m = arr
-                    .iter()
-                    .max_by(|a, b| {
-                        let ka = eval_one(&args[0], a, env).unwrap_or(Value::Null);
-                        let kb = eval_one(&args[0], b, env).unwrap_or(Value::Null);
-                        compare_values(&ka, &kb)
-                    })
+                let (_, m) = keyed_by(arr, &args[0], env)?
+                    .into_iter()
+                    .max_by(|a, b| compare_values(&a.0, &b.0))
                     .unwrap();
-                Ok(vec![m.clone()])
+                Ok(vec![m])
             }
             Value::Array(_) => Ok(vec![Value::Null]),
             _ => Err(QfError::TypeError("max_by requires array".into())),
@@ -458,11 +542,8 @@ pub fn call_builtin(
             let needle = eval_one(&args[0], input, env)?;
             match input {
                 Value::Array(arr) => {
-                    let indices: Vec<Value> = arr
-                        .iter()
-                        .enumerate()
-                        .filter(|(_, v)| *v == &needle)
-                        .map(|(i, _)| Value::Number(i.into()))
+                    let indices: Vec<Value> = array_indices(arr, &needle)
+                        .map(|i| Value::Number(i.into()))
                         .collect();
                     if name == "index" {
                         Ok(vec![indices.into_iter().next().unwrap_or(Value::Null)])
@@ -492,7 +573,7 @@ pub fn call_builtin(
             let needle = eval_one(&args[0], input, env)?;
             match input {
                 Value::Array(arr) => {
-                    let idx = arr.iter().rposition(|v| v == &needle);
+                    let idx = array_indices(arr, &needle).last();
                     Ok(vec![idx
                         .map(|i| Value::Number(i.into()))
                         .unwrap_or(Value::Null)])
@@ -514,19 +595,13 @@ pub fn call_builtin(
         // ── Type conversion ────────────────────────────────
         ("tostring", 0) => match input {
             Value::String(_) => Ok(vec![input.clone()]),
-            Value::Null => Ok(vec![Value::String("null".into())]),
-            Value::Bool(b) => Ok(vec![Value::String(b.to_string())]),
-            Value::Number(n) => Ok(vec![Value::String(n.to_string())]),
-            _ => Ok(vec![Value::String(serde_json::to_string(input).unwrap_or_default())]),
+            _ => Ok(vec![Value::String(value_to_string(input))]),
         },
         ("tonumber", 0) => match input {
             Value::Number(_) => Ok(vec![input.clone()]),
-            Value::String(s) => {
-                let n: f64 = s
-                    .parse()
-                    .map_err(|_| QfError::TypeError(format!("cannot convert \"{s}\" to number")))?;
-                Ok(vec![json_f64(n)])
-            }
+            Value::String(s) => parse_number(s)
+                .map(|n| vec![n])
+                .ok_or_else(|| QfError::TypeError(format!("cannot convert \"{s}\" to number"))),
             _ => Err(QfError::TypeError(format!(
                 "cannot convert {} to number",
                 value_type(input)
@@ -540,28 +615,34 @@ pub fn call_builtin(
             Value::String(s) => Ok(vec![Value::String(s.to_ascii_uppercase())]),
             _ => Err(QfError::TypeError("ascii_upcase requires string".into())),
         },
-        ("ltrimstr", 1) => {
-            let prefix = eval_one(&args[0], input, env)?;
-            match (input, &prefix) {
-                (Value::String(s), Value::String(p)) => Ok(vec![Value::String(
-                    s.strip_prefix(p.as_str())
-                        .unwrap_or(s)
-                        .to_string(),
-                )]),
-                _ => Ok(vec![input.clone()]),
-            }
-        }
-        ("rtrimstr", 1) => {
-            let suffix = eval_one(&args[0], input, env)?;
-            match (input, &suffix) {
-                (Value::String(s), Value::String(p)) => Ok(vec![Value::String(
-                    s.strip_suffix(p.as_str())
-                        .unwrap_or(s)
-                        .to_string(),
-                )]),
-                _ => Ok(vec![input.clone()]),
-            }
-        }
+        // Full Unicode case mapping; `ß` upcases to `SS`
+        ("downcase", 0) => match input {
+            Value::String(s) => Ok(vec![Value::String(s.to_lowercase())]),
+            _ => Err(QfError::TypeError("downcase requires string".into())),
+        },
+        ("upcase", 0) => match input {
+            Value::String(s) => Ok(vec![Value::String(s.to_uppercase())]),
+            _ => Err(QfError::TypeError("upcase requires string".into())),
+        },
+        // One output per argument output, like every jq builtin
+        ("ltrimstr", 1) => Ok(eval(&args[0], input, env)?
+            .iter()
+            .map(|prefix| match (input, prefix) {
+                (Value::String(s), Value::String(p)) => {
+                    Value::String(s.strip_prefix(p.as_str()).unwrap_or(s).to_string())
+                }
+                _ => input.clone(),
+            })
+            .collect()),
+        ("rtrimstr", 1) => Ok(eval(&args[0], input, env)?
+            .iter()
+            .map(|suffix| match (input, suffix) {
+                (Value::String(s), Value::String(p)) => {
+                    Value::String(s.strip_suffix(p.as_str()).unwrap_or(s).to_string())
+                }
+                _ => input.clone(),
+            })
+            .collect()),
         ("trim", 0) => match input {
             Value::String(s) => Ok(vec![Value::String(s.trim().to_string())]),
             _ => Ok(vec![input.clone()]),
@@ -596,24 +677,20 @@ pub fn call_builtin(
                 _ => Err(QfError::TypeError("join requires array and string".into())),
             }
         }
-        ("startswith", 1) => {
-            let prefix = eval_one(&args[0], input, env)?;
-            match (input, &prefix) {
-                (Value::String(s), Value::String(p)) => {
-                    Ok(vec![Value::Bool(s.starts_with(p.as_str()))])
-                }
+        ("startswith", 1) => eval(&args[0], input, env)?
+            .iter()
+            .map(|prefix| match (input, prefix) {
+                (Value::String(s), Value::String(p)) => Ok(Value::Bool(s.starts_with(p.as_str()))),
                 _ => Err(QfError::TypeError("startswith requires strings".into())),
-            }
-        }
-        ("endswith", 1) => {
-            let suffix = eval_one(&args[0], input, env)?;
-            match (input, &suffix) {
-                (Value::String(s), Value::String(p)) => {
-                    Ok(vec![Value::Bool(s.ends_with(p.as_str()))])
-                }
+            })
+            .collect(),
+        ("endswith", 1) => eval(&args[0], input, env)?
+            .iter()
+            .map(|suffix| match (input, suffix) {
+                (Value::String(s), Value::String(p)) => Ok(Value::Bool(s.ends_with(p.as_str()))),
                 _ => Err(QfError::TypeError("endswith requires strings".into())),
-            }
-        }
+            })
+            .collect(),
         ("ascii", 0) => match input {
             Value::Number(n) => {
                 let c = n.as_u64().unwrap_or(0) as u8 as char;
@@ -669,32 +746,15 @@ pub fn call_builtin(
             match (input, &pattern) {
                 (Value::String(s), Value::String(p)) => {
                     let re = build_regex(p, &flags)?;
-                    if let Some(m) = re.find(s) {
-                        let mut result = serde_json::Map::new();
-                        result.insert("offset".into(), Value::Number(m.start().into()));
-                        result.insert("length".into(), Value::Number(m.len().into()));
-                        result.insert("string".into(), Value::String(m.as_str().to_string()));
-                        let captures: Vec<Value> = re
-                            .captures(s)
-                            .map(|caps| {
-                                (1..caps.len())
-                                    .map(|i| {
-                                        let mut cap = serde_json::Map::new();
-                                        if let Some(m) = caps.get(i) {
-                                            cap.insert("offset".into(), Value::Number(m.start().into()));
-                                            cap.insert("length".into(), Value::Number(m.len().into()));
-                                            cap.insert("string".into(), Value::String(m.as_str().to_string()));
-                                            cap.insert("name".into(), Value::Null);
-                                        }
-                                        Value::Object(cap)
-                                    })
-                                    .collect()
-                            })
-                            .unwrap_or_default();
-                        result.insert("captures".into(), Value::Array(captures));
-                        Ok(vec![Value::Object(result)])
+                    // Like jq, no match produces no output; `g` yields every match
+                    let matches = re
+                        .captures_iter(s)
+                        .filter(|caps| !(flags.contains('n') && caps[0].is_empty()))
+                        .map(|caps| match_object(&re, &caps, s));
+                    if flags.contains('g') {
+                        Ok(matches.collect())
                     } else {
-                        Ok(vec![Value::Null])
+                        Ok(matches.take(1).collect())
                     }
                 }
                 _ => Err(QfError::TypeError("match requires string".into())),
@@ -728,13 +788,19 @@ pub fn call_builtin(
                 _ => Err(QfError::TypeError("capture requires string".into())),
             }
         }
-        ("scan", 1) => {
+        ("scan", 1) | ("scan", 2) => {
             let pattern = eval_one(&args[0], input, env)?;
+            let flags = if args.len() > 1 {
+                eval_one(&args[1], input, env)?.as_str().unwrap_or("").to_string()
+            } else {
+                String::new()
+            };
             match (input, &pattern) {
                 (Value::String(s), Value::String(p)) => {
-                    let re = build_regex(p, "")?;
+                    let re = build_regex(p, &flags)?;
                     let results: Vec<Value> = re
                         .find_iter(s)
+                        .filter(|m| !(flags.contains('n') && m.is_empty()))
                         .map(|m| Value::String(m.as_str().to_string()))
                         .collect();
                     Ok(vec![Value::Array(results)])
@@ -753,7 +819,9 @@ pub fn call_builtin(
             match (input, &pattern, &replacement) {
                 (Value::String(s), Value::String(p), Value::String(r)) => {
                     let re = build_regex(p, &flags)?;
-                    Ok(vec![Value::String(re.replace(s, r.as_str()).to_string())])
+                    // `sub(re; s; "g")` is `gsub(re; s)`
+                    let limit = if flags.contains('g') { 0 } else { 1 };
+                    Ok(vec![Value::String(replace_matches(&re, s, r, limit, &flags))])
                 }
                 _ => Err(QfError::TypeError("sub requires strings".into())),
             }
@@ -769,7 +837,7 @@ pub fn call_builtin(
             match (input, &pattern, &replacement) {
                 (Value::String(s), Value::String(p), Value::String(r)) => {
                     let re = build_regex(p, &flags)?;
-                    Ok(vec![Value::String(re.replace_all(s, r.as_str()).to_string())])
+                    Ok(vec![Value::String(replace_matches(&re, s, r, 0, &flags))])
                 }
                 _ => Err(QfError::TypeError("gsub requires strings".into())),
             }
@@ -777,16 +845,24 @@ pub fn call_builtin(
 
         // ── Selection helpers ──────────────────────────────
         ("first", 1) => {
-            let vals = eval(&args[0], input, env)?;
-            Ok(vals.into_iter().take(1).collect())
+            let mut first = None;
+            eval_each(&args[0], input, env, &mut |v| {
+                first = Some(v);
+                Ok(false)
+            })?;
+            Ok(first.into_iter().collect())
         }
         ("first", 0) => match input {
             Value::Array(arr) => Ok(vec![arr.first().cloned().unwrap_or(Value::Null)]),
             _ => Ok(vec![input.clone()]),
         },
         ("last", 1) => {
-            let vals = eval(&args[0], input, env)?;
-            Ok(vals.into_iter().last().into_iter().collect())
+            let mut last = None;
+            eval_each(&args[0], input, env, &mut |v| {
+                last = Some(v);
+                Ok(true)
+            })?;
+            Ok(last.into_iter().collect())
         }
         ("last", 0) => match input {
             Value::Array(arr) => Ok(vec![arr.last().cloned().unwrap_or(Value::Null)]),
@@ -800,62 +876,70 @@ pub fn call_builtin(
                 _ => Ok(vec![Value::Null]),
             }
         }
+        ("nth", 2) => {
+            let n = eval_one(&args[0], input, env)?.as_i64().unwrap_or(0);
+            if n < 0 {
+                return Err(QfError::Runtime(
+                    "Out of bounds negative array index".into(),
+                ));
+            }
+            let mut seen = 0;
+            let mut nth = None;
+            eval_each(&args[1], input, env, &mut |v| {
+                if seen == n {
+                    nth = Some(v);
+                    return Ok(false);
+                }
+                seen += 1;
+                Ok(true)
+            })?;
+            Ok(nth.into_iter().collect())
+        }
         ("limit", 2) => {
-            let n = eval_one(&args[0], input, env)?;
-            let count = n.as_u64().unwrap_or(0) as usize;
-            let vals = eval(&args[1], input, env)?;
-            Ok(vals.into_iter().take(count).collect())
+            let n = eval_one(&args[0], input, env)?.as_i64().unwrap_or(0);
+            let mut results = Vec::new();
+            if n > 0 {
+                eval_each(&args[1], input, env, &mut |v| {
+                    results.push(v);
+                    Ok((results.len() as i64) < n)
+                })?;
+            }
+            Ok(results)
         }
         ("recurse", 0) => {
             let mut results = Vec::new();
             recurse_all(input, &mut results);
             Ok(results)
         }
-        ("recurse", 1) => {
-            let mut results = vec![input.clone()];
-            let mut current = vec![input.clone()];
-            for _ in 0..256 {
-                let mut next = Vec::new();
-                for val in &current {
-                    match eval(&args[0], val, env) {
-                        Ok(vals) => {
-                            for v in vals {
-                                if !v.is_null() {
-                                    next.push(v);
-                                }
-                            }
-                        }
-                        Err(_) => {}
-                    }
-                }
-                if next.is_empty() {
-                    break;
-                }
-                results.extend(next.clone());
-                current = next;
-            }
+        ("recurse", 1 | 2) => {
+            let mut results = Vec::new();
+            recurse_each(&args[0], args.get(1), input, env, &mut |v| {
+                results.push(v);
+                Ok(true)
+            })?;
             Ok(results)
         }
         ("until", 2) => {
             let mut val = input.clone();
-            for _ in 0..10000 {
+            loop {
                 let cond = eval_one(&args[0], &val, env)?;
                 if is_truthy(&cond) {
-                    return Ok(vec![val]);
+                    break;
                 }
                 val = eval_one(&args[1], &val, env)?;
             }
-            Err(QfError::Runtime("until: loop limit exceeded".into()))
+            Ok(vec![val])
         }
         ("while", 2) => {
             let mut val = input.clone();
             let mut results = Vec::new();
-            for _ in 0..10000 {
+            loop {
                 let cond = eval_one(&args[0], &val, env)?;
                 if !is_truthy(&cond) {
                     break;
                 }
                 results.push(val.clone());
+                env.check_outputs(results.len())?;
                 val = eval_one(&args[1], &val, env)?;
             }
             Ok(results)
@@ -863,11 +947,11 @@ pub fn call_builtin(
         ("repeat", 1) => {
             let mut val = input.clone();
             let mut results = Vec::new();
-            for _ in 0..10000 {
+            loop {
                 results.push(val.clone());
+                env.check_outputs(results.len())?;
                 val = eval_one(&args[0], &val, env)?;
             }
-            Ok(results)
         }
 
         // ── Math ───────────────────────────────────────────
@@ -897,11 +981,36 @@ pub fn call_builtin(
             let x = eval_one(&args[1], input, env)?.as_f64().unwrap_or(0.0);
             Ok(vec![json_f64(y.atan2(x))])
         }
+        ("sinh", 0) => num_op(input, f64::sinh),
+        ("cosh", 0) => num_op(input, f64::cosh),
+        ("tanh", 0) => num_op(input, f64::tanh),
+        ("asinh", 0) => num_op(input, f64::asinh),
+        ("acosh", 0) => num_op(input, f64::acosh),
+        ("atanh", 0) => num_op(input, f64::atanh),
+        ("cbrt", 0) => num_op(input, f64::cbrt),
+        ("trunc", 0) => num_op(input, f64::trunc),
+        ("expm1", 0) => num_op(input, f64::exp_m1),
+        ("log1p", 0) => num_op(input, f64::ln_1p),
+        ("logb", 0) => num_op(input, logb),
+        ("significand", 0) => num_op(input, significand),
+        ("hypot", 2) => {
+            let x = eval_one(&args[0], input, env)?.as_f64().unwrap_or(0.0);
+            let y = eval_one(&args[1], input, env)?.as_f64().unwrap_or(0.0);
+            Ok(vec![json_f64(x.hypot(y))])
+        }
+        ("copysign", 2) => {
+            let x = eval_one(&args[0], input, env)?.as_f64().unwrap_or(0.0);
+            let y = eval_one(&args[1], input, env)?.as_f64().unwrap_or(0.0);
+            Ok(vec![json_f64(x.copysign(y))])
+        }
 
         // ── JSON ───────────────────────────────────────────
-        ("tojson", 0) => Ok(vec![Value::String(
-            serde_json::to_string(input).unwrap_or_default(),
-        )]),
+        ("tojson", 0) => Ok(vec![Value::String(to_json_string(input, env))]),
+        // Multi-line JSON, indented by --tojson-indent or else two spaces
+        ("tojsonpretty", 0) => {
+            let indent = env.tojson_indent().unwrap_or(2);
+            Ok(vec![Value::String(pretty_json(input, indent))])
+        }
         ("fromjson", 0) => match input {
             Value::String(s) => {
                 let v: Value = serde_json::from_str(s)
@@ -938,6 +1047,48 @@ pub fn call_builtin(
             collect_leaf_paths(input, &mut vec![], &mut result);
             Ok(result)
         }
+        ("scalar_paths", 0) | ("array_paths", 0) | ("object_paths", 0) => {
+            let keep: fn(&Value) -> bool = match name {
+                "scalar_paths" => |v| !v.is_array() && !v.is_object(),
+                "array_paths" => Value::is_array,
+                _ => Value::is_object,
+            };
+            let mut result = Vec::new();
+            collect_typed_paths(input, &mut vec![], &mut result, keep);
+            Ok(result)
+        }
+        ("tostream", 0) => {
+            let mut events = Vec::new();
+            collect_stream_events(input, &mut vec![], &mut events);
+            Ok(events)
+        }
+        ("fromstream", 1) => {
+            let mut results = Vec::new();
+            let mut current = Value::Null;
+            for event in eval(&args[0], input, env)? {
+                let (path, leaf) = match &event {
+                    Value::Array(e) => match (e.first(), e.get(1), e.len()) {
+                        (Some(Value::Array(path)), leaf, 1 | 2) => (path, leaf),
+                        _ => return Err(QfError::Runtime("Invalid stream event".into())),
+                    },
+                    _ => return Err(QfError::Runtime("Invalid stream event".into())),
+                };
+                match leaf {
+                    Some(leaf) => {
+                        let segments = path_segments(path);
+                        current = super::eval::set_path_pub(&current, &segments, leaf.clone())?;
+                        // A top-level scalar is complete on its own
+                        if path.is_empty() {
+                            results.push(std::mem::take(&mut current));
+                        }
+                    }
+                    // Closing the last child of the top-level value
+                    None if path.len() == 1 => results.push(std::mem::take(&mut current)),
+                    None => {}
+                }
+            }
+            Ok(results)
+        }
         ("getpath", 1) => {
             let path = eval_one(&args[0], input, env)?;
             match &path {
@@ -949,13 +1100,12 @@ pub fn call_builtin(
                                 .as_object()
                                 .and_then(|m| m.get(k).cloned())
                                 .unwrap_or(Value::Null),
-                            Value::Number(n) => {
-                                let i = n.as_i64().unwrap_or(0);
-                                current
-                                    .as_array()
-                                    .and_then(|a| a.get(i as usize).cloned())
-                                    .unwrap_or(Value::Null)
-                            }
+                            Value::Number(n) => current
+                                .as_array()
+                                .and_then(|a| a.get(super::eval::resolve_index(a.len(), n)?).cloned())
+                                .unwrap_or(Value::Null),
+                            // Mismatched segments (a key on an array, an index
+                            // on an object, anything on a scalar) read as null
                             _ => Value::Null,
                         };
                     }
@@ -969,17 +1119,7 @@ pub fn call_builtin(
             let val = eval_one(&args[1], input, env)?;
             match &path {
                 Value::Array(arr) => {
-                    let segments: Vec<super::eval::PathSegment> = arr
-                        .iter()
-                        .filter_map(|v| match v {
-                            Value::String(s) => Some(super::eval::PathSegment::Key(s.clone())),
-                            Value::Number(n) => {
-                                Some(super::eval::PathSegment::Index(n.as_i64().unwrap_or(0)))
-                            }
-                            _ => None,
-                        })
-                        .collect();
-                    Ok(vec![super::eval::set_path_pub(input, &segments, val)?])
+                    Ok(vec![super::eval::set_path_pub(input, &path_segments(arr), val)?])
                 }
                 _ => Err(QfError::TypeError("setpath requires array path".into())),
             }
@@ -988,33 +1128,21 @@ pub fn call_builtin(
             let paths_val = eval_one(&args[0], input, env)?;
             match &paths_val {
                 Value::Array(paths) => {
-                    let mut result = input.clone();
-                    // Delete paths in reverse order to avoid index shifting
-                    let mut sorted_paths: Vec<&Value> = paths.iter().collect();
-                    sorted_paths.sort_by(|a, b| {
-                        let la = a.as_array().map(|v| v.len()).unwrap_or(0);
-                        let lb = b.as_array().map(|v| v.len()).unwrap_or(0);
-                        lb.cmp(&la)
-                    });
-                    for path in sorted_paths {
-                        if let Value::Array(segs) = path {
-                            result = delete_path(&result, segs);
-                        }
-                    }
-                    Ok(vec![result])
+                    let paths = paths
+                        .iter()
+                        .filter_map(|path| match path {
+                            Value::Array(segs) => Some(path_segments(segs)),
+                            _ => None,
+                        })
+                        .collect();
+                    Ok(vec![delete_paths(input, paths)])
                 }
                 _ => Err(QfError::TypeError("delpaths requires array".into())),
             }
         }
 
         // ── Environment ────────────────────────────────────
-        ("env", 0) => {
-            let mut map = serde_json::Map::new();
-            for (k, v) in std::env::vars() {
-                map.insert(k, Value::String(v));
-            }
-            Ok(vec![Value::Object(map)])
-        }
+        ("env", 0) => Ok(vec![env_object(env)]),
 
         // ── Not ────────────────────────────────────────────
         ("not", 0) => Ok(vec![Value::Bool(!is_truthy(input))]),
@@ -1023,53 +1151,18 @@ pub fn call_builtin(
         ("null", 0) => Ok(vec![Value::Null]),
         ("true", 0) => Ok(vec![Value::Bool(true)]),
         ("false", 0) => Ok(vec![Value::Bool(false)]),
+        ("input", 0) if !env.allow_env() => Err(QfError::Runtime("No more inputs".into())),
         ("input", 0) => Ok(vec![Value::Null]), // simplified
         ("inputs", 0) => Ok(vec![]),            // simplified
+        ("input_filename", 0) => Ok(vec![env
+            .input_filename()
+            .map_or(Value::Null, |name| Value::String(name.to_string()))]),
+        ("input_line_number", 0) => Ok(vec![Value::Number(env.input_line_number().into())]),
 
         // ── Array manipulation ─────────────────────────────
         ("del", 1) => {
-            // del(.foo) removes the key
-            // We need to collect paths and delete them
-            match &args[0] {
-                Expr::Field(name) => match input {
-                    Value::Object(map) => {
-                        let mut new_map = map.clone();
-                        new_map.remove(name);
-                        Ok(vec![Value::Object(new_map)])
-                    }
-                    _ => Ok(vec![input.clone()]),
-                },
-                Expr::Index(base, idx_expr) => {
-                    let idx = eval_one(idx_expr, input, env)?;
-                    let base_val = eval_one(base, input, env)?;
-                    match (&base_val, &idx) {
-                        (Value::Array(arr), Value::Number(n)) => {
-                            let i = n.as_i64().unwrap_or(0) as usize;
-                            let mut new_arr = arr.clone();
-                            if i < new_arr.len() {
-                                new_arr.remove(i);
-                            }
-                            Ok(vec![Value::Array(new_arr)])
-                        }
-                        (Value::Object(map), Value::String(k)) => {
-                            let mut new_map = map.clone();
-                            new_map.remove(k);
-                            Ok(vec![Value::Object(new_map)])
-                        }
-                        _ => Ok(vec![input.clone()]),
-                    }
-                }
-                Expr::Pipe(_left, _right) => {
-                    // del(.foo.bar) — need proper path deletion
-                    let paths = super::eval::collect_paths_pub(&args[0], input, env)?;
-                    let mut result = input.clone();
-                    for path in paths.iter().rev() {
-                        result = delete_path_segments(&result, path);
-                    }
-                    Ok(vec![result])
-                }
-                _ => Ok(vec![input.clone()]),
-            }
+            let paths = super::eval::collect_paths_pub(&args[0], input, env)?;
+            Ok(vec![delete_paths(input, paths)])
         }
 
         _ => Err(QfError::UndefinedFunction(name.to_string(), args.len())),
@@ -1077,7 +1170,7 @@ pub fn call_builtin(
 }
 
 /// Apply a format string (@base64, @csv, etc.)
-pub fn apply_format(name: &str, input: &Value) -> Result<Vec<Value>, QfError> {
+pub fn apply_format(name: &str, input: &Value, env: &Env) -> Result<Vec<Value>, QfError> {
     match name {
         "base64" => {
             let s = value_to_string(input);
@@ -1096,20 +1189,23 @@ pub fn apply_format(name: &str, input: &Value) -> Result<Vec<Value>, QfError> {
         },
         "uri" => {
             let s = value_to_string(input);
-            let encoded: String = s
-                .chars()
-                .map(|c| {
-                    if c.is_ascii_alphanumeric() || "-_.~".contains(c) {
-                        c.to_string()
-                    } else {
-                        format!("%{:02X}", c as u32)
-                    }
-                })
-                .collect();
+            // Percent-encode each UTF-8 byte, keeping only unreserved characters
+            let mut encoded = String::with_capacity(s.len());
+            for b in s.bytes() {
+                if b.is_ascii_alphanumeric() || b"-_.~".contains(&b) {
+                    encoded.push(b as char);
+                } else {
+                    encoded.push_str(&format!("%{b:02X}"));
+                }
+            }
             Ok(vec![Value::String(encoded)])
         }
-        "csv" => format_as_csv(input, b','),
-        "tsv" => format_as_csv(input, b'\t'),
+        "urid" => match input {
+            Value::String(s) => Ok(vec![Value::String(percent_decode(s)?)]),
+            _ => Err(QfError::TypeError("@urid requires string".into())),
+        },
+        "csv" => format_as_csv(input, b',', "csv"),
+        "tsv" => format_as_csv(input, b'\t', "tsv"),
         "html" => {
             let s = value_to_string(input);
             let escaped = s
@@ -1120,16 +1216,130 @@ pub fn apply_format(name: &str, input: &Value) -> Result<Vec<Value>, QfError> {
                 .replace('"', "&quot;");
             Ok(vec![Value::String(escaped)])
         }
-        "json" => Ok(vec![Value::String(
-            serde_json::to_string(input).unwrap_or_default(),
-        )]),
+        "json" => Ok(vec![Value::String(to_json_string(input, env))]),
         "text" => Ok(vec![Value::String(value_to_string(input))]),
+        // Block-style YAML without the final newline, so it can be embedded
+        // in a string like the other formats
+        "yaml" => {
+            let yaml = crate::output::format_value(input, Format::Yaml, false, false)?;
+            Ok(vec![Value::String(yaml.trim_end_matches('\n').to_string())])
+        }
         _ => Err(QfError::Runtime(format!("unknown format: @{name}"))),
     }
 }
 
 // ── Helpers ────────────────────────────────────────────────
 
+/// Reverse `@uri`: decode `%XX` escapes into bytes, which must form UTF-8.
+fn percent_decode(s: &str) -> Result<String, QfError> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = bytes
+                .get(i + 1..i + 3)
+                .and_then(|h| std::str::from_utf8(h).ok())
+                .and_then(|h| u8::from_str_radix(h, 16).ok())
+                .ok_or_else(|| QfError::Runtime(format!("@urid: invalid escape at offset {i} in {s:?}")))?;
+            out.push(hex);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).map_err(|e| QfError::Runtime(format!("@urid: {e}")))
+}
+
+/// Replace up to `limit` matches (0 for all) with `replacement`, leaving
+/// empty matches alone under the `n` flag.
+fn replace_matches(re: &Regex, s: &str, replacement: &str, limit: usize, flags: &str) -> String {
+    let skip_empty = flags.contains('n');
+    re.replacen(s, limit, |caps: &regex::Captures| {
+        if skip_empty && caps[0].is_empty() {
+            return String::new();
+        }
+        let mut out = String::new();
+        caps.expand(replacement, &mut out);
+        out
+    })
+    .into_owned()
+}
+
+/// A jq match object: offsets and lengths in codepoints, one capture entry
+/// per group (offset -1 and a null string when the group didn't take part).
+fn match_object(re: &Regex, caps: &regex::Captures, s: &str) -> Value {
+    let chars = |byte: usize| Value::from(s[..byte].chars().count());
+    let m = caps.get(0).expect("group 0 always participates");
+    let captures = re
+        .capture_names()
+        .enumerate()
+        .skip(1)
+        .map(|(i, name)| {
+            let name = name.map_or(Value::Null, |n| Value::String(n.to_string()));
+            match caps.get(i) {
+                Some(g) => serde_json::json!({
+                    "offset": chars(g.start()),
+                    "length": g.as_str().chars().count(),
+                    "string": g.as_str(),
+                    "name": name,
+                }),
+                None => serde_json::json!({"offset": -1, "length": 0, "string": null, "name": name}),
+            }
+        })
+        .collect();
+    serde_json::json!({
+        "offset": chars(m.start()),
+        "length": m.as_str().chars().count(),
+        "string": m.as_str(),
+        "captures": Value::Array(captures),
+    })
+}
+
+/// Positions in `arr` where `needle` occurs. An array needle matches as a
+/// contiguous subarray; anything else matches a single element.
+fn array_indices<'a>(arr: &'a [Value], needle: &'a Value) -> impl Iterator<Item = usize> + 'a {
+    let window: &[Value] = match needle {
+        Value::Array(sub) => sub,
+        other => std::slice::from_ref(other),
+    };
+    // Element-wise by value, so `1.0` is found where `1` is
+    (0..arr.len()).filter(move |&i| {
+        !window.is_empty()
+            && arr.len() - i >= window.len()
+            && arr[i..].iter().zip(window).all(|(a, b)| values_equal(a, b))
+    })
+}
+
+/// Serialize for `tojson`/`@json`, honouring the environment's indent setting.
+fn to_json_string(input: &Value, env: &Env) -> String {
+    match env.tojson_indent() {
+        Some(indent) => pretty_json(input, indent),
+        None => serde_json::to_string(input).unwrap_or_default(),
+    }
+}
+
+fn pretty_json(input: &Value, indent: usize) -> String {
+    let indent = b" ".repeat(indent);
+    let formatter = serde_json::ser::PrettyFormatter::with_indent(&indent);
+    let mut buf = Vec::new();
+    let mut ser = serde_json::Serializer::with_formatter(&mut buf, formatter);
+    match serde::Serialize::serialize(input, &mut ser) {
+        Ok(()) => String::from_utf8(buf).unwrap_or_default(),
+        Err(_) => String::new(),
+    }
+}
+
+/// The process environment as an object, or `{}` when `env` is sandboxed.
+pub(crate) fn env_object(env: &Env) -> Value {
+    if env.allow_env() {
+        env.process_env().clone()
+    } else {
+        Value::Object(serde_json::Map::new())
+    }
+}
+
 fn length(input: &Value) -> Result<Value, QfError> {
     match input {
         Value::Null => Ok(Value::Number(0.into())),
@@ -1197,7 +1407,7 @@ fn value_contains(a: &Value, b: &Value) -> bool {
         (Value::Object(a), Value::Object(b)) => {
             b.iter().all(|(k, bv)| a.get(k).is_some_and(|av| value_contains(av, bv)))
         }
-        _ => a == b,
+        _ => values_equal(a, b),
     }
 }
 
@@ -1211,6 +1421,113 @@ fn json_f64(f: f64) -> Value {
     }
 }
 
+/// Parse a number for `tonumber`. Integers that fit `i64`/`u64` keep their
+/// exact value; anything else goes through `f64`, with magnitudes too large
+/// for it clamped to the largest finite one as jq does. A leading `+` is
+/// allowed; spelled-out values such as `Infinity` and `NaN` are not numbers.
+fn parse_number(s: &str) -> Option<Value> {
+    let unsigned = s.strip_prefix('+').unwrap_or(s);
+    if unsigned.starts_with(['+', '-']) && s.starts_with('+') {
+        return None;
+    }
+    if let Ok(i) = unsigned.parse::<i64>() {
+        return Some(Value::Number(i.into()));
+    }
+    if let Ok(u) = unsigned.parse::<u64>() {
+        return Some(Value::Number(u.into()));
+    }
+    if !unsigned.chars().all(|c| c.is_ascii_digit() || matches!(c, '.' | 'e' | 'E' | '+' | '-')) {
+        return None;
+    }
+    let f = unsigned.parse::<f64>().ok()?;
+    Some(json_f64(if f.is_infinite() { f64::MAX.copysign(f) } else { f }))
+}
+
+/// Pair each element with its `*_by` key: every output of `f` collected into
+/// an array, so `sort_by(.a, .b)` compares on `.a` and then `.b`.
+fn keyed_by(arr: &[Value], f: &Expr, env: &Env) -> Result<Vec<(Value, Value)>, QfError> {
+    arr.iter()
+        .map(|item| Ok((Value::Array(eval(f, item, env)?), item.clone())))
+        .collect()
+}
+
+/// `.[]`, the rows of `INDEX(f)`, `GROUP_BY` and `UNIQUE_BY`.
+fn rows(input: &Value, env: &Env) -> Result<Vec<Value>, QfError> {
+    eval(&Expr::Iterate(Box::new(Expr::Identity)), input, env)
+}
+
+/// The keys `row` is filed under: each output of `f`, as `tostring` gives it.
+fn row_keys(f: &Expr, row: &Value, env: &Env) -> Result<Vec<String>, QfError> {
+    Ok(eval(f, row, env)?
+        .into_iter()
+        .map(|key| match key {
+            Value::String(s) => s,
+            other => value_to_string(&other),
+        })
+        .collect())
+}
+
+/// Whether `container` has `key`, with jq's error for any other pairing.
+fn has_key(container: &Value, key: &Value) -> Result<bool, QfError> {
+    match (container, key) {
+        (Value::Object(m), Value::String(k)) => Ok(m.contains_key(k)),
+        (Value::Array(a), Value::Number(n)) => {
+            let i = n.as_f64().unwrap_or(-1.0).floor();
+            Ok(i >= 0.0 && i < a.len() as f64)
+        }
+        _ => Err(QfError::TypeError(format!(
+            "Cannot check whether {} has a {} key",
+            value_type(container),
+            value_type(key)
+        ))),
+    }
+}
+
+fn select_if(input: &Value, keep: bool) -> Vec<Value> {
+    if keep {
+        vec![input.clone()]
+    } else {
+        vec![]
+    }
+}
+
+/// Unbiased binary exponent of `x`, as C's `logb`.
+fn logb(x: f64) -> f64 {
+    if x == 0.0 {
+        f64::NEG_INFINITY
+    } else if !x.is_finite() {
+        x.abs()
+    } else {
+        f64::from(exponent(x))
+    }
+}
+
+/// Mantissa of `x` scaled into `[1, 2)`, as C's `significand`.
+fn significand(x: f64) -> f64 {
+    if x == 0.0 || !x.is_finite() {
+        x
+    } else {
+        // Subnormals are normalized first so the exponent bits can be set
+        let x = if x.abs() < f64::MIN_POSITIVE { x * 2f64.powi(54) } else { x };
+        f64::from_bits((x.to_bits() & !F64_EXPONENT_MASK) | (1023 << 52))
+    }
+}
+
+const F64_EXPONENT_MASK: u64 = 0x7ff << 52;
+
+/// Unbiased exponent of a finite, nonzero `x`, read from its bits. For
+/// subnormals it counts the leading zeros of the mantissa.
+fn exponent(x: f64) -> i32 {
+    let bits = x.to_bits();
+    let biased = ((bits & F64_EXPONENT_MASK) >> 52) as i32;
+    if biased == 0 {
+        let mantissa = bits & ((1 << 52) - 1);
+        -1011 - mantissa.leading_zeros() as i32
+    } else {
+        biased - 1023
+    }
+}
+
 fn num_op(input: &Value, f: fn(f64) -> f64) -> Result<Vec<Value>, QfError> {
     match input {
         Value::Number(n) => {
@@ -1224,36 +1541,61 @@ fn num_op(input: &Value, f: fn(f64) -> f64) -> Result<Vec<Value>, QfError> {
     }
 }
 
+/// Most compiled patterns kept per thread by [`build_regex`].
+const REGEX_CACHE_SIZE: usize = 64;
+
+thread_local! {
+    static REGEX_CACHE: RefCell<HashMap<(String, String), Regex>> = RefCell::new(HashMap::new());
+}
+
+/// Compile `pattern` with jq's `flags`, reusing the regex from an earlier
+/// call with the same arguments, so `map(test("..."))` compiles once.
 fn build_regex(pattern: &str, flags: &str) -> Result<Regex, QfError> {
-    let mut pat = pattern.to_string();
-    if flags.contains('x') {
-        // Extended mode: strip comments and whitespace
-        pat = pat
-            .lines()
-            .map(|l| l.split('#').next().unwrap_or("").trim())
-            .collect::<Vec<_>>()
-            .join("");
+    let key = (pattern.to_string(), flags.to_string());
+    if let Some(re) = REGEX_CACHE.with(|cache| cache.borrow().get(&key).cloned()) {
+        return Ok(re);
     }
-    let case_insensitive = flags.contains('i');
-    let multiline = flags.contains('m');
-    let dotall = flags.contains('s');
+    let re = compile_regex(pattern, flags)?;
+    REGEX_CACHE.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        // A crude bound: queries rarely use more than a handful of patterns
+        if cache.len() >= REGEX_CACHE_SIZE {
+            cache.clear();
+        }
+        cache.insert(key, re.clone());
+    });
+    Ok(re)
+}
 
-    let mut re_str = String::new();
-    if case_insensitive || multiline || dotall {
-        re_str.push_str("(?");
-        if case_insensitive {
-            re_str.push('i');
-        }
-        if multiline {
-            re_str.push('m');
-        }
-        if dotall {
-            re_str.push('s');
+fn compile_regex(pattern: &str, flags: &str) -> Result<Regex, QfError> {
+    // Each inline flag once, as the regex crate rejects repeats like `(?ii)`
+    let mut inline = String::new();
+    let mut set = |flag: char| {
+        if !inline.contains(flag) {
+            inline.push(flag);
+        }
+    };
+    for flag in flags.chars() {
+        match flag {
+            'i' | 'm' | 's' | 'x' => set(flag),
+            // Both single-line and multi-line
+            'p' => "ms".chars().for_each(&mut set),
+            // `g` and `n` are applied by the caller; `l` (longest match)
+            // has no equivalent in the regex crate
+            'g' | 'n' | 'l' => {}
+            _ => {
+                return Err(QfError::Runtime(format!(
+                    "{flags} is not a valid modifier string"
+                )))
+            }
         }
-        re_str.push(')');
     }
-    re_str.push_str(&pat);
 
+    let re_str = if inline.is_empty() {
+        pattern.to_string()
+    } else {
+        format!("(?{inline}){pattern}")
+    };
     Regex::new(&re_str).map_err(|e| QfError::Runtime(format!("invalid regex: {e}")))
 }
 
@@ -1262,25 +1604,91 @@ fn value_to_string(v: &Value) -> String {
         Value::String(s) => s.clone(),
         Value::Null => "null".into(),
         Value::Bool(b) => b.to_string(),
-        Value::Number(n) => n.to_string(),
-        _ => serde_json::to_string(v).unwrap_or_default(),
+        Value::Number(n) => format_number(n),
+        _ => {
+            let mut buf = Vec::new();
+            let mut ser = serde_json::Serializer::with_formatter(&mut buf, JqNumberFormatter);
+            match serde::Serialize::serialize(v, &mut ser) {
+                Ok(()) => String::from_utf8(buf).unwrap_or_default(),
+                Err(_) => String::new(),
+            }
+        }
+    }
+}
+
+/// Render a number the way jq does: integral floats drop the `.0`, and
+/// very large or small magnitudes use exponent notation such as `1e+100`.
+pub fn format_number(n: &serde_json::Number) -> String {
+    match n.as_f64() {
+        Some(f) if n.is_f64() => format_f64(f),
+        _ => n.to_string(),
+    }
+}
+
+fn format_f64(f: f64) -> String {
+    if f == 0.0 {
+        return if f.is_sign_negative() { "-0".into() } else { "0".into() };
+    }
+    // Shortest round-trip digits and exponent, e.g. "1.5e2" -> ("15", 2)
+    let sci = format!("{:e}", f.abs());
+    let (mantissa, exp) = sci.split_once('e').unwrap_or((&sci, "0"));
+    let digits: String = mantissa.chars().filter(|c| *c != '.').collect();
+    let exp: i32 = exp.parse().unwrap_or(0);
+    let ndigits = digits.len() as i32;
+    let decpt = exp + 1;
+    let sign = if f < 0.0 { "-" } else { "" };
+
+    if decpt <= -4 || decpt > ndigits + 15 {
+        let (first, rest) = digits.split_at(1);
+        let frac = if rest.is_empty() { String::new() } else { format!(".{rest}") };
+        let exp_sign = if exp < 0 { '-' } else { '+' };
+        format!("{sign}{first}{frac}e{exp_sign}{:02}", exp.abs())
+    } else if decpt <= 0 {
+        format!("{sign}0.{}{digits}", "0".repeat((-decpt) as usize))
+    } else if decpt >= ndigits {
+        format!("{sign}{digits}{}", "0".repeat((decpt - ndigits) as usize))
+    } else {
+        let (int, frac) = digits.split_at(decpt as usize);
+        format!("{sign}{int}.{frac}")
+    }
+}
+
+/// Compact JSON formatter that writes floats with [`format_number`] rules.
+struct JqNumberFormatter;
+
+impl serde_json::ser::Formatter for JqNumberFormatter {
+    fn write_f64<W>(&mut self, writer: &mut W, value: f64) -> std::io::Result<()>
+    where
+        W: ?Sized + std::io::Write,
+    {
+        writer.write_all(format_f64(value).as_bytes())
     }
 }
 
-fn format_as_csv(input: &Value, delimiter: u8) -> Result<Vec<Value>, QfError> {
+/// A row for `@csv`/`@tsv`: null becomes an empty field and numbers are
+/// written as jq prints them; arrays and objects are rejected, as in jq.
+fn format_as_csv(input: &Value, delimiter: u8, name: &str) -> Result<Vec<Value>, QfError> {
     match input {
         Value::Array(arr) => {
-            let mut wtr = csv::WriterBuilder::new()
-                .delimiter(delimiter)
-                .from_writer(vec![]);
-            let fields: Vec<String> = arr
+            let fields = arr
                 .iter()
                 .map(|v| match v {
-                    Value::String(s) => s.clone(),
-                    Value::Null => String::new(),
-                    v => v.to_string(),
+                    Value::Null => Ok(String::new()),
+                    Value::Array(_) | Value::Object(_) => Err(QfError::TypeError(format!(
+                        "{} ({}) is not valid in a {name} row",
+                        value_type(v),
+                        value_to_string(v)
+                    ))),
+                    v => Ok(value_to_string(v)),
                 })
-                .collect();
+                .collect::<Result<Vec<String>, QfError>>()?;
+            if delimiter == b'\t' {
+                let fields: Vec<String> = fields.iter().map(|f| escape_tsv(f)).collect();
+                return Ok(vec![Value::String(fields.join("\t"))]);
+            }
+            let mut wtr = csv::WriterBuilder::new()
+                .delimiter(delimiter)
+                .from_writer(vec![]);
             wtr.write_record(&fields)
                 .map_err(|e| QfError::Runtime(e.to_string()))?;
             let bytes = wtr
@@ -1288,16 +1696,54 @@ fn format_as_csv(input: &Value, delimiter: u8) -> Result<Vec<Value>, QfError> {
                 .map_err(|e| QfError::Runtime(e.to_string()))?;
             let s = String::from_utf8(bytes)
                 .map_err(|e| QfError::Runtime(e.to_string()))?;
-            Ok(vec![Value::String(s.trim_end().to_string())])
+            // Only the record terminator: trailing empty fields stay
+            Ok(vec![Value::String(s.trim_end_matches(['\r', '\n']).to_string())])
         }
         _ => Err(QfError::TypeError("@csv/@tsv requires array".into())),
     }
 }
 
+/// A `@tsv` field as jq writes it: no quoting, with backslashes, tabs and
+/// line breaks escaped.
+fn escape_tsv(field: &str) -> String {
+    let mut out = String::with_capacity(field.len());
+    for c in field.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\t' => out.push_str("\\t"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
 fn compare_values(a: &Value, b: &Value) -> std::cmp::Ordering {
     super::eval::compare_values_pub(a, b)
 }
 
+fn values_equal(a: &Value, b: &Value) -> bool {
+    super::eval::values_equal_pub(a, b)
+}
+
+/// Whether `cond` yields a value whose truthiness is `target` for any output
+/// of `generator`, pulling outputs lazily and stopping at the first hit.
+fn short_circuit(
+    generator: &Expr,
+    cond: &Expr,
+    target: bool,
+    input: &Value,
+    env: &Env,
+) -> Result<bool, QfError> {
+    let mut found = false;
+    eval_each(generator, input, env, &mut |v| {
+        found = eval(cond, &v, env)?.iter().any(|c| is_truthy(c) == target);
+        Ok(!found)
+    })?;
+    Ok(found)
+}
+
 fn recurse_all(val: &Value, results: &mut Vec<Value>) {
     results.push(val.clone());
     match val {
@@ -1368,6 +1814,33 @@ fn collect_all_paths_filtered(
     Ok(())
 }
 
+/// Paths below `val` (excluding `val` itself) whose values satisfy `keep`,
+/// in the same order as `paths`.
+fn collect_typed_paths(
+    val: &Value,
+    current: &mut Vec<Value>,
+    result: &mut Vec<Value>,
+    keep: fn(&Value) -> bool,
+) {
+    let children: Box<dyn Iterator<Item = (Value, &Value)>> = match val {
+        Value::Array(arr) => Box::new(
+            arr.iter()
+                .enumerate()
+                .map(|(i, v)| (Value::Number(i.into()), v)),
+        ),
+        Value::Object(map) => Box::new(map.iter().map(|(k, v)| (Value::String(k.clone()), v))),
+        _ => return,
+    };
+    for (seg, child) in children {
+        current.push(seg);
+        if keep(child) {
+            result.push(Value::Array(current.clone()));
+        }
+        collect_typed_paths(child, current, result, keep);
+        current.pop();
+    }
+}
+
 fn collect_leaf_paths(val: &Value, current: &mut Vec<Value>, result: &mut Vec<Value>) {
     match val {
         Value::Array(arr) => {
@@ -1390,44 +1863,147 @@ fn collect_leaf_paths(val: &Value, current: &mut Vec<Value>, result: &mut Vec<Va
     }
 }
 
-fn delete_path(val: &Value, path: &[Value]) -> Value {
-    if path.is_empty() {
-        return Value::Null;
+/// The events `tostream` emits: `[path, leaf]` for every scalar or empty
+/// collection, and `[path]` after the last child of each collection.
+fn collect_stream_events(val: &Value, current: &mut Vec<Value>, result: &mut Vec<Value>) {
+    let last = match val {
+        Value::Array(arr) if !arr.is_empty() => {
+            for (i, item) in arr.iter().enumerate() {
+                current.push(Value::Number(i.into()));
+                collect_stream_events(item, current, result);
+                current.pop();
+            }
+            Value::Number((arr.len() - 1).into())
+        }
+        Value::Object(map) if !map.is_empty() => {
+            for (k, v) in map {
+                current.push(Value::String(k.clone()));
+                collect_stream_events(v, current, result);
+                current.pop();
+            }
+            Value::String(map.keys().next_back().cloned().unwrap_or_default())
+        }
+        _ => {
+            result.push(Value::Array(vec![Value::Array(current.clone()), val.clone()]));
+            return;
+        }
+    };
+    let mut closing = current.clone();
+    closing.push(last);
+    result.push(Value::Array(vec![Value::Array(closing)]));
+}
+
+/// Convert a path array into segments, skipping anything that is neither a
+/// key nor an index.
+pub(crate) fn path_segments(path: &[Value]) -> Vec<super::eval::PathSegment> {
+    path.iter()
+        .filter_map(|v| match v {
+            Value::String(s) => Some(super::eval::PathSegment::Key(s.clone())),
+            Value::Number(n) => Some(super::eval::PathSegment::Index(n.as_i64().unwrap_or(0))),
+            // `{"start": s, "end": e}`, as `path(.[s:e])` reports a slice
+            Value::Object(m) => Some(super::eval::PathSegment::Slice(
+                m.get("start").and_then(Value::as_i64).unwrap_or(0),
+                m.get("end").and_then(Value::as_i64),
+            )),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Rewrite slice segments in `path` as plain indices into `input`: a slice
+/// followed by an index or another slice is folded into it, and a trailing
+/// slice becomes one path per element it covers.
+fn expand_slices(
+    input: &Value,
+    mut path: Vec<super::eval::PathSegment>,
+) -> Vec<Vec<super::eval::PathSegment>> {
+    use super::eval::PathSegment;
+    let Some(pos) = path.iter().position(|seg| matches!(seg, PathSegment::Slice(..))) else {
+        return vec![path];
+    };
+    let PathSegment::Slice(from, to) = path[pos] else { unreachable!() };
+    let Value::Array(arr) = super::eval::get_path_pub(input, &path[..pos]) else {
+        return Vec::new();
+    };
+    let (start, end) = super::eval::resolve_slice(arr.len(), from, to);
+    let (start, len) = (start as i64, (end - start) as i64);
+    match path.get(pos + 1).cloned() {
+        None => (start..start + len)
+            .flat_map(|i| {
+                let mut expanded = path.clone();
+                expanded[pos] = PathSegment::Index(i);
+                expand_slices(input, expanded)
+            })
+            .collect(),
+        Some(PathSegment::Index(i)) => {
+            let i = if i < 0 { len + i } else { i };
+            if !(0..len).contains(&i) {
+                return Vec::new();
+            }
+            path.splice(pos..pos + 2, [PathSegment::Index(start + i)]);
+            expand_slices(input, path)
+        }
+        Some(PathSegment::Slice(inner_from, inner_to)) => {
+            let (s, e) = super::eval::resolve_slice(len as usize, inner_from, inner_to);
+            let folded = PathSegment::Slice(start + s as i64, Some(start + e as i64));
+            path.splice(pos..pos + 2, [folded]);
+            expand_slices(input, path)
+        }
+        // An array slice has no keys
+        Some(PathSegment::Key(_)) => Vec::new(),
     }
-    let seg = &path[0];
-    let rest = &path[1..];
-    match (val, seg) {
-        (Value::Object(map), Value::String(key)) => {
-            if rest.is_empty() {
-                let mut new_map = map.clone();
-                new_map.remove(key);
-                Value::Object(new_map)
-            } else {
-                let mut new_map = map.clone();
-                if let Some(child) = map.get(key) {
-                    new_map.insert(key.clone(), delete_path(child, rest));
+}
+
+/// Count negative indices in `path` from the end of the array they index,
+/// so `-1` and the last element's own index name the same path.
+fn resolve_negative_indices(
+    input: &Value,
+    mut path: Vec<super::eval::PathSegment>,
+) -> Vec<super::eval::PathSegment> {
+    use super::eval::PathSegment;
+    for pos in 0..path.len() {
+        if let PathSegment::Index(i) = path[pos] {
+            if i < 0 {
+                if let Value::Array(arr) = super::eval::get_path_pub(input, &path[..pos]) {
+                    path[pos] = PathSegment::Index(arr.len() as i64 + i);
                 }
-                Value::Object(new_map)
             }
         }
-        (Value::Array(arr), Value::Number(n)) => {
-            let idx = n.as_i64().unwrap_or(0) as usize;
-            if rest.is_empty() {
-                let mut new_arr = arr.clone();
-                if idx < new_arr.len() {
-                    new_arr.remove(idx);
-                }
-                Value::Array(new_arr)
-            } else {
-                let mut new_arr = arr.clone();
-                if let Some(child) = arr.get(idx) {
-                    new_arr[idx] = delete_path(child, rest);
+    }
+    path
+}
+
+/// Delete every path from `input`, highest array indices first so earlier
+/// deletions don't shift the elements later paths refer to.
+fn delete_paths(input: &Value, paths: Vec<Vec<super::eval::PathSegment>>) -> Value {
+    use super::eval::PathSegment;
+    let mut paths: Vec<_> = paths
+        .into_iter()
+        .flat_map(|p| expand_slices(input, p))
+        .map(|p| resolve_negative_indices(input, p))
+        .collect();
+    paths.sort_by(|a, b| {
+        for (x, y) in a.iter().zip(b) {
+            let ord = match (x, y) {
+                (PathSegment::Index(i), PathSegment::Index(j)) => i.cmp(j),
+                (PathSegment::Key(k), PathSegment::Key(l)) => k.cmp(l),
+                (PathSegment::Index(_), PathSegment::Key(_)) => std::cmp::Ordering::Less,
+                (PathSegment::Key(_), PathSegment::Index(_)) => std::cmp::Ordering::Greater,
+                // Slices were expanded to indices above
+                (PathSegment::Slice(..), _) | (_, PathSegment::Slice(..)) => {
+                    std::cmp::Ordering::Equal
                 }
-                Value::Array(new_arr)
+            };
+            if ord.is_ne() {
+                return ord.reverse();
             }
         }
-        _ => val.clone(),
-    }
+        b.len().cmp(&a.len())
+    });
+    paths.dedup();
+    paths
+        .iter()
+        .fold(input.clone(), |acc, path| delete_path_segments(&acc, path))
 }
 
 fn delete_path_segments(val: &Value, path: &[super::eval::PathSegment]) -> Value {
@@ -1477,24 +2053,30 @@ fn delete_path_segments(val: &Value, path: &[super::eval::PathSegment]) -> Value
 
 fn builtin_names() -> Vec<String> {
     vec![
-        "length", "utf8bytelength", "keys", "keys_unsorted", "values", "has", "in", "type",
+        "length", "utf8bytelength", "keys", "keys_unsorted", "values", "has", "in", "IN", "type",
+        "INDEX", "GROUP_BY", "UNIQUE_BY",
+        "nulls", "booleans", "numbers", "strings", "arrays", "objects", "iterables", "scalars",
         "infinite", "nan", "isinfinite", "isnan", "isnormal", "builtins",
-        "select", "empty", "error", "debug",
-        "map", "map_values", "to_entries", "from_entries", "with_entries", "transpose",
+        "select", "empty", "error", "debug", "stderr",
+        "map", "map_values", "to_entries", "from_entries", "with_entries", "transpose", "tabulate",
         "add", "any", "all", "flatten", "range",
         "sort", "sort_by", "group_by", "unique", "unique_by", "reverse",
         "min", "max", "min_by", "max_by",
         "contains", "inside", "indices", "index", "rindex",
-        "tostring", "tonumber", "ascii_downcase", "ascii_upcase",
+        "tostring", "tonumber", "ascii_downcase", "ascii_upcase", "downcase", "upcase",
         "ltrimstr", "rtrimstr", "trim", "split", "join",
         "startswith", "endswith", "ascii", "explode", "implode",
         "test", "match", "capture", "scan", "sub", "gsub",
         "first", "last", "nth", "limit", "recurse", "until", "while", "repeat",
         "floor", "ceil", "round", "fabs", "sqrt", "log", "log2", "log10",
         "exp", "exp2", "pow", "sin", "cos", "tan", "asin", "acos", "atan", "atan2",
-        "tojson", "fromjson",
-        "path", "paths", "leaf_paths", "getpath", "setpath", "delpaths",
+        "sinh", "cosh", "tanh", "asinh", "acosh", "atanh", "cbrt", "trunc",
+        "expm1", "log1p", "hypot", "copysign", "logb", "significand",
+        "tojson", "tojsonpretty", "fromjson",
+        "path", "paths", "leaf_paths", "getpath", "setpath", "delpaths", "tostream", "fromstream",
+        "scalar_paths", "array_paths", "object_paths",
         "env", "not", "null", "true", "false", "input", "inputs", "del",
+        "input_filename", "input_line_number",
     ].into_iter().map(String::from).collect()
 }
 
@@ -1517,24 +2099,133 @@ mod tests {
         assert_eq!(result, json!(["a", "b"]));
     }
 
+    #[test]
+    fn test_build_regex_flags() {
+        assert!(build_regex("abc", "i").unwrap().is_match("ABC"));
+        assert!(build_regex("^b$", "m").unwrap().is_match("a\nb"));
+        assert!(build_regex("a.b", "s").unwrap().is_match("a\nb"));
+        assert!(build_regex("^a.b$", "p").unwrap().is_match("x\na\nb"));
+        assert!(build_regex("a b # comment", "x").unwrap().is_match("ab"));
+        for flag in ["g", "n", "l", "gnl", ""] {
+            assert!(build_regex("a", flag).unwrap().is_match("a"), "flag {flag}");
+        }
+        let err = build_regex("a", "gq").unwrap_err();
+        assert!(err.to_string().contains("gq is not a valid modifier string"));
+    }
+
+    #[test]
+    fn test_regex_cache() {
+        let first = build_regex("ca+che", "i").unwrap();
+        let again = build_regex("ca+che", "i").unwrap();
+        assert_eq!(first.as_str(), again.as_str());
+        assert!(REGEX_CACHE.with(|c| c.borrow().contains_key(&("ca+che".into(), "i".into()))));
+        // Flags are part of the key
+        assert!(!build_regex("ca+che", "").unwrap().is_match("CAACHE"));
+        assert!(build_regex("ca+che", "i").unwrap().is_match("CAACHE"));
+        // Errors are not cached, and the cache stays bounded
+        assert!(build_regex("(", "").is_err());
+        assert!(build_regex("(", "").is_err());
+        for i in 0..REGEX_CACHE_SIZE * 2 {
+            build_regex(&format!("x{i}"), "").unwrap();
+        }
+        assert!(REGEX_CACHE.with(|c| c.borrow().len()) <= REGEX_CACHE_SIZE);
+        // Results over many inputs are unchanged
+        let input = json!(["a1", "b", "c22"]);
+        let out = crate::query::query(&input, r#"map(test("\\d+")), [.[] | sub("\\d"; "_")]"#);
+        assert_eq!(out.unwrap(), vec![json!([true, false, true]), json!(["a_", "b", "c_2"])]);
+    }
+
+    #[test]
+    fn test_parse_number() {
+        assert_eq!(parse_number("42"), Some(json!(42)));
+        assert!(parse_number("42").unwrap().is_i64());
+        assert_eq!(parse_number("+1.5"), Some(json!(1.5)));
+        assert_eq!(parse_number("-7"), Some(json!(-7)));
+        assert_eq!(parse_number("1234567890123456789"), Some(json!(1234567890123456789_i64)));
+        assert_eq!(parse_number("18446744073709551615"), Some(json!(u64::MAX)));
+        assert_eq!(parse_number("1e1000"), Some(json!(f64::MAX)));
+        assert_eq!(parse_number("-1e1000"), Some(json!(-f64::MAX)));
+        for bad in ["", "+", "+-1", "++1", "abc", "1x", "Infinity", "-inf", "NaN"] {
+            assert_eq!(parse_number(bad), None, "{bad:?}");
+        }
+    }
+
     #[test]
     fn test_format_base64() {
-        let result = apply_format("base64", &json!("hello")).unwrap();
+        let result = apply_format("base64", &json!("hello"), &Env::new()).unwrap();
         assert_eq!(result, vec![json!("aGVsbG8=")]);
     }
 
     #[test]
     fn test_format_base64d() {
-        let result = apply_format("base64d", &json!("aGVsbG8=")).unwrap();
+        let result = apply_format("base64d", &json!("aGVsbG8="), &Env::new()).unwrap();
         assert_eq!(result, vec![json!("hello")]);
     }
 
+    #[test]
+    fn test_format_uri_utf8() {
+        let env = Env::new();
+        let result = apply_format("uri", &json!("café au lait"), &env).unwrap();
+        assert_eq!(result, vec![json!("caf%C3%A9%20au%20lait")]);
+        let result = apply_format("urid", &json!("caf%C3%A9%20au%20lait"), &env).unwrap();
+        assert_eq!(result, vec![json!("café au lait")]);
+        assert!(apply_format("urid", &json!("%E9"), &env).is_err());
+        assert!(apply_format("urid", &json!("100%"), &env).is_err());
+    }
+
     #[test]
     fn test_format_html() {
-        let result = apply_format("html", &json!("<b>test</b>")).unwrap();
+        let result = apply_format("html", &json!("<b>test</b>"), &Env::new()).unwrap();
         assert_eq!(result, vec![json!("&lt;b&gt;test&lt;/b&gt;")]);
     }
 
+    #[test]
+    fn test_format_json_indent() {
+        let mut env = Env::new();
+        assert_eq!(
+            apply_format("json", &json!({"a": [1]}), &env).unwrap(),
+            vec![json!(r#"{"a":[1]}"#)]
+        );
+        env.set_tojson_indent(Some(2));
+        assert_eq!(
+            apply_format("json", &json!({"a": [1]}), &env).unwrap(),
+            vec![json!("{\n  \"a\": [\n    1\n  ]\n}")]
+        );
+    }
+
+    #[test]
+    fn test_tojsonpretty() {
+        let mut env = Env::new();
+        let val = json!({"a": [1, "x"]});
+        let run = |name: &str, env: &Env| call_builtin(name, &[], &val, env).unwrap();
+        assert_eq!(run("tojson", &env), vec![json!(r#"{"a":[1,"x"]}"#)]);
+        assert_eq!(
+            run("tojsonpretty", &env),
+            vec![json!("{\n  \"a\": [\n    1,\n    \"x\"\n  ]\n}")]
+        );
+        env.set_tojson_indent(Some(4));
+        assert_eq!(
+            run("tojsonpretty", &env),
+            vec![json!("{\n    \"a\": [\n        1,\n        \"x\"\n    ]\n}")]
+        );
+    }
+
+    #[test]
+    fn test_format_number() {
+        let f = |x: f64| format_number(&serde_json::Number::from_f64(x).unwrap());
+        assert_eq!(f(1.0), "1");
+        assert_eq!(f(1.5), "1.5");
+        assert_eq!(f(-2.25), "-2.25");
+        assert_eq!(f(1e100), "1e+100");
+        assert_eq!(f(1.5e300), "1.5e+300");
+        assert_eq!(f(0.0001), "0.0001");
+        assert_eq!(f(0.00001), "1e-05");
+        assert_eq!(f(1e15), "1000000000000000");
+        assert_eq!(f(1e17), "1e+17");
+        assert_eq!(f(0.1 + 0.2), "0.30000000000000004");
+        assert_eq!(format_number(&serde_json::Number::from(42)), "42");
+    }
+
     #[test]
     fn test_contains() {
         assert!(value_contains(&json!("foobar"), &json!("foo")));
@@ -1,9 +1,17 @@
 use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock};
 
 use serde_json::Value;
 
+use crate::error::QfError;
+
 use super::ast::Expr;
 
+/// Default for [`Env::max_depth`]: deep enough for ordinary recursive
+/// definitions, shallow enough to fail cleanly on runaway ones.
+pub const DEFAULT_MAX_DEPTH: usize = 1000;
+
 #[derive(Debug, Clone)]
 pub struct FuncDef {
     pub params: Vec<String>,
@@ -14,13 +22,61 @@ pub struct FuncDef {
 pub struct Env {
     variables: HashMap<String, Value>,
     functions: HashMap<(String, usize), FuncDef>,
+    /// Indent width for `tojson`/`@json`; `None` keeps them compact.
+    tojson_indent: Option<usize>,
+    /// Evaluate `map(f)` elements concurrently on the rayon pool.
+    parallel_map: bool,
+    /// Whether `env`/`$ENV` may read the process environment,
+    /// `input`/`inputs` may consume input and `include`/`import` may read
+    /// module files.
+    allow_env: bool,
+    /// Directories searched for modules; empty means the current directory.
+    library_path: Arc<Vec<PathBuf>>,
+    /// Most values any single expression may produce; `None` is unbounded.
+    max_outputs: Option<usize>,
+    /// Deepest nesting of user function calls; `None` is unbounded.
+    max_depth: Option<usize>,
+    /// Current user-function call depth.
+    depth: usize,
+    /// File the current input came from, for `input_filename`.
+    input_filename: Option<String>,
+    /// Line of the current input record, for `input_line_number`; 0 when
+    /// the input isn't read line by line.
+    input_line_number: usize,
+    /// `$ENV`, read from the process on first use and shared by clones.
+    process_env: Arc<OnceLock<Value>>,
 }
 
-impl Env {
-    pub fn new() -> Self {
+impl Default for Env {
+    fn default() -> Self {
         Env {
             variables: HashMap::new(),
             functions: HashMap::new(),
+            tojson_indent: None,
+            parallel_map: false,
+            allow_env: true,
+            library_path: Arc::default(),
+            max_outputs: None,
+            max_depth: Some(DEFAULT_MAX_DEPTH),
+            depth: 0,
+            input_filename: None,
+            input_line_number: 0,
+            process_env: Arc::default(),
+        }
+    }
+}
+
+impl Env {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// An environment for untrusted queries: `env`/`$ENV` are empty and
+    /// `input`/`inputs` produce nothing.
+    pub fn sandboxed() -> Self {
+        Env {
+            allow_env: false,
+            ..Self::default()
         }
     }
 
@@ -40,6 +96,105 @@ impl Env {
         self.functions.insert((name, arity), def);
     }
 
+    pub fn tojson_indent(&self) -> Option<usize> {
+        self.tojson_indent
+    }
+
+    pub fn set_tojson_indent(&mut self, indent: Option<usize>) {
+        self.tojson_indent = indent;
+    }
+
+    pub fn parallel_map(&self) -> bool {
+        self.parallel_map
+    }
+
+    pub fn set_parallel_map(&mut self, enabled: bool) {
+        self.parallel_map = enabled;
+    }
+
+    pub fn allow_env(&self) -> bool {
+        self.allow_env
+    }
+
+    pub fn set_allow_env(&mut self, allowed: bool) {
+        self.allow_env = allowed;
+    }
+
+    pub fn library_path(&self) -> &[PathBuf] {
+        &self.library_path
+    }
+
+    pub fn set_library_path(&mut self, dirs: Vec<PathBuf>) {
+        self.library_path = Arc::new(dirs);
+    }
+
+    pub fn max_outputs(&self) -> Option<usize> {
+        self.max_outputs
+    }
+
+    pub fn set_max_outputs(&mut self, limit: Option<usize>) {
+        self.max_outputs = limit;
+    }
+
+    pub fn max_depth(&self) -> Option<usize> {
+        self.max_depth
+    }
+
+    pub fn set_max_depth(&mut self, limit: Option<usize>) {
+        self.max_depth = limit;
+    }
+
+    pub fn input_filename(&self) -> Option<&str> {
+        self.input_filename.as_deref()
+    }
+
+    pub fn set_input_filename(&mut self, name: Option<String>) {
+        self.input_filename = name;
+    }
+
+    pub fn input_line_number(&self) -> usize {
+        self.input_line_number
+    }
+
+    pub fn set_input_line_number(&mut self, line: usize) {
+        self.input_line_number = line;
+    }
+
+    /// The process environment as an object. It is read once, so every
+    /// `$ENV`/`env` in a query (and in clones of this `Env`) sees the same
+    /// snapshot.
+    pub fn process_env(&self) -> &Value {
+        self.process_env.get_or_init(|| {
+            Value::Object(std::env::vars().map(|(k, v)| (k, Value::String(v))).collect())
+        })
+    }
+
+    /// Fail once an expression has produced more than `max_outputs` values.
+    pub fn check_outputs(&self, count: usize) -> Result<(), QfError> {
+        match self.max_outputs {
+            Some(max) if count > max => Err(QfError::Runtime(format!(
+                "output limit of {max} exceeded"
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Fail once `depth` goes beyond `max_depth`.
+    pub fn check_depth(&self, depth: usize) -> Result<(), QfError> {
+        match self.max_depth {
+            Some(max) if depth > max => Err(QfError::Runtime(format!(
+                "recursion depth limit of {max} exceeded"
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Record entry into a user-defined function body.
+    pub fn descend(&mut self) -> Result<(), QfError> {
+        self.depth += 1;
+        self.check_depth(self.depth)
+    }
+
     pub fn child(&self) -> Self {
         self.clone()
     }
@@ -7,7 +7,15 @@ use super::builtins;
 use super::env::{Env, FuncDef};
 
 /// Evaluate a jq expression against an input value, producing zero or more outputs.
+///
+/// Fails if the outputs exceed the environment's `max_outputs` limit.
 pub fn eval(expr: &Expr, input: &Value, env: &Env) -> Result<Vec<Value>, QfError> {
+    let results = eval_inner(expr, input, env)?;
+    env.check_outputs(results.len())?;
+    Ok(results)
+}
+
+fn eval_inner(expr: &Expr, input: &Value, env: &Env) -> Result<Vec<Value>, QfError> {
     match expr {
         Expr::Identity => Ok(vec![input.clone()]),
 
@@ -23,11 +31,7 @@ pub fn eval(expr: &Expr, input: &Value, env: &Env) -> Result<Vec<Value>, QfError
                 .cloned()
                 .unwrap_or(Value::Null)]),
             Value::Null => Ok(vec![Value::Null]),
-            _ => Err(QfError::TypeError(format!(
-                "cannot index {} with string \"{}\"",
-                value_type(input),
-                name
-            ))),
+            _ => Err(index_error(input, &Value::String(name.clone()))),
         },
 
         Expr::OptionalField(name) => match input {
@@ -44,7 +48,8 @@ pub fn eval(expr: &Expr, input: &Value, env: &Env) -> Result<Vec<Value>, QfError
             for val in &vals {
                 let indices = eval(idx_expr, input, env)?;
                 for idx in &indices {
-                    results.push(index_value(val, idx)?);
+                    let v = index_value(val, idx).map_err(|e| index_error_at(e, expr, val, idx))?;
+                    results.push(v);
                 }
             }
             Ok(results)
@@ -56,16 +61,24 @@ pub fn eval(expr: &Expr, input: &Value, env: &Env) -> Result<Vec<Value>, QfError
             for val in &vals {
                 let indices = eval(idx_expr, input, env)?;
                 for idx in &indices {
-                    match index_value(val, idx) {
-                        Ok(v) => results.push(v),
-                        Err(_) => {}
+                    if let Ok(v) = index_value(val, idx) {
+                        results.push(v);
                     }
                 }
             }
             Ok(results)
         }
 
-        Expr::Slice(expr, from, to) => {
+        Expr::Slice(expr, from, to, Some(step)) => {
+            let bounds = slice_args(from, to, step, input, env)?;
+            let mut results = Vec::new();
+            for val in &eval(expr, input, env)? {
+                results.push(step_slice_value(val, bounds)?);
+            }
+            Ok(results)
+        }
+
+        Expr::Slice(expr, from, to, None) => {
             let vals = eval(expr, input, env)?;
             let mut results = Vec::new();
             for val in &vals {
@@ -88,14 +101,24 @@ pub fn eval(expr: &Expr, input: &Value, env: &Env) -> Result<Vec<Value>, QfError
             Ok(results)
         }
 
-        Expr::Iterate(expr) => {
-            let vals = eval(expr, input, env)?;
+        Expr::Iterate(inner) | Expr::OptionalIterate(inner) => {
+            // Borrow the collections when we can, so each item is cloned
+            // once rather than along with its whole container
+            let owned;
+            let vals = match navigate(inner, input) {
+                Some(vals) => vals,
+                None => {
+                    owned = eval(inner, input, env)?;
+                    owned.iter().collect()
+                }
+            };
             let mut results = Vec::new();
-            for val in &vals {
+            for val in vals {
                 match val {
                     Value::Array(arr) => results.extend(arr.iter().cloned()),
                     Value::Object(map) => results.extend(map.values().cloned()),
                     Value::Null => {}
+                    _ if matches!(expr, Expr::OptionalIterate(_)) => {}
                     _ => {
                         return Err(QfError::TypeError(format!(
                             "cannot iterate over {}",
@@ -107,24 +130,19 @@ pub fn eval(expr: &Expr, input: &Value, env: &Env) -> Result<Vec<Value>, QfError
             Ok(results)
         }
 
-        Expr::OptionalIterate(expr) => {
-            let vals = eval(expr, input, env)?;
-            let mut results = Vec::new();
-            for val in &vals {
-                match val {
-                    Value::Array(arr) => results.extend(arr.iter().cloned()),
-                    Value::Object(map) => results.extend(map.values().cloned()),
-                    _ => {}
+        Expr::Pipe(left, right) => {
+            if let Some(vals) = navigate(left, input) {
+                env.check_outputs(vals.len())?;
+                let mut results = Vec::new();
+                for val in vals {
+                    results.extend(eval(right, val, env).map_err(|e| locate(e, left, right, val))?);
                 }
+                return Ok(results);
             }
-            Ok(results)
-        }
-
-        Expr::Pipe(left, right) => {
             let left_results = eval(left, input, env)?;
             let mut results = Vec::new();
             for val in &left_results {
-                results.extend(eval(right, val, env)?);
+                results.extend(eval(right, val, env).map_err(|e| locate(e, left, right, val))?);
             }
             Ok(results)
         }
@@ -164,6 +182,23 @@ pub fn eval(expr: &Expr, input: &Value, env: &Env) -> Result<Vec<Value>, QfError
             Ok(results)
         }
 
+        Expr::BinOp(op @ (BinOp::And | BinOp::Or), left, right) => {
+            // The right side only runs for left outputs that don't decide
+            // the result on their own
+            let decisive = matches!(op, BinOp::Or);
+            let mut results = Vec::new();
+            for lv in eval(left, input, env)? {
+                if is_truthy(&lv) == decisive {
+                    results.push(Value::Bool(decisive));
+                    continue;
+                }
+                for rv in eval(right, input, env)? {
+                    results.push(Value::Bool(is_truthy(&rv)));
+                }
+            }
+            Ok(results)
+        }
+
         Expr::BinOp(op, left, right) => {
             let left_vals = eval(left, input, env)?;
             let mut results = Vec::new();
@@ -185,7 +220,9 @@ pub fn eval(expr: &Expr, input: &Value, env: &Env) -> Result<Vec<Value>, QfError
         }
 
         Expr::Alternative(left, right) => {
-            let vals = eval(left, input, env)?;
+            // An error on the left ends it like `?`, and counts towards the
+            // fallback like null and false
+            let (vals, _) = eval_until_error(left, input, env);
             let non_null: Vec<_> = vals
                 .into_iter()
                 .filter(|v| !v.is_null() && v != &Value::Bool(false))
@@ -197,17 +234,15 @@ pub fn eval(expr: &Expr, input: &Value, env: &Env) -> Result<Vec<Value>, QfError
             }
         }
 
-        Expr::Try(expr, catch) => match eval(expr, input, env) {
-            Ok(vals) => Ok(vals),
-            Err(e) => {
-                if let Some(catch_expr) = catch {
-                    let err_val = Value::String(e.to_string());
-                    eval(catch_expr, &err_val, env)
-                } else {
-                    Ok(vec![])
-                }
+        Expr::Try(expr, catch) => {
+            // Outputs produced before the error are kept, as in jq
+            let (mut results, outcome) = eval_until_error(expr, input, env);
+            if let (Err(e), Some(catch_expr)) = (outcome, catch) {
+                let err_val = Value::String(e.to_string());
+                results.extend(eval(catch_expr, &err_val, env).map_err(|e| relocate(e, None))?);
             }
-        },
+            Ok(results)
+        }
 
         Expr::ArrayConstruct(inner) => {
             let vals = eval(inner, input, env)?;
@@ -224,28 +259,11 @@ pub fn eval(expr: &Expr, input: &Value, env: &Env) -> Result<Vec<Value>, QfError
             elif_branches,
             else_branch,
         } => {
-            let cond_vals = eval(cond, input, env)?;
             let mut results = Vec::new();
-            for cv in &cond_vals {
-                if is_truthy(cv) {
-                    results.extend(eval(then_branch, input, env)?);
-                } else {
-                    let mut handled = false;
-                    for (elif_cond, elif_body) in elif_branches {
-                        let elif_vals = eval(elif_cond, input, env)?;
-                        if elif_vals.iter().any(|v| is_truthy(v)) {
-                            results.extend(eval(elif_body, input, env)?);
-                            handled = true;
-                            break;
-                        }
-                    }
-                    if !handled {
-                        if let Some(else_br) = else_branch {
-                            results.extend(eval(else_br, input, env)?);
-                        } else {
-                            results.push(input.clone());
-                        }
-                    }
+            for cv in &eval(cond, input, env)? {
+                match if_branch(cv, then_branch, elif_branches, else_branch, input, env)? {
+                    Some(branch) => results.extend(eval(branch, input, env)?),
+                    None => results.push(input.clone()),
                 }
             }
             Ok(results)
@@ -259,6 +277,10 @@ pub fn eval(expr: &Expr, input: &Value, env: &Env) -> Result<Vec<Value>, QfError
             let vals = eval(expr, input, env)?;
             let mut results = Vec::new();
             for val in &vals {
+                if let Pattern::Alternative(alternatives) = pattern {
+                    eval_alternatives(alternatives, val, body, input, env, &mut results)?;
+                    continue;
+                }
                 let mut child_env = env.child();
                 bind_pattern(&mut child_env, pattern, val)?;
                 results.extend(eval(body, input, &child_env)?);
@@ -277,7 +299,8 @@ pub fn eval(expr: &Expr, input: &Value, env: &Env) -> Result<Vec<Value>, QfError
             for item in &items {
                 let mut child_env = env.child();
                 bind_pattern(&mut child_env, pattern, item)?;
-                acc = eval_one(update, &acc, &child_env)?;
+                // Errors are relative to the accumulator, not the input
+                acc = eval_one(update, &acc, &child_env).map_err(|e| relocate(e, None))?;
             }
             Ok(vec![acc])
         }
@@ -295,9 +318,9 @@ pub fn eval(expr: &Expr, input: &Value, env: &Env) -> Result<Vec<Value>, QfError
             for item in &items {
                 let mut child_env = env.child();
                 bind_pattern(&mut child_env, pattern, item)?;
-                acc = eval_one(update, &acc, &child_env)?;
+                acc = eval_one(update, &acc, &child_env).map_err(|e| relocate(e, None))?;
                 if let Some(ext) = extract {
-                    results.extend(eval(ext, &acc, &child_env)?);
+                    results.extend(eval(ext, &acc, &child_env).map_err(|e| relocate(e, None))?);
                 } else {
                     results.push(acc.clone());
                 }
@@ -306,12 +329,19 @@ pub fn eval(expr: &Expr, input: &Value, env: &Env) -> Result<Vec<Value>, QfError
         }
 
         Expr::Label(name, body) => {
-            match eval(body, input, env) {
-                Ok(v) => Ok(v),
+            // Generated lazily, so a break stops generators such as
+            // `recurse(f)` and keeps what came before it
+            let mut results = Vec::new();
+            let outcome = eval_each(body, input, env, &mut |val| {
+                results.push(val);
+                Ok(true)
+            });
+            match outcome {
+                Ok(_) => Ok(results),
                 Err(QfError::UserError(msg)) if msg.starts_with("__break__") => {
                     let break_name = msg.trim_start_matches("__break__");
                     if break_name == name {
-                        Ok(vec![])
+                        Ok(results)
                     } else {
                         Err(QfError::UserError(msg))
                     }
@@ -345,6 +375,7 @@ pub fn eval(expr: &Expr, input: &Value, env: &Env) -> Result<Vec<Value>, QfError
             if let Some(func) = env.get_func(name, args.len()) {
                 let func = func.clone();
                 let mut child_env = env.child();
+                child_env.descend()?;
                 for (param, arg) in func.params.iter().zip(args.iter()) {
                     // In jq, function args are filters, not values.
                     // For simplicity, we evaluate the arg and bind the result.
@@ -355,8 +386,9 @@ pub fn eval(expr: &Expr, input: &Value, env: &Env) -> Result<Vec<Value>, QfError
                 return eval(&func.body, input, &child_env);
             }
 
-            // Built-in functions
-            builtins::call_builtin(name, args, input, env)
+            // Built-in functions; those that run `f` on other values than
+            // `.` (`map(f)`, `recurse(f)`, ...) can't locate its errors
+            builtins::call_builtin(name, args, input, env).map_err(|e| relocate(e, None))
         }
 
         Expr::VarRef(name) => match env.get_var(name) {
@@ -364,11 +396,7 @@ pub fn eval(expr: &Expr, input: &Value, env: &Env) -> Result<Vec<Value>, QfError
             None => {
                 // Special env vars
                 if name == "ENV" {
-                    let mut map = serde_json::Map::new();
-                    for (k, v) in std::env::vars() {
-                        map.insert(k, Value::String(v));
-                    }
-                    return Ok(vec![Value::Object(map)]);
+                    return Ok(vec![builtins::env_object(env)]);
                 }
                 if name == "__loc__" {
                     return Ok(vec![Value::Null]);
@@ -399,15 +427,27 @@ pub fn eval(expr: &Expr, input: &Value, env: &Env) -> Result<Vec<Value>, QfError
             eval_assign(path_expr, val_expr, input, env, AssignMode::Alt)
         }
 
-        Expr::Format(name) => builtins::apply_format(name, input),
+        Expr::Format(name) => builtins::apply_format(name, input, env),
 
-        Expr::Optional(expr) => match eval(expr, input, env) {
-            Ok(v) => Ok(v),
-            Err(_) => Ok(vec![]),
-        },
+        Expr::Optional(expr) => Ok(eval_until_error(expr, input, env).0),
     }
 }
 
+/// Collect the outputs of `expr` one at a time, stopping at the first
+/// error: the outputs before it, and the error if there was one.
+fn eval_until_error(
+    expr: &Expr,
+    input: &Value,
+    env: &Env,
+) -> (Vec<Value>, Result<(), QfError>) {
+    let mut results = Vec::new();
+    let outcome = eval_each(expr, input, env, &mut |val| {
+        results.push(val);
+        Ok(true)
+    });
+    (results, outcome.map(|_| ()))
+}
+
 /// Evaluate an expression expecting exactly one output.
 pub fn eval_one(expr: &Expr, input: &Value, env: &Env) -> Result<Value, QfError> {
     let mut vals = eval(expr, input, env)?;
@@ -418,6 +458,240 @@ pub fn eval_one(expr: &Expr, input: &Value, env: &Env) -> Result<Value, QfError>
     }
 }
 
+/// Evaluate `expr`, handing each output to `emit` as soon as it is produced.
+///
+/// `emit` returns `Ok(false)` to stop early, in which case this returns
+/// `Ok(false)` too. Generators that can be driven incrementally (`,`, `|`,
+/// `.[]`, `range`, `repeat`, `limit`, `first`) are never fully materialized,
+/// so consumers such as `first(g)` or `nth(n; g)` only do the work they need.
+/// Everything else falls back to [`eval`].
+pub fn eval_each(
+    expr: &Expr,
+    input: &Value,
+    env: &Env,
+    emit: &mut dyn FnMut(Value) -> Result<bool, QfError>,
+) -> Result<bool, QfError> {
+    match expr {
+        Expr::Comma(left, right) => {
+            if !eval_each(left, input, env, emit)? {
+                return Ok(false);
+            }
+            eval_each(right, input, env, emit)
+        }
+
+        Expr::Pipe(left, right) => {
+            if let Some(vals) = navigate(left, input) {
+                for val in vals {
+                    if !pipe_each(left, right, val, env, emit)? {
+                        return Ok(false);
+                    }
+                }
+                return Ok(true);
+            }
+            eval_each(left, input, env, &mut |val| pipe_each(left, right, &val, env, emit))
+        }
+
+        Expr::Iterate(inner) => eval_each(inner, input, env, &mut |val| {
+            match val {
+                Value::Array(arr) => {
+                    for item in arr {
+                        if !emit(item)? {
+                            return Ok(false);
+                        }
+                    }
+                }
+                Value::Object(map) => {
+                    for (_, item) in map {
+                        if !emit(item)? {
+                            return Ok(false);
+                        }
+                    }
+                }
+                Value::Null => {}
+                other => {
+                    return Err(QfError::TypeError(format!(
+                        "cannot iterate over {}",
+                        value_type(&other)
+                    )))
+                }
+            }
+            Ok(true)
+        }),
+
+        Expr::If {
+            cond,
+            then_branch,
+            elif_branches,
+            else_branch,
+        } => {
+            for cv in eval(cond, input, env)? {
+                let branch = if_branch(&cv, then_branch, elif_branches, else_branch, input, env)?;
+                let more = match branch {
+                    Some(branch) => eval_each(branch, input, env, emit)?,
+                    None => emit(input.clone())?,
+                };
+                if !more {
+                    return Ok(false);
+                }
+            }
+            Ok(true)
+        }
+
+        Expr::FuncCall(name, args) if env.get_func(name, args.len()).is_none() => {
+            match (name.as_str(), args.len()) {
+                ("range", 1 | 2) => {
+                    let bound = |e: &Expr| -> Result<i64, QfError> {
+                        Ok(eval_one(e, input, env)?.as_f64().unwrap_or(0.0) as i64)
+                    };
+                    let (start, end) = if args.len() == 1 {
+                        (0, bound(&args[0])?)
+                    } else {
+                        (bound(&args[0])?, bound(&args[1])?)
+                    };
+                    for (count, i) in (start..end).enumerate() {
+                        env.check_outputs(count + 1)?;
+                        if !emit(Value::Number(i.into()))? {
+                            return Ok(false);
+                        }
+                    }
+                    Ok(true)
+                }
+                ("repeat", 1) => {
+                    let mut val = input.clone();
+                    for count in 1.. {
+                        env.check_outputs(count)?;
+                        if !emit(val.clone())? {
+                            return Ok(false);
+                        }
+                        val = eval_one(&args[0], &val, env)?;
+                    }
+                    Ok(true)
+                }
+                ("limit", 2) => {
+                    let n = eval_one(&args[0], input, env)?.as_i64().unwrap_or(0);
+                    if n <= 0 {
+                        return Ok(true);
+                    }
+                    let mut taken = 0;
+                    let mut wants_more = true;
+                    eval_each(&args[1], input, env, &mut |val| {
+                        taken += 1;
+                        wants_more = emit(val)?;
+                        Ok(wants_more && taken < n)
+                    })?;
+                    Ok(wants_more)
+                }
+                ("recurse", 1 | 2) => recurse_each(&args[0], args.get(1), input, env, emit)
+                    .map_err(|e| relocate(e, None)),
+                ("first", 1) => {
+                    let mut wants_more = true;
+                    eval_each(&args[0], input, env, &mut |val| {
+                        wants_more = emit(val)?;
+                        Ok(false)
+                    })?;
+                    Ok(wants_more)
+                }
+                _ => emit_all(expr, input, env, emit),
+            }
+        }
+
+        _ => emit_all(expr, input, env, emit),
+    }
+}
+
+/// The branch of an `if` taken when its condition gives `cond`; `None` for
+/// a missing `else`, which passes the input through.
+fn if_branch<'e>(
+    cond: &Value,
+    then_branch: &'e Expr,
+    elif_branches: &'e [(Expr, Expr)],
+    else_branch: &'e Option<Box<Expr>>,
+    input: &Value,
+    env: &Env,
+) -> Result<Option<&'e Expr>, QfError> {
+    if is_truthy(cond) {
+        return Ok(Some(then_branch));
+    }
+    for (elif_cond, elif_body) in elif_branches {
+        if eval(elif_cond, input, env)?.iter().any(is_truthy) {
+            return Ok(Some(elif_body));
+        }
+    }
+    Ok(else_branch.as_deref())
+}
+
+/// The right side of `left | right` for one output `val` of the left side.
+/// Only errors raised by `right` itself are located; those `emit` returns
+/// come from further down the pipeline and already are.
+fn pipe_each(
+    left: &Expr,
+    right: &Expr,
+    val: &Value,
+    env: &Env,
+    emit: &mut dyn FnMut(Value) -> Result<bool, QfError>,
+) -> Result<bool, QfError> {
+    let mut downstream = false;
+    let outcome = eval_each(right, val, env, &mut |v| emit(v).inspect_err(|_| downstream = true));
+    outcome.map_err(|e| if downstream { e } else { locate(e, left, right, val) })
+}
+
+/// `recurse(f)` and `recurse(f; cond)`: `input`, then depth first each
+/// output of `f` (those `cond` accepts) and what `f` gives for it in turn.
+/// Values are emitted as they are found, so `limit`, `first` and `break`
+/// can stop an unbounded recursion.
+pub fn recurse_each(
+    f: &Expr,
+    cond: Option<&Expr>,
+    input: &Value,
+    env: &Env,
+    emit: &mut dyn FnMut(Value) -> Result<bool, QfError>,
+) -> Result<bool, QfError> {
+    let children = |val: &Value| -> Result<Vec<Value>, QfError> {
+        let mut children = Vec::new();
+        for child in eval(f, val, env)? {
+            let keep = match cond {
+                Some(cond) => eval(cond, &child, env)?.iter().filter(|c| is_truthy(c)).count(),
+                None => 1,
+            };
+            children.extend(std::iter::repeat_n(child, keep));
+        }
+        Ok(children)
+    };
+    if !emit(input.clone())? {
+        return Ok(false);
+    }
+    let mut count = 1;
+    // Explicit stack instead of recursion, so deep chains can't overflow
+    let mut stack = vec![children(input)?.into_iter()];
+    while let Some(pending) = stack.last_mut() {
+        let Some(val) = pending.next() else {
+            stack.pop();
+            continue;
+        };
+        count += 1;
+        env.check_outputs(count)?;
+        if !emit(val.clone())? {
+            return Ok(false);
+        }
+        stack.push(children(&val)?.into_iter());
+    }
+    Ok(true)
+}
+
+fn emit_all(
+    expr: &Expr,
+    input: &Value,
+    env: &Env,
+    emit: &mut dyn FnMut(Value) -> Result<bool, QfError>,
+) -> Result<bool, QfError> {
+    for val in eval(expr, input, env)? {
+        if !emit(val)? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
 // ── Helpers ────────────────────────────────────────────────────
 
 pub fn value_type(v: &Value) -> &'static str {
@@ -441,76 +715,195 @@ fn json_f64(f: f64) -> Value {
         .unwrap_or(Value::Null)
 }
 
+/// Borrow the values a read-only path such as `.a.b[]` selects from `input`.
+///
+/// `None` when `expr` does anything but navigate, or when navigating would
+/// fail or drop values (e.g. `.a` on a number); the caller then evaluates it
+/// the ordinary way, cloning as it goes.
+fn navigate<'v>(expr: &Expr, input: &'v Value) -> Option<Vec<&'v Value>> {
+    static NULL: Value = Value::Null;
+    match expr {
+        Expr::Identity => Some(vec![input]),
+        Expr::Field(name) | Expr::OptionalField(name) => match input {
+            Value::Object(map) => Some(vec![map.get(name).unwrap_or(&NULL)]),
+            Value::Null => Some(vec![&NULL]),
+            _ => None,
+        },
+        Expr::Iterate(inner) => {
+            let mut out = Vec::new();
+            for val in navigate(inner, input)? {
+                match val {
+                    Value::Array(arr) => out.extend(arr),
+                    Value::Object(map) => out.extend(map.values()),
+                    Value::Null => {}
+                    _ => return None,
+                }
+            }
+            Some(out)
+        }
+        Expr::Pipe(left, right) => {
+            let mut out = Vec::new();
+            for val in navigate(left, input)? {
+                out.extend(navigate(right, val)?);
+            }
+            Some(out)
+        }
+        _ => None,
+    }
+}
+
 fn index_value(val: &Value, idx: &Value) -> Result<Value, QfError> {
     match (val, idx) {
-        (Value::Array(arr), Value::Number(n)) => {
-            let i = n.as_i64().unwrap_or(0);
-            let i = if i < 0 {
-                (arr.len() as i64 + i) as usize
-            } else {
-                i as usize
-            };
-            Ok(arr.get(i).cloned().unwrap_or(Value::Null))
-        }
+        (Value::Array(arr), Value::Number(n)) => Ok(resolve_index(arr.len(), n)
+            .and_then(|i| arr.get(i).cloned())
+            .unwrap_or(Value::Null)),
         (Value::Object(map), Value::String(key)) => {
             Ok(map.get(key).cloned().unwrap_or(Value::Null))
         }
         (Value::Null, _) => Ok(Value::Null),
-        _ => Err(QfError::TypeError(format!(
-            "cannot index {} with {}",
-            value_type(val),
-            value_type(idx)
-        ))),
+        _ => Err(index_error(val, idx)),
     }
 }
 
-fn slice_value(val: &Value, from: isize, to: Option<isize>) -> Result<Value, QfError> {
-    match val {
-        Value::Array(arr) => {
-            let len = arr.len() as isize;
-            let start = if from < 0 {
-                (len + from).max(0) as usize
-            } else {
-                from.min(len) as usize
+/// jq's error for an index that does not apply to `val`: string keys are
+/// quoted (`Cannot index array with "foo"`), other indices give their type
+/// (`Cannot index object with number`).
+fn index_error(val: &Value, idx: &Value) -> QfError {
+    QfError::TypeError(format!("Cannot index {} with {}", value_type(val), index_name(idx)))
+}
+
+fn index_name(idx: &Value) -> String {
+    match idx {
+        Value::String(_) => idx.to_string(),
+        _ => value_type(idx).to_string(),
+    }
+}
+
+/// Add the path to an error from `left | right` when `right` is a field
+/// lookup that failed on `val`: `.a.b.c` on `{"a":{"b":"x"}}` reports
+/// `Cannot index string at .a.b with "c"`.
+fn locate(err: QfError, left: &Expr, right: &Expr, val: &Value) -> QfError {
+    match right {
+        Expr::Field(name) => index_error_at(err, left, val, &Value::String(name.clone())),
+        _ => relocate(err, static_path(left)),
+    }
+}
+
+/// Move the location of an indexing error raised on some value to the input
+/// `prefix` leads to that value from, or drop it when there is no such fixed
+/// path, since a location is only useful relative to the query's input.
+fn relocate(err: QfError, prefix: Option<String>) -> QfError {
+    let QfError::TypeError(msg) = &err else {
+        return err;
+    };
+    let Some((head, path, tail)) = split_location(msg) else {
+        return err;
+    };
+    QfError::TypeError(match prefix {
+        Some(prefix) => {
+            // A leading index is written `.[0]`, but `.a[0]` after a prefix
+            let path = match path.strip_prefix('.') {
+                Some(index) if index.starts_with('[') && !prefix.is_empty() => index,
+                _ => path,
             };
-            let end = match to {
-                Some(t) => {
-                    if t < 0 {
-                        (len + t).max(0) as usize
-                    } else {
-                        t.min(len) as usize
-                    }
+            let path = format!("{prefix}{path}");
+            let path = if path.starts_with('[') { format!(".{path}") } else { path };
+            format!("{head} at {path}{tail}")
+        }
+        None => format!("{head}{tail}"),
+    })
+}
+
+/// Split `Cannot index T at PATH with K`, as [`index_error_at`] writes it,
+/// into the text before ` at `, the path, and the text from ` with ` on.
+fn split_location(msg: &str) -> Option<(&str, &str, &str)> {
+    let rest = msg.strip_prefix("Cannot index ")?;
+    let path_start = msg.len() - rest.len() + rest.find(' ')?;
+    let after = msg[path_start..].strip_prefix(" at ")?;
+    // Each segment is `.name`, `.` or `[json]`, as `static_path` writes them
+    let mut path = after;
+    loop {
+        let len = if let Some(inner) = path.strip_prefix('[') {
+            let mut values = serde_json::Deserializer::from_str(inner).into_iter::<Value>();
+            match values.next() {
+                Some(Ok(_)) if inner[values.byte_offset()..].starts_with(']') => {
+                    values.byte_offset() + 2
                 }
-                None => len as usize,
-            };
-            if start >= end {
-                Ok(Value::Array(vec![]))
+                _ => return None,
+            }
+        } else if let Some(name) = path.strip_prefix('.') {
+            1 + name.find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(name.len())
+        } else {
+            break;
+        };
+        path = &path[len..];
+    }
+    let tail_start = msg.len() - path.len();
+    path.starts_with(" with ")
+        .then(|| (&msg[..path_start], &msg[path_start + 4..tail_start], &msg[tail_start..]))
+}
+
+/// Rebuild an indexing error with the location of `val`, if `base` (the
+/// expression that produced it) is a fixed path.
+fn index_error_at(err: QfError, base: &Expr, val: &Value, idx: &Value) -> QfError {
+    if !matches!(err, QfError::TypeError(_)) {
+        return err;
+    }
+    let Some(path) = static_path(base).filter(|p| !p.is_empty()) else {
+        return err;
+    };
+    let path = if path.starts_with('[') { format!(".{path}") } else { path };
+    QfError::TypeError(format!(
+        "Cannot index {} at {path} with {}",
+        value_type(val),
+        index_name(idx)
+    ))
+}
+
+/// `expr` written back as a path (`.a.b[0]`) if it is made only of field
+/// lookups and literal indices; the identity is the empty string.
+fn static_path(expr: &Expr) -> Option<String> {
+    match expr {
+        Expr::Identity => Some(String::new()),
+        Expr::Field(name) => {
+            let plain = name.chars().next().is_some_and(|c| c.is_alphabetic() || c == '_')
+                && name.chars().all(|c| c.is_alphanumeric() || c == '_');
+            if plain {
+                Some(format!(".{name}"))
             } else {
-                Ok(Value::Array(arr[start..end].to_vec()))
+                Some(format!("[{}]", Value::String(name.clone())))
             }
         }
-        Value::String(s) => {
-            let len = s.len() as isize;
-            let start = if from < 0 {
-                (len + from).max(0) as usize
-            } else {
-                from.min(len) as usize
+        Expr::Pipe(left, right) => Some(static_path(left)? + &static_path(right)?),
+        Expr::Index(base, idx) => {
+            let idx = match idx.as_ref() {
+                Expr::Literal(v @ (Value::Number(_) | Value::String(_))) => v.to_string(),
+                _ => return None,
             };
-            let end = match to {
-                Some(t) => {
-                    if t < 0 {
-                        (len + t).max(0) as usize
-                    } else {
-                        t.min(len) as usize
-                    }
-                }
-                None => len as usize,
-            };
-            if start >= end {
-                Ok(Value::String(String::new()))
-            } else {
-                Ok(Value::String(s[start..end].to_string()))
-            }
+            Some(format!("{}[{idx}]", static_path(base)?))
+        }
+        _ => None,
+    }
+}
+
+/// Resolve an array index, counting negative indices back from `len` and
+/// flooring fractional ones. `None` if it lands before the start.
+pub fn resolve_index(len: usize, n: &serde_json::Number) -> Option<usize> {
+    let i = n.as_i64().or_else(|| n.as_f64().map(|f| f.floor() as i64))?;
+    let i = if i < 0 { len as i64 + i } else { i };
+    usize::try_from(i).ok()
+}
+
+fn slice_value(val: &Value, from: isize, to: Option<isize>) -> Result<Value, QfError> {
+    match val {
+        Value::Array(arr) => {
+            let (start, end) = slice_bounds(arr.len(), from, to);
+            Ok(Value::Array(arr[start..end].to_vec()))
+        }
+        Value::String(s) => {
+            // Bounds count characters, not bytes
+            let (start, end) = slice_bounds(s.chars().count(), from, to);
+            Ok(Value::String(s.chars().skip(start).take(end - start).collect()))
         }
         _ => Err(QfError::TypeError(format!(
             "cannot slice {}",
@@ -519,25 +912,134 @@ fn slice_value(val: &Value, from: isize, to: Option<isize>) -> Result<Value, QfE
     }
 }
 
+/// Resolve `[from:to]` against a length, counting negative bounds from the
+/// end and clamping to `0..=len`. The result always has `start <= end`.
+pub fn slice_bounds(len: usize, from: isize, to: Option<isize>) -> (usize, usize) {
+    let len = len as isize;
+    let clamp = |i: isize| if i < 0 { (len + i).max(0) } else { i.min(len) } as usize;
+    let start = clamp(from);
+    let end = to.map_or(len as usize, clamp);
+    (start, end.max(start))
+}
+
+/// The `from`, `to` and `step` of a stepped slice, evaluated against `input`.
+fn slice_args(
+    from: &Option<Box<Expr>>,
+    to: &Option<Box<Expr>>,
+    step: &Expr,
+    input: &Value,
+    env: &Env,
+) -> Result<(Option<i64>, Option<i64>, i64), QfError> {
+    let bound = |e: &Option<Box<Expr>>| -> Result<Option<i64>, QfError> {
+        match e {
+            Some(e) => Ok(Some(eval_one(e, input, env)?.as_i64().unwrap_or(0))),
+            None => Ok(None),
+        }
+    };
+    let step = eval_one(step, input, env)?;
+    let step = match step.as_i64() {
+        Some(0) => return Err(QfError::Runtime("slice step cannot be zero".into())),
+        Some(step) => step,
+        None => {
+            return Err(QfError::TypeError(format!(
+                "slice step must be an integer, got {}",
+                serde_json::to_string(&step).unwrap_or_default()
+            )))
+        }
+    };
+    Ok((bound(from)?, bound(to)?, step))
+}
+
+/// `.[from:to:step]` of an array or string.
+fn step_slice_value(
+    val: &Value,
+    (from, to, step): (Option<i64>, Option<i64>, i64),
+) -> Result<Value, QfError> {
+    match val {
+        Value::Array(arr) => {
+            let picked = step_indices(arr.len(), from, to, step).map(|i| arr[i].clone());
+            Ok(Value::Array(picked.collect()))
+        }
+        Value::String(s) => {
+            let chars: Vec<char> = s.chars().collect();
+            let picked = step_indices(chars.len(), from, to, step).map(|i| chars[i]);
+            Ok(Value::String(picked.collect()))
+        }
+        Value::Null => Ok(Value::Null),
+        _ => Err(QfError::TypeError(format!("cannot slice {}", value_type(val)))),
+    }
+}
+
+/// The indices `[from:to:step]` selects from `len` items, as in Python: a
+/// negative step walks backwards, starting from the end by default.
+fn step_indices(
+    len: usize,
+    from: Option<i64>,
+    to: Option<i64>,
+    step: i64,
+) -> impl Iterator<Item = usize> {
+    let len = len as i64;
+    let resolve = |i: i64| if i < 0 { i + len } else { i };
+    let (start, end) = if step > 0 {
+        let clamp = |i: i64| resolve(i).clamp(0, len);
+        (from.map_or(0, clamp), to.map_or(len, clamp))
+    } else {
+        // -1 stands for "before the first item"
+        let clamp = |i: i64| resolve(i).clamp(-1, len - 1);
+        (from.map_or(len - 1, clamp), to.map_or(-1, clamp))
+    };
+    let span = if step > 0 { end - start } else { start - end };
+    // `span` is at most `len + 1`, so only the step can be out of range
+    let count = if span > 0 {
+        (span as u64 - 1) / step.unsigned_abs() + 1
+    } else {
+        0
+    };
+    (0..count as i64).map(move |k| (start + k * step) as usize)
+}
+
+/// [`slice_bounds`] for the bounds stored in a [`PathSegment::Slice`].
+pub fn resolve_slice(len: usize, from: i64, to: Option<i64>) -> (usize, usize) {
+    slice_bounds(len, from as isize, to.map(|t| t as isize))
+}
+
 fn eval_binop(op: &BinOp, left: &Value, right: &Value) -> Result<Value, QfError> {
     match op {
         BinOp::Add => add_values(left, right),
-        BinOp::Sub => arith_op(left, right, |a, b| a - b),
+        BinOp::Sub => {
+            if let Some(r) = int_op(left, right, i64::checked_sub) {
+                return Ok(r);
+            }
+            arith_op(left, right, |a, b| a - b)
+        }
         BinOp::Mul => mul_values(left, right),
-        BinOp::Div => arith_op(left, right, |a, b| {
-            if b == 0.0 {
-                f64::NAN
-            } else {
-                a / b
+        BinOp::Div => {
+            // Exact integer quotients stay integers; anything else is a float
+            let exact =
+                |a: i64, b: i64| a.checked_rem(b).filter(|r| *r == 0).and(a.checked_div(b));
+            if let Some(r) = int_op(left, right, exact) {
+                return Ok(r);
             }
-        }),
-        BinOp::Mod => arith_op(left, right, |a, b| {
-            if b == 0.0 {
-                f64::NAN
-            } else {
-                a % b
+            arith_op(left, right, |a, b| {
+                if b == 0.0 {
+                    f64::NAN
+                } else {
+                    a / b
+                }
+            })
+        }
+        BinOp::Mod => {
+            if let Some(r) = int_op(left, right, i64::checked_rem) {
+                return Ok(r);
             }
-        }),
+            arith_op(left, right, |a, b| {
+                if b == 0.0 {
+                    f64::NAN
+                } else {
+                    a % b
+                }
+            })
+        }
         BinOp::Eq => Ok(Value::Bool(values_equal(left, right))),
         BinOp::Ne => Ok(Value::Bool(!values_equal(left, right))),
         BinOp::Lt => Ok(Value::Bool(compare_values(left, right) == std::cmp::Ordering::Less)),
@@ -644,6 +1146,18 @@ fn mul_values(left: &Value, right: &Value) -> Result<Value, QfError> {
     }
 }
 
+/// Apply `f` to two integer operands, or `None` if either operand is not an
+/// `i64` or the result does not fit.
+fn int_op(left: &Value, right: &Value, f: fn(i64, i64) -> Option<i64>) -> Option<Value> {
+    match (left, right) {
+        (Value::Number(a), Value::Number(b)) => f(a.as_i64()?, b.as_i64()?).map(Value::from),
+        _ => None,
+    }
+}
+
+/// Largest integer that an `f64` cannot have rounded to (2^53 - 1).
+const MAX_SAFE_INTEGER: f64 = 9_007_199_254_740_991.0;
+
 fn arith_op(
     left: &Value,
     right: &Value,
@@ -654,11 +1168,14 @@ fn arith_op(
             let af = a.as_f64().unwrap_or(0.0);
             let bf = b.as_f64().unwrap_or(0.0);
             let result = f(af, bf);
-            // Keep integer if both were integers and result fits
-            if a.is_i64() && b.is_i64() && result.fract() == 0.0 {
-                if result >= i64::MIN as f64 && result <= i64::MAX as f64 {
-                    return Ok(Value::Number((result as i64).into()));
-                }
+            // Keep integer if both were integers and the float math was
+            // exact; beyond 2^53 operands or result may have been rounded
+            if a.is_i64()
+                && b.is_i64()
+                && [af, bf, result].iter().all(|x| x.abs() <= MAX_SAFE_INTEGER)
+                && result.fract() == 0.0
+            {
+                return Ok(Value::Number((result as i64).into()));
             }
             Ok(json_f64(result))
         }
@@ -670,8 +1187,26 @@ fn arith_op(
     }
 }
 
+/// jq equality: numbers compare by value, so `3 == 3.0` and `-0 == 0`,
+/// while NaN equals nothing, not even itself.
 fn values_equal(a: &Value, b: &Value) -> bool {
-    a == b
+    match (a, b) {
+        (Value::Number(x), Value::Number(y)) => {
+            !is_nan(x) && !is_nan(y) && compare_numbers(x, y).is_eq()
+        }
+        (Value::Array(x), Value::Array(y)) => {
+            x.len() == y.len() && x.iter().zip(y).all(|(x, y)| values_equal(x, y))
+        }
+        (Value::Object(x), Value::Object(y)) => {
+            x.len() == y.len()
+                && x.iter().all(|(k, v)| y.get(k).is_some_and(|w| values_equal(v, w)))
+        }
+        _ => a == b,
+    }
+}
+
+pub fn values_equal_pub(a: &Value, b: &Value) -> bool {
+    values_equal(a, b)
 }
 
 pub fn compare_values_pub(a: &Value, b: &Value) -> std::cmp::Ordering {
@@ -682,6 +1217,10 @@ pub fn set_path_pub(val: &Value, path: &[PathSegment], new_val: Value) -> Result
     set_path(val, path, new_val)
 }
 
+pub fn get_path_pub(val: &Value, path: &[PathSegment]) -> Value {
+    get_path(val, path)
+}
+
 pub fn collect_paths_pub(
     expr: &Expr,
     input: &Value,
@@ -713,11 +1252,7 @@ fn compare_values(a: &Value, b: &Value) -> std::cmp::Ordering {
     match (a, b) {
         (Value::Null, Value::Null) => std::cmp::Ordering::Equal,
         (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
-        (Value::Number(a), Value::Number(b)) => {
-            let af = a.as_f64().unwrap_or(0.0);
-            let bf = b.as_f64().unwrap_or(0.0);
-            af.partial_cmp(&bf).unwrap_or(std::cmp::Ordering::Equal)
-        }
+        (Value::Number(a), Value::Number(b)) => compare_numbers(a, b),
         (Value::String(a), Value::String(b)) => a.cmp(b),
         (Value::Array(a), Value::Array(b)) => {
             for (x, y) in a.iter().zip(b.iter()) {
@@ -728,11 +1263,45 @@ fn compare_values(a: &Value, b: &Value) -> std::cmp::Ordering {
             }
             a.len().cmp(&b.len())
         }
-        (Value::Object(_), Value::Object(_)) => std::cmp::Ordering::Equal,
+        // Objects order by their sorted keys first, then by the values
+        // under those keys
+        (Value::Object(a), Value::Object(b)) => a
+            .keys()
+            .cmp(b.keys())
+            .then_with(|| {
+                a.values()
+                    .zip(b.values())
+                    .map(|(x, y)| compare_values(x, y))
+                    .find(|c| c.is_ne())
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            }),
         _ => std::cmp::Ordering::Equal,
     }
 }
 
+/// A total order on numbers: integers compare exactly, `-0` and `0` are
+/// equal, and NaN sorts below every other number, as in jq.
+fn compare_numbers(a: &serde_json::Number, b: &serde_json::Number) -> std::cmp::Ordering {
+    if let (Some(x), Some(y)) = (a.as_i64(), b.as_i64()) {
+        return x.cmp(&y);
+    }
+    if let (Some(x), Some(y)) = (a.as_u64(), b.as_u64()) {
+        return x.cmp(&y);
+    }
+    let x = a.as_f64().unwrap_or(f64::NAN);
+    let y = b.as_f64().unwrap_or(f64::NAN);
+    match (x.is_nan(), y.is_nan()) {
+        (true, true) => std::cmp::Ordering::Equal,
+        (true, false) => std::cmp::Ordering::Less,
+        (false, true) => std::cmp::Ordering::Greater,
+        (false, false) => x.partial_cmp(&y).unwrap_or(std::cmp::Ordering::Equal),
+    }
+}
+
+fn is_nan(n: &serde_json::Number) -> bool {
+    n.as_f64().is_some_and(f64::is_nan)
+}
+
 fn recurse_all(val: &Value, results: &mut Vec<Value>) {
     results.push(val.clone());
     match val {
@@ -766,7 +1335,7 @@ fn eval_object_construct(
                     let key_str = match key {
                         ObjectKey::Ident(s) | ObjectKey::String(s) => s.clone(),
                         ObjectKey::Format(name) => {
-                            let vals = builtins::apply_format(name, input)?;
+                            let vals = builtins::apply_format(name, input, env)?;
                             vals.into_iter()
                                 .next()
                                 .and_then(|v| v.as_str().map(String::from))
@@ -821,7 +1390,7 @@ fn eval_object_construct(
                     next.push(new_obj);
                 }
                 ObjectEntry::ShorthandFormat(name) => {
-                    let vals = builtins::apply_format(name, input)?;
+                    let vals = builtins::apply_format(name, input, env)?;
                     for v in &vals {
                         let mut new_obj = obj.clone();
                         new_obj.insert(name.clone(), v.clone());
@@ -868,6 +1437,81 @@ fn bind_pattern(env: &mut Env, pattern: &Pattern, value: &Value) -> Result<(), Q
                 value_type(value)
             ))),
         },
+        Pattern::Alternative(alternatives) => {
+            // Variables the matching alternative leaves out are null
+            let mut names = Vec::new();
+            pattern_variables(pattern, &mut names);
+            let mut result = Ok(());
+            for alternative in alternatives {
+                for name in &names {
+                    env.set_var(name.clone(), Value::Null);
+                }
+                result = bind_pattern(env, alternative, value);
+                if result.is_ok() {
+                    break;
+                }
+            }
+            result
+        }
+    }
+}
+
+/// `body` under each of the `?//` `alternatives` in turn until one binds
+/// `value` and runs to the end without error. Outputs produced before an
+/// error are kept; the last alternative's error is the one reported.
+fn eval_alternatives(
+    alternatives: &[Pattern],
+    value: &Value,
+    body: &Expr,
+    input: &Value,
+    env: &Env,
+    results: &mut Vec<Value>,
+) -> Result<(), QfError> {
+    let mut names = Vec::new();
+    for alternative in alternatives {
+        pattern_variables(alternative, &mut names);
+    }
+    for (i, alternative) in alternatives.iter().enumerate() {
+        // Variables the matching alternative leaves out are null
+        let mut child_env = env.child();
+        for name in &names {
+            child_env.set_var(name.clone(), Value::Null);
+        }
+        let outcome = bind_pattern(&mut child_env, alternative, value).and_then(|()| {
+            eval_each(body, input, &child_env, &mut |val| {
+                results.push(val);
+                Ok(true)
+            })
+        });
+        match outcome {
+            Err(QfError::UserError(msg)) if msg.starts_with("__break__") => {
+                return Err(QfError::UserError(msg))
+            }
+            Err(_) if i + 1 < alternatives.len() => {}
+            outcome => return outcome.map(drop),
+        }
+    }
+    Ok(())
+}
+
+/// Every variable name `pattern` binds, in order of appearance.
+fn pattern_variables(pattern: &Pattern, names: &mut Vec<String>) {
+    match pattern {
+        Pattern::Variable(name) => {
+            if !names.contains(name) {
+                names.push(name.clone());
+            }
+        }
+        Pattern::Array(patterns) | Pattern::Alternative(patterns) => {
+            for pat in patterns {
+                pattern_variables(pat, names);
+            }
+        }
+        Pattern::Object(fields) => {
+            for (_, pat) in fields {
+                pattern_variables(pat, names);
+            }
+        }
     }
 }
 
@@ -900,7 +1544,7 @@ fn eval_assign(
             }
             AssignMode::Update => {
                 let current = get_path(&result, path);
-                let new_val = eval_one(val_expr, &current, env)?;
+                let new_val = eval_one(val_expr, &current, env).map_err(|e| relocate(e, None))?;
                 result = set_path(&result, path, new_val)?;
             }
             AssignMode::ArithUpdate(op) => {
@@ -945,18 +1589,23 @@ fn collect_paths(
         }
         Expr::Index(base, idx_expr) => {
             let base_paths = collect_paths(base, input, env)?;
+            // As in `eval`, the index sees the same `.` as the base, not
+            // the value being indexed
+            let segs: Vec<PathSegment> = eval(idx_expr, input, env)?
+                .iter()
+                .filter_map(|idx| match idx {
+                    Value::Number(n) => Some(PathSegment::Index(n.as_i64().unwrap_or(0))),
+                    Value::String(s) => Some(PathSegment::Key(s.clone())),
+                    _ => None,
+                })
+                .collect();
             let mut all = Vec::new();
             for bp in &base_paths {
-                let sub_val = get_path(input, bp);
-                let idx = eval_one(idx_expr, &sub_val, env)?;
-                let seg = match &idx {
-                    Value::Number(n) => PathSegment::Index(n.as_i64().unwrap_or(0)),
-                    Value::String(s) => PathSegment::Key(s.clone()),
-                    _ => continue,
-                };
-                let mut path = bp.clone();
-                path.push(seg);
-                all.push(path);
+                for seg in &segs {
+                    let mut path = bp.clone();
+                    path.push(seg.clone());
+                    all.push(path);
+                }
             }
             Ok(all)
         }
@@ -985,22 +1634,251 @@ fn collect_paths(
             }
             Ok(all)
         }
-        _ => {
-            // For complex expressions, fall back to a single identity path
-            Ok(vec![vec![]])
+        Expr::Slice(base, from, to, Some(step)) => {
+            // A stepped slice addresses its items one index at a time
+            let (from, to, step) = slice_args(from, to, step, input, env)?;
+            let mut all = Vec::new();
+            for bp in collect_paths(base, input, env)? {
+                let len = match get_path(input, &bp) {
+                    Value::Array(arr) => arr.len(),
+                    _ => 0,
+                };
+                for i in step_indices(len, from, to, step) {
+                    let mut path = bp.clone();
+                    path.push(PathSegment::Index(i as i64));
+                    all.push(path);
+                }
+            }
+            Ok(all)
+        }
+        Expr::Slice(base, from, to, None) => {
+            let bound = |e: &Option<Box<Expr>>| -> Result<Option<i64>, QfError> {
+                match e {
+                    Some(e) => Ok(Some(eval_one(e, input, env)?.as_i64().unwrap_or(0))),
+                    None => Ok(None),
+                }
+            };
+            let (from, to) = (bound(from)?.unwrap_or(0), bound(to)?);
+            let mut all = collect_paths(base, input, env)?;
+            for path in &mut all {
+                path.push(PathSegment::Slice(from, to));
+            }
+            Ok(all)
+        }
+        Expr::Alternative(left, right) => {
+            // The paths of the left side whose values are truthy, or failing
+            // that (or if the left side errors) those of the right side
+            let left_paths = collect_paths(left, input, env).unwrap_or_default();
+            let truthy: Vec<_> = left_paths
+                .into_iter()
+                .filter(|p| is_truthy(&get_path(input, p)))
+                .collect();
+            if truthy.is_empty() {
+                collect_paths(right, input, env)
+            } else {
+                Ok(truthy)
+            }
+        }
+        Expr::FuncCall(name, args)
+            if name == "select" && args.len() == 1 && env.get_func(name, 1).is_none() =>
+        {
+            let keep = eval(&args[0], input, env)?.iter().any(is_truthy);
+            Ok(if keep { vec![vec![]] } else { vec![] })
+        }
+        Expr::Comma(left, right) => {
+            let mut all = collect_paths(left, input, env)?;
+            all.extend(collect_paths(right, input, env)?);
+            Ok(all)
+        }
+        Expr::RecurseAll => {
+            let mut all = vec![vec![]];
+            descendant_paths(input, &mut Vec::new(), &mut all);
+            Ok(all)
+        }
+        Expr::OptionalField(name) => Ok(match input {
+            Value::Object(_) | Value::Null => vec![vec![PathSegment::Key(name.clone())]],
+            _ => vec![],
+        }),
+        Expr::OptionalIndex(base, idx) => {
+            Ok(collect_paths(&Expr::Index(base.clone(), idx.clone()), input, env)
+                .unwrap_or_default())
+        }
+        Expr::OptionalIterate(base) => {
+            Ok(collect_paths(&Expr::Iterate(base.clone()), input, env).unwrap_or_default())
+        }
+        Expr::Optional(inner) | Expr::Try(inner, None) => {
+            Ok(collect_paths(inner, input, env).unwrap_or_default())
+        }
+        Expr::If {
+            cond,
+            then_branch,
+            elif_branches,
+            else_branch,
+        } => {
+            let mut all = Vec::new();
+            for c in eval(cond, input, env)? {
+                match if_branch(&c, then_branch, elif_branches, else_branch, input, env)? {
+                    Some(branch) => all.extend(collect_paths(branch, input, env)?),
+                    None => all.push(vec![]),
+                }
+            }
+            Ok(all)
         }
+        Expr::FuncCall(name, args)
+            if name == "empty" && args.is_empty() && env.get_func(name, 0).is_none() =>
+        {
+            Ok(vec![])
+        }
+        Expr::FuncCall(name, args) if env.get_func(name, args.len()).is_none() => {
+            builtin_paths(name, args, expr, input, env)
+        }
+        Expr::FuncDef {
+            name,
+            params,
+            body,
+            rest,
+        } => {
+            let mut child_env = env.child();
+            child_env.set_func(
+                name.clone(),
+                params.len(),
+                FuncDef {
+                    params: params.clone(),
+                    body: (**body).clone(),
+                },
+            );
+            collect_paths(rest, input, &child_env)
+        }
+        Expr::FuncCall(name, args) => {
+            let Some(func) = env.get_func(name, args.len()) else {
+                return Err(invalid_path(expr, input, env));
+            };
+            let mut child_env = env.child();
+            child_env.descend()?;
+            for (param, arg) in func.params.iter().zip(args) {
+                child_env.set_var(param.clone(), eval_one(arg, input, env)?);
+            }
+            collect_paths(&func.body, input, &child_env)
+        }
+        _ => Err(invalid_path(expr, input, env)),
     }
 }
 
-#[derive(Debug, Clone)]
+/// Paths of the builtins that pass on part of their input rather than a
+/// value of their own.
+fn builtin_paths(
+    name: &str,
+    args: &[Expr],
+    expr: &Expr,
+    input: &Value,
+    env: &Env,
+) -> Result<Vec<Vec<PathSegment>>, QfError> {
+    let count = |n: &Expr| -> Result<i64, QfError> {
+        Ok(eval_one(n, input, env)?.as_i64().unwrap_or(0))
+    };
+    match (name, args.len()) {
+        // Type selectors keep their input or drop it
+        (
+            "values" | "nulls" | "booleans" | "numbers" | "strings" | "arrays" | "objects"
+            | "iterables" | "scalars",
+            0,
+        ) => Ok(vec![vec![]; eval(expr, input, env)?.len()]),
+        ("first", 0) if input.is_array() => Ok(vec![vec![PathSegment::Index(0)]]),
+        ("last", 0) if input.is_array() => Ok(vec![vec![PathSegment::Index(-1)]]),
+        ("first" | "last", 0) => Ok(vec![vec![]]),
+        ("nth", 1) => Ok(eval(&args[0], input, env)?
+            .iter()
+            .map(|n| vec![PathSegment::Index(n.as_u64().unwrap_or(0) as i64)])
+            .collect()),
+        ("getpath", 1) => eval(&args[0], input, env)?
+            .iter()
+            .map(|path| match path {
+                Value::Array(path) => Ok(builtins::path_segments(path)),
+                _ => Err(QfError::TypeError("Path must be specified as an array".into())),
+            })
+            .collect(),
+        ("first", 1) => Ok(collect_paths(&args[0], input, env)?.into_iter().take(1).collect()),
+        ("last", 1) => Ok(collect_paths(&args[0], input, env)?.pop().into_iter().collect()),
+        ("limit", 2) => {
+            let n = usize::try_from(count(&args[0])?).unwrap_or(0);
+            Ok(collect_paths(&args[1], input, env)?.into_iter().take(n).collect())
+        }
+        ("nth", 2) => {
+            let n = usize::try_from(count(&args[0])?)
+                .map_err(|_| QfError::Runtime("Out of bounds negative array index".into()))?;
+            Ok(collect_paths(&args[1], input, env)?.into_iter().nth(n).into_iter().collect())
+        }
+        ("recurse", 0) => {
+            let mut all = vec![vec![]];
+            descendant_paths(input, &mut Vec::new(), &mut all);
+            Ok(all)
+        }
+        ("recurse", 1) => {
+            let mut all = Vec::new();
+            recurse_paths(&args[0], input, Vec::new(), env, &mut all)?;
+            Ok(all)
+        }
+        _ => Err(invalid_path(expr, input, env)),
+    }
+}
+
+/// The paths `recurse(f)` visits from `prefix` in `root`, each before the
+/// ones below it.
+fn recurse_paths(
+    f: &Expr,
+    root: &Value,
+    prefix: Vec<PathSegment>,
+    env: &Env,
+    out: &mut Vec<Vec<PathSegment>>,
+) -> Result<(), QfError> {
+    let children = collect_paths(f, &get_path(root, &prefix), env)?;
+    out.push(prefix.clone());
+    for child in children {
+        let mut path = prefix.clone();
+        path.extend(child);
+        recurse_paths(f, root, path, env, out)?;
+    }
+    Ok(())
+}
+
+/// The error for an expression that doesn't address part of its input,
+/// naming the value it produced as jq does.
+fn invalid_path(expr: &Expr, input: &Value, env: &Env) -> QfError {
+    let result = eval(expr, input, env).ok().and_then(|vals| vals.into_iter().next());
+    QfError::Runtime(match result {
+        Some(val) => format!("Invalid path expression with result {val}"),
+        None => "Invalid path expression".into(),
+    })
+}
+
+/// The path of every value below `val`, parents before their children.
+fn descendant_paths(val: &Value, prefix: &mut Vec<PathSegment>, out: &mut Vec<Vec<PathSegment>>) {
+    let children: Vec<(PathSegment, &Value)> = match val {
+        Value::Array(arr) => {
+            arr.iter().enumerate().map(|(i, v)| (PathSegment::Index(i as i64), v)).collect()
+        }
+        Value::Object(map) => map.iter().map(|(k, v)| (PathSegment::Key(k.clone()), v)).collect(),
+        _ => return,
+    };
+    for (seg, child) in children {
+        prefix.push(seg);
+        out.push(prefix.clone());
+        descendant_paths(child, prefix, out);
+        prefix.pop();
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum PathSegment {
     Key(String),
     Index(i64),
+    /// `[from:to]` of an array, resolved against its length when applied
+    Slice(i64, Option<i64>),
 }
 
 fn get_path(val: &Value, path: &[PathSegment]) -> Value {
     let mut current = val;
-    for seg in path {
+    for (i, seg) in path.iter().enumerate() {
         match seg {
             PathSegment::Key(k) => {
                 current = match current {
@@ -1021,6 +1899,15 @@ fn get_path(val: &Value, path: &[PathSegment]) -> Value {
                     _ => return Value::Null,
                 };
             }
+            PathSegment::Slice(from, to) => {
+                return match current {
+                    Value::Array(arr) => {
+                        let (start, end) = resolve_slice(arr.len(), *from, *to);
+                        get_path(&Value::Array(arr[start..end].to_vec()), &path[i + 1..])
+                    }
+                    _ => Value::Null,
+                };
+            }
         }
     }
     current.clone()
@@ -1052,8 +1939,12 @@ fn set_path(val: &Value, path: &[PathSegment], new_val: Value) -> Result<Value,
                 Value::Null => Vec::new(),
                 _ => return Err(QfError::TypeError("cannot set index on non-array".into())),
             };
+            // Negative indices count from the end and, as in jq, must land
+            // inside the array; positive ones past the end pad with nulls
             let idx = if *i < 0 {
-                (arr.len() as i64 + i).max(0) as usize
+                usize::try_from(arr.len() as i64 + i).map_err(|_| {
+                    QfError::Runtime("Out of bounds negative array index".into())
+                })?
             } else {
                 *i as usize
             };
@@ -1065,6 +1956,24 @@ fn set_path(val: &Value, path: &[PathSegment], new_val: Value) -> Result<Value,
             arr[idx] = updated;
             Ok(Value::Array(arr))
         }
+        PathSegment::Slice(from, to) => {
+            let mut arr = match val {
+                Value::Array(a) => a.clone(),
+                Value::Null => Vec::new(),
+                _ => return Err(QfError::TypeError("cannot set slice on non-array".into())),
+            };
+            let (start, end) = resolve_slice(arr.len(), *from, *to);
+            let sub = Value::Array(arr[start..end].to_vec());
+            match set_path(&sub, rest, new_val)? {
+                Value::Array(items) => {
+                    arr.splice(start..end, items);
+                    Ok(Value::Array(arr))
+                }
+                _ => Err(QfError::TypeError(
+                    "a slice of an array can only be assigned another array".into(),
+                )),
+            }
+        }
     }
 }
 
@@ -1137,6 +2046,28 @@ mod tests {
         assert_eq!(eval_expr("null", "1 + 2"), vec![json!(3)]);
     }
 
+    #[test]
+    fn eval_integer_arithmetic_boundaries() {
+        // Operands come from the input: number literals in queries are f64
+        let op = |l: &str, r: &str, q: &str| eval_expr(&format!("[{l},{r}]"), q);
+        let sub = |l, r| op(l, r, ".[0] - .[1]");
+        let div = |l, r| op(l, r, ".[0] / .[1]");
+        // Integer operands stay exact past 2^53 instead of rounding through f64
+        assert_eq!(sub("9007199254740995", "2"), vec![json!(9007199254740993_i64)]);
+        assert_eq!(sub("9223372036854775807", "1"), vec![json!(i64::MAX - 1)]);
+        assert_eq!(div("9223372036854775806", "2"), vec![json!(i64::MAX / 2)]);
+        assert_eq!(div("9007199254740993", "9007199254740993"), vec![json!(1)]);
+        assert_eq!(op("9223372036854775807", "10", ".[0] % .[1]"), vec![json!(7)]);
+        // Overflow promotes to float rather than wrapping or saturating
+        assert_eq!(sub("9223372036854775807", "-1"), vec![json!(9.223372036854776e18)]);
+        assert_eq!(sub("-9223372036854775808", "1"), vec![json!(-9.223372036854776e18)]);
+        assert_eq!(div("-9223372036854775808", "-1"), vec![json!(9.223372036854776e18)]);
+        // Inexact results are floats, and never recast once past 2^53
+        assert_eq!(div("7", "2"), vec![json!(3.5)]);
+        assert!(div("9007199254740993", "2")[0].is_f64());
+        assert_eq!(div("18014398509481988", "0.5"), vec![json!(36028797018963976.0)]);
+    }
+
     #[test]
     fn eval_string_concat() {
         assert_eq!(
@@ -1160,6 +2091,46 @@ mod tests {
         );
     }
 
+    #[test]
+    fn eval_array_collects_generator() {
+        assert_eq!(eval_expr("null", "[range(3)]"), vec![json!([0, 1, 2])]);
+        assert_eq!(eval_expr("null", "[empty]"), vec![json!([])]);
+        assert_eq!(
+            eval_expr("[1,2,3]", "[.[] | select(. > 1)]"),
+            vec![json!([2, 3])]
+        );
+    }
+
+    #[test]
+    fn eval_object_construct_generator_value() {
+        assert_eq!(
+            eval_expr("null", "{a: (1,2), b: 3}"),
+            vec![json!({"a": 1, "b": 3}), json!({"a": 2, "b": 3})]
+        );
+    }
+
+    #[test]
+    fn eval_object_construct_generator_key() {
+        assert_eq!(
+            eval_expr("null", r#"{(("a","b")): 1, c: 2}"#),
+            vec![json!({"a": 1, "c": 2}), json!({"b": 1, "c": 2})]
+        );
+        // Every key pairs with every value, later entries varying fastest
+        assert_eq!(
+            eval_expr(r#"["x","y"]"#, "{a: (1,2), (.[]): 3}"),
+            vec![
+                json!({"a": 1, "x": 3}),
+                json!({"a": 1, "y": 3}),
+                json!({"a": 2, "x": 3}),
+                json!({"a": 2, "y": 3}),
+            ]
+        );
+        assert_eq!(
+            eval_expr("null", r#"{(("a","b")): (1,2)}"#),
+            vec![json!({"a": 1}), json!({"a": 2}), json!({"b": 1}), json!({"b": 2})]
+        );
+    }
+
     #[test]
     fn eval_object_construct() {
         let result = eval_expr(r#"{"x":1,"y":2}"#, r#"{a: .x, b: .y}"#);
@@ -1230,6 +2201,21 @@ mod tests {
         );
     }
 
+    #[test]
+    fn eval_set_assign_rhs_sees_root() {
+        let input = r#"{
+            "defaults": {"active": true},
+            "users": [{"name": "a", "active": false}, {"name": "b"}]
+        }"#;
+        assert_eq!(
+            eval_expr(input, ".users[].active = .defaults.active"),
+            vec![json!({
+                "defaults": {"active": true},
+                "users": [{"name": "a", "active": true}, {"name": "b", "active": true}]
+            })]
+        );
+    }
+
     #[test]
     fn eval_negative_index() {
         assert_eq!(
@@ -1286,4 +2272,76 @@ mod tests {
             vec![json!(["a", "b"])]
         );
     }
+
+    #[test]
+    fn navigation_borrows_from_input() {
+        let items: Vec<Value> = (0..100_000).map(|i| json!({"name": i, "tags": ["x"]})).collect();
+        let input = json!({"items": items});
+        let expr = crate::query::parse_query(".items[]").unwrap();
+        // Every selected element is a reference into `input`, not a copy
+        let refs = navigate(&expr, &input).unwrap();
+        assert_eq!(refs.len(), 100_000);
+        assert!(std::ptr::eq(refs[5], &input["items"][5]));
+
+        // `.items[] | .name` clones only the names
+        let names = crate::query::query(&input, ".items[] | .name").unwrap();
+        assert_eq!(names.len(), 100_000);
+        assert_eq!(names[99_999], json!(99_999));
+
+        // Anything beyond plain navigation falls back to evaluation
+        for query in [".items[0]", ".items | length", "1"] {
+            let expr = crate::query::parse_query(query).unwrap();
+            assert!(navigate(&expr, &input).is_none(), "{query}");
+        }
+        assert!(navigate(&crate::query::parse_query(".a").unwrap(), &json!(1)).is_none());
+    }
+
+    #[test]
+    fn navigation_matches_evaluation() {
+        let input = json!({"a": {"b": [1, {"c": 2}]}, "n": null, "s": "x"});
+        let eval_query = |q: &str| crate::query::query(&input, q).map_err(|e| e.to_string());
+        assert_eq!(eval_query(".a.b[] | .c?"), Ok(vec![json!(2)]));
+        assert_eq!(eval_query(".n[] | ."), Ok(vec![]));
+        assert_eq!(eval_query(".missing.x | ."), Ok(vec![json!(null)]));
+        assert_eq!(eval_query(".s[]? | ."), Ok(vec![]));
+        assert!(eval_query(".s[] | .").is_err());
+        assert!(eval_query(".a.b[] | .c").is_err());
+        // Streaming evaluation takes the same path
+        assert_eq!(eval_query("first(.a.b[] | .)"), Ok(vec![json!(1)]));
+    }
+
+    #[test]
+    fn numbers_compare_by_value() {
+        let input = r#"[3, 3.0, -0.0, 0, 1.5]"#;
+        assert_eq!(eval_expr(input, ".[0] == .[1]"), vec![json!(true)]);
+        assert_eq!(eval_expr(input, ".[2] == .[3]"), vec![json!(true)]);
+        assert_eq!(eval_expr(input, ".[2] < .[3]"), vec![json!(false)]);
+        assert_eq!(eval_expr(input, "unique"), vec![json!([-0.0, 1.5, 3])]);
+        assert_eq!(
+            eval_expr(input, "[.[] | IN(0)]"),
+            vec![json!([false, false, true, true, false])]
+        );
+        // Integers beyond f64 precision still compare exactly
+        let big = json!([9007199254740993u64, 9007199254740992u64]);
+        assert!(compare_values(&big[0], &big[1]).is_gt());
+    }
+
+    #[test]
+    fn nan_sorts_before_numbers() {
+        // serde_json has no NaN, so `nan` is held as null, which also sorts
+        // below every number
+        assert_eq!(eval_expr("null", "[1, nan, -1] | sort"), vec![json!([null, -1, 1])]);
+        assert_eq!(eval_expr("null", "nan < -1e300"), vec![json!(true)]);
+        assert!(compare_numbers(&(-1).into(), &1.into()).is_lt());
+    }
+
+    #[test]
+    fn objects_order_by_keys_then_values() {
+        let input = r#"[{"b": 1}, {"a": 2}, {"a": 1, "b": 0}, {"a": 1}]"#;
+        assert_eq!(
+            eval_expr(input, "sort"),
+            vec![json!([{"a": 1}, {"a": 2}, {"a": 1, "b": 0}, {"b": 1}])]
+        );
+        assert_eq!(eval_expr(input, r#"{"a": 1.0} == .[3]"#), vec![json!(true)]);
+    }
 }
@@ -15,13 +15,72 @@ impl Parser {
 
     pub fn parse(&mut self) -> Result<Expr, QfError> {
         let expr = self.parse_pipe()?;
+        self.expect_eof()?;
+        Ok(expr)
+    }
+
+    /// A main program: its leading module directives, then the query.
+    pub fn parse_program(&mut self) -> Result<(Vec<Directive>, Expr), QfError> {
+        let directives = self.parse_directives()?;
+        Ok((directives, self.parse()?))
+    }
+
+    /// A module file: its directives, then nothing but `def`s.
+    pub fn parse_module(&mut self) -> Result<(Vec<Directive>, Vec<Def>), QfError> {
+        let directives = self.parse_directives()?;
+        let mut defs = Vec::new();
+        while matches!(self.current(), Token::Def) {
+            defs.push(self.parse_def()?);
+        }
+        self.expect_eof()?;
+        Ok((directives, defs))
+    }
+
+    fn expect_eof(&self) -> Result<(), QfError> {
         if !self.at_eof() {
             return Err(self.error(format!(
                 "unexpected token: {:?}",
                 self.current()
             )));
         }
-        Ok(expr)
+        Ok(())
+    }
+
+    fn parse_directives(&mut self) -> Result<Vec<Directive>, QfError> {
+        let mut directives = Vec::new();
+        loop {
+            let directive = match self.current() {
+                Token::Include => {
+                    self.advance();
+                    Directive::Include(self.parse_module_path()?)
+                }
+                Token::Import => {
+                    self.advance();
+                    let path = self.parse_module_path()?;
+                    self.expect(&Token::As)?;
+                    match self.advance() {
+                        Token::Ident(alias) => Directive::Import { path, alias },
+                        Token::Variable(alias) => Directive::ImportData { path, alias },
+                        other => {
+                            return Err(self.error(format!(
+                                "expected module name after 'as', got {:?}",
+                                other
+                            )))
+                        }
+                    }
+                }
+                _ => return Ok(directives),
+            };
+            self.expect(&Token::Semicolon)?;
+            directives.push(directive);
+        }
+    }
+
+    fn parse_module_path(&mut self) -> Result<String, QfError> {
+        match self.advance() {
+            Token::String(path) => Ok(path),
+            other => Err(self.error(format!("expected module path string, got {:?}", other))),
+        }
     }
 
     fn current(&self) -> &Token {
@@ -94,7 +153,7 @@ impl Parser {
         // Handle `as $var |`
         if matches!(self.current(), Token::As) {
             self.advance(); // skip 'as'
-            let pattern = self.parse_pattern()?;
+            let pattern = self.parse_patterns()?;
             self.expect(&Token::Pipe)?;
             let body = self.parse_pipe()?;
             return Ok(Expr::As {
@@ -281,6 +340,28 @@ impl Parser {
         self.parse_postfix()
     }
 
+    /// The rest of a slice of `base` after its start bound, from the first
+    /// `:` through the closing `]`: `:N]`, `:]`, `:N:S]` or `::S]`.
+    fn parse_slice(&mut self, base: Expr, from: Option<Expr>) -> Result<Expr, QfError> {
+        self.expect(&Token::Colon)?;
+        let bound = |parser: &mut Self| -> Result<Option<Box<Expr>>, QfError> {
+            if matches!(parser.current(), Token::RBracket | Token::Colon) {
+                Ok(None)
+            } else {
+                Ok(Some(Box::new(parser.parse_pipe()?)))
+            }
+        };
+        let to = bound(self)?;
+        let step = if matches!(self.current(), Token::Colon) {
+            self.advance(); // skip :
+            bound(self)?
+        } else {
+            None
+        };
+        self.expect(&Token::RBracket)?;
+        Ok(Expr::Slice(Box::new(base), from.map(Box::new), to, step))
+    }
+
     /// postfix: primary ('.' ident | '[' expr ']' | '[]' | '?')*
     fn parse_postfix(&mut self) -> Result<Expr, QfError> {
         let mut expr = self.parse_primary()?;
@@ -322,23 +403,13 @@ impl Parser {
                             expr = Expr::Iterate(Box::new(expr));
                         }
                     } else if matches!(self.current(), Token::Colon) {
-                        // Slice: [: N]
-                        self.advance(); // skip :
-                        let end = self.parse_pipe()?;
-                        self.expect(&Token::RBracket)?;
-                        expr = Expr::Slice(Box::new(expr), None, Some(Box::new(end)));
+                        // Slice: [:N], [:N:S] or [::S]
+                        expr = self.parse_slice(expr, None)?;
                     } else {
                         let idx = self.parse_pipe()?;
                         if matches!(self.current(), Token::Colon) {
-                            // Slice: [M:N] or [M:]
-                            self.advance(); // skip :
-                            let end = if matches!(self.current(), Token::RBracket) {
-                                None
-                            } else {
-                                Some(Box::new(self.parse_pipe()?))
-                            };
-                            self.expect(&Token::RBracket)?;
-                            expr = Expr::Slice(Box::new(expr), Some(Box::new(idx)), end);
+                            // Slice: [M:N], [M:] or [M:N:S]
+                            expr = self.parse_slice(expr, Some(idx))?;
                         } else {
                             self.expect(&Token::RBracket)?;
                             // Check for optional
@@ -647,7 +718,7 @@ impl Parser {
         self.advance(); // skip 'reduce'
         let expr = self.parse_postfix()?;
         self.expect(&Token::As)?;
-        let pattern = self.parse_pattern()?;
+        let pattern = self.parse_patterns()?;
         self.expect(&Token::LParen)?;
         let init = self.parse_pipe()?;
         self.expect(&Token::Semicolon)?;
@@ -665,7 +736,7 @@ impl Parser {
         self.advance(); // skip 'foreach'
         let expr = self.parse_postfix()?;
         self.expect(&Token::As)?;
-        let pattern = self.parse_pattern()?;
+        let pattern = self.parse_patterns()?;
         self.expect(&Token::LParen)?;
         let init = self.parse_pipe()?;
         self.expect(&Token::Semicolon)?;
@@ -687,6 +758,18 @@ impl Parser {
     }
 
     fn parse_funcdef(&mut self) -> Result<Expr, QfError> {
+        let def = self.parse_def()?;
+        let rest = self.parse_pipe()?;
+        Ok(Expr::FuncDef {
+            name: def.name,
+            params: def.params,
+            body: Box::new(def.body),
+            rest: Box::new(rest),
+        })
+    }
+
+    /// `def name(params): body;`
+    fn parse_def(&mut self) -> Result<Def, QfError> {
         self.advance(); // skip 'def'
         let name = match self.advance() {
             Token::Ident(name) => name,
@@ -723,14 +806,7 @@ impl Parser {
         self.expect(&Token::Colon)?;
         let body = self.parse_pipe()?;
         self.expect(&Token::Semicolon)?;
-        let rest = self.parse_pipe()?;
-
-        Ok(Expr::FuncDef {
-            name,
-            params,
-            body: Box::new(body),
-            rest: Box::new(rest),
-        })
+        Ok(Def { name, params, body })
     }
 
     fn parse_label(&mut self) -> Result<Expr, QfError> {
@@ -744,6 +820,41 @@ impl Parser {
         Ok(Expr::Label(name, Box::new(body)))
     }
 
+    /// A pattern, or several separated by `?//`.
+    fn parse_patterns(&mut self) -> Result<Pattern, QfError> {
+        let mut patterns = vec![self.parse_pattern()?];
+        while matches!(self.current(), Token::Question)
+            && matches!(self.peek(), Token::Alternative)
+        {
+            self.advance(); // skip '?'
+            self.advance(); // skip '//'
+            patterns.push(self.parse_pattern()?);
+        }
+        if patterns.len() == 1 {
+            Ok(patterns.remove(0))
+        } else {
+            Ok(Pattern::Alternative(patterns))
+        }
+    }
+
+    /// One field of an object pattern: `key: pattern`, or `$name` for
+    /// `name: $name`.
+    fn parse_pattern_field(&mut self) -> Result<(String, Pattern), QfError> {
+        let key = match self.advance() {
+            Token::Variable(name) => return Ok((name.clone(), Pattern::Variable(name))),
+            Token::Ident(k) => k,
+            other => {
+                return Err(self.error(format!(
+                    "expected field name in pattern, got {:?}",
+                    other
+                )))
+            }
+        };
+        self.expect(&Token::Colon)?;
+        let pat = self.parse_pattern()?;
+        Ok((key, pat))
+    }
+
     fn parse_pattern(&mut self) -> Result<Pattern, QfError> {
         match self.current().clone() {
             Token::Variable(name) => {
@@ -767,32 +878,10 @@ impl Parser {
                 self.advance();
                 let mut fields = Vec::new();
                 if !matches!(self.current(), Token::RBrace) {
-                    let key = match self.advance() {
-                        Token::Ident(k) => k,
-                        other => {
-                            return Err(self.error(format!(
-                                "expected field name in pattern, got {:?}",
-                                other
-                            )))
-                        }
-                    };
-                    self.expect(&Token::Colon)?;
-                    let pat = self.parse_pattern()?;
-                    fields.push((key, pat));
+                    fields.push(self.parse_pattern_field()?);
                     while matches!(self.current(), Token::Comma) {
                         self.advance();
-                        let key = match self.advance() {
-                            Token::Ident(k) => k,
-                            other => {
-                                return Err(self.error(format!(
-                                    "expected field name in pattern, got {:?}",
-                                    other
-                                )))
-                            }
-                        };
-                        self.expect(&Token::Colon)?;
-                        let pat = self.parse_pattern()?;
-                        fields.push((key, pat));
+                        fields.push(self.parse_pattern_field()?);
                     }
                 }
                 self.expect(&Token::RBrace)?;
@@ -973,4 +1062,22 @@ mod tests {
             }
         );
     }
+
+    #[test]
+    fn parse_pattern_alternatives() {
+        let expr = parse_expr(". as [$a] ?// {$a, b: $c} | $a");
+        let Expr::As { pattern, .. } = expr else {
+            panic!("expected As, got {expr:?}");
+        };
+        assert_eq!(
+            pattern,
+            Pattern::Alternative(vec![
+                Pattern::Array(vec![Pattern::Variable("a".into())]),
+                Pattern::Object(vec![
+                    ("a".into(), Pattern::Variable("a".into())),
+                    ("b".into(), Pattern::Variable("c".into())),
+                ]),
+            ])
+        );
+    }
 }
@@ -102,6 +102,7 @@ impl Token {
             "import" => Some(Token::Import),
             "include" => Some(Token::Include),
             "label" => Some(Token::Label),
+            "break" => Some(Token::Break),
             "true" => Some(Token::True),
             "false" => Some(Token::False),
             "null" => Some(Token::Null),
@@ -293,12 +294,7 @@ impl Lexer {
                 '$' => {
                     self.pos += 1;
                     let start = self.pos;
-                    while self.pos < self.input.len()
-                        && (self.input[self.pos].is_ascii_alphanumeric()
-                            || self.input[self.pos] == '_')
-                    {
-                        self.pos += 1;
-                    }
+                    self.skip_name();
                     let name: String = self.input[start..self.pos].iter().collect();
                     if name.is_empty() {
                         return Err(QfError::SyntaxError {
@@ -381,12 +377,25 @@ impl Lexer {
 
     fn read_string(&mut self) -> Result<(), QfError> {
         self.pos += 1; // skip opening quote
+        // `@base64 "\(.a)"` applies the format to each interpolated value,
+        // so the whole string becomes `("" + (.a | @base64) + "")`
+        let format = match self.tokens.last() {
+            Some(Token::Format(name)) => Some(name.clone()),
+            _ => None,
+        };
+        if format.is_some() {
+            self.tokens.pop();
+            self.tokens.push(Token::LParen);
+        }
         let mut s = String::new();
         while self.pos < self.input.len() {
             match self.input[self.pos] {
                 '"' => {
                     self.pos += 1;
                     self.tokens.push(Token::String(s));
+                    if format.is_some() {
+                        self.tokens.push(Token::RParen);
+                    }
                     return Ok(());
                 }
                 '\\' => {
@@ -436,10 +445,13 @@ impl Lexer {
                             if let Some(Token::Eof) = sub_lexer.tokens.last() {
                                 sub_lexer.tokens.pop();
                             }
-                            // Add pipe to tostring before closing
+                            // Add pipe to tostring (or the format) before closing
                             self.tokens.extend(sub_lexer.tokens);
                             self.tokens.push(Token::Pipe);
-                            self.tokens.push(Token::Ident("tostring".into()));
+                            self.tokens.push(match &format {
+                                Some(name) => Token::Format(name.clone()),
+                                None => Token::Ident("tostring".into()),
+                            });
                             self.tokens.push(Token::RParen);
                             self.tokens.push(Token::Plus);
 
@@ -493,11 +505,7 @@ impl Lexer {
 
     fn read_ident(&mut self) {
         let start = self.pos;
-        while self.pos < self.input.len()
-            && (self.input[self.pos].is_ascii_alphanumeric() || self.input[self.pos] == '_')
-        {
-            self.pos += 1;
-        }
+        self.skip_name();
         let word: String = self.input[start..self.pos].iter().collect();
         if let Some(kw) = Token::is_keyword(&word) {
             self.tokens.push(kw);
@@ -505,6 +513,24 @@ impl Lexer {
             self.tokens.push(Token::Ident(word));
         }
     }
+
+    /// Move past a name, including any `module::` prefixes.
+    fn skip_name(&mut self) {
+        let is_name_char = |c: char| c.is_ascii_alphanumeric() || c == '_';
+        let start = self.pos;
+        loop {
+            while self.pos < self.input.len() && is_name_char(self.input[self.pos]) {
+                self.pos += 1;
+            }
+            let qualified = self.input.get(self.pos..self.pos + 3).is_some_and(|next| {
+                next[..2] == [':', ':'] && (next[2].is_ascii_alphabetic() || next[2] == '_')
+            });
+            if !qualified || self.pos == start {
+                return;
+            }
+            self.pos += 2;
+        }
+    }
 }
 
 #[cfg(test)]
@@ -640,4 +666,17 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn lex_module_qualified_names() {
+        assert_eq!(
+            lex("m::f $d::d .[1:2]"),
+            vec![
+                Token::Ident("m::f".into()),
+                Token::Variable("d::d".into()),
+                Token::Dot, Token::LBracket, Token::Number(1.0), Token::Colon,
+                Token::Number(2.0), Token::RBracket, Token::Eof,
+            ]
+        );
+    }
 }
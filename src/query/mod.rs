@@ -4,8 +4,11 @@ pub mod env;
 pub mod eval;
 pub mod jq_parser;
 pub mod lexer;
+pub mod module;
 pub mod path;
 
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 use serde_json::Value;
 
 use crate::error::QfError;
@@ -15,13 +18,86 @@ use crate::error::QfError;
 /// Uses the JQ-compatible engine for complex queries, falls back to
 /// the simple path engine for basic dot-notation paths.
 pub fn query(input: &Value, query_str: &str) -> Result<Vec<Value>, QfError> {
+    query_with_env(input, query_str, &env::Env::new())
+}
+
+/// Execute a query string with a pre-populated environment, e.g. one
+/// carrying `--arg`/`--argjson` variable bindings.
+pub fn query_with_env(
+    input: &Value,
+    query_str: &str,
+    env: &env::Env,
+) -> Result<Vec<Value>, QfError> {
     // Use the JQ engine for all queries
+    let expr = parse_query_with_env(query_str, env)?;
+    eval::eval(&expr, input, env)
+}
+
+/// Execute a query against any serializable value, deserializing each
+/// result into `U`.
+///
+/// `input` goes through `serde_json::to_value` first, so it is seen by the
+/// query exactly as its JSON form would be.
+pub fn query_typed<T: Serialize, U: DeserializeOwned>(
+    input: &T,
+    query_str: &str,
+) -> Result<Vec<U>, QfError> {
+    let program = Program::compile(query_str)?;
+    let value = serde_json::to_value(input).map_err(|e| QfError::Parse(e.to_string()))?;
+    program
+        .run(&value)?
+        .into_iter()
+        .map(|v| serde_json::from_value(v).map_err(|e| QfError::Parse(e.to_string())))
+        .collect()
+}
+
+/// A query parsed once and ready to run against any number of inputs.
+#[derive(Debug, Clone)]
+pub struct Program {
+    expr: ast::Expr,
+}
+
+impl Program {
+    /// Lex and parse `query_str`.
+    pub fn compile(query_str: &str) -> Result<Program, QfError> {
+        Ok(Program {
+            expr: parse_query(query_str)?,
+        })
+    }
+
+    /// Lex and parse `query_str`, loading any modules it includes or
+    /// imports from `env`'s library path.
+    pub fn compile_with_env(query_str: &str, env: &env::Env) -> Result<Program, QfError> {
+        Ok(Program {
+            expr: parse_query_with_env(query_str, env)?,
+        })
+    }
+
+    /// Run the program against `input` with an empty environment.
+    pub fn run(&self, input: &Value) -> Result<Vec<Value>, QfError> {
+        self.run_with_env(input, &env::Env::new())
+    }
+
+    /// Run the program against `input` with the given environment.
+    pub fn run_with_env(&self, input: &Value, env: &env::Env) -> Result<Vec<Value>, QfError> {
+        eval::eval(&self.expr, input, env)
+    }
+}
+
+/// Parse a query string into an expression that can be evaluated against
+/// many inputs without re-parsing.
+pub fn parse_query(query_str: &str) -> Result<ast::Expr, QfError> {
+    parse_query_with_env(query_str, &env::Env::new())
+}
+
+/// Parse a query string, resolving its `include`/`import` directives
+/// against `env`'s library path.
+pub fn parse_query_with_env(query_str: &str, env: &env::Env) -> Result<ast::Expr, QfError> {
     let mut lex = lexer::Lexer::new(query_str);
     lex.tokenize()?;
     let mut parser = jq_parser::Parser::new(lex.tokens);
-    let expr = parser.parse()?;
-    let env = env::Env::new();
-    eval::eval(&expr, input, &env)
+    let (directives, expr) = parser.parse_program()?;
+    module::link(&directives, expr, env)
 }
 
 #[cfg(test)]
@@ -29,6 +105,50 @@ mod tests {
     use super::*;
     use serde_json::json;
 
+    #[test]
+    fn typed_query_over_structs() {
+        #[derive(Serialize)]
+        struct Service {
+            name: String,
+            port: u16,
+            tags: Vec<String>,
+        }
+        #[derive(Debug, PartialEq, serde::Deserialize)]
+        struct Endpoint {
+            name: String,
+            url: String,
+        }
+        let services = vec![
+            Service { name: "api".into(), port: 8080, tags: vec!["public".into()] },
+            Service { name: "db".into(), port: 5432, tags: vec![] },
+        ];
+        let public: Vec<String> =
+            query_typed(&services, r#".[] | select(.tags | index("public")) | .name"#).unwrap();
+        assert_eq!(public, vec!["api".to_string()]);
+        let endpoints: Vec<Endpoint> = query_typed(
+            &services,
+            r#".[] | {name, url: "http://localhost:\(.port)"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            endpoints[1],
+            Endpoint { name: "db".into(), url: "http://localhost:5432".into() }
+        );
+        let ports: Vec<u16> = query_typed(&services, ".[].port").unwrap();
+        assert_eq!(ports, vec![8080, 5432]);
+        // A result that does not fit `U` is an error
+        assert!(query_typed::<_, u16>(&services, ".[].name").is_err());
+    }
+
+    #[test]
+    fn program_compiles_once_runs_many() {
+        let program = Program::compile(".a + 1").unwrap();
+        assert_eq!(program.run(&json!({"a": 1})).unwrap(), vec![json!(2)]);
+        assert_eq!(program.run(&json!({"a": 41})).unwrap(), vec![json!(42)]);
+        assert!(program.run(&json!({"a": "x"})).is_err());
+        assert!(Program::compile(".a +").is_err());
+    }
+
     #[test]
     fn query_shorthand() {
         let val = json!({"a": {"b": 1}});
@@ -285,6 +405,14 @@ mod tests {
         );
     }
 
+    #[test]
+    fn query_with_bound_variable() {
+        let mut env = env::Env::new();
+        env.set_var("x".into(), json!("hi"));
+        let results = query_with_env(&json!(null), "$x", &env).unwrap();
+        assert_eq!(results, vec![json!("hi")]);
+    }
+
     #[test]
     fn query_logical_ops() {
         assert_eq!(
@@ -300,4 +428,893 @@ mod tests {
             vec![json!(false)]
         );
     }
+
+    #[test]
+    fn query_indices_subarray() {
+        let val = json!([0, 1, 2, 1, 2]);
+        assert_eq!(query(&val, "indices([1,2])").unwrap(), vec![json!([1, 3])]);
+        assert_eq!(query(&val, "indices(1)").unwrap(), vec![json!([1, 3])]);
+        assert_eq!(query(&val, "index([1,2])").unwrap(), vec![json!(1)]);
+        assert_eq!(query(&val, "rindex([1,2])").unwrap(), vec![json!(3)]);
+        assert_eq!(query(&val, "indices([2,0])").unwrap(), vec![json!([])]);
+        // Numbers match by value
+        assert_eq!(query(&json!([1.0, 2]), "indices(1)").unwrap(), vec![json!([0])]);
+        assert_eq!(query(&val, "index([1.0, 2.0])").unwrap(), vec![json!(1)]);
+        assert_eq!(
+            query(&json!("a,b, cd, efg"), "indices(\", \")").unwrap(),
+            vec![json!([3, 7])]
+        );
+    }
+
+    #[test]
+    fn query_in_stream() {
+        assert_eq!(query(&json!(2), "IN(1, 2, 3)").unwrap(), vec![json!(true)]);
+        assert_eq!(query(&json!(5), "IN(1, 2, 3)").unwrap(), vec![json!(false)]);
+        assert_eq!(
+            query(&json!([1, 5]), "map(IN(0, 5))").unwrap(),
+            vec![json!([false, true])]
+        );
+    }
+
+    #[test]
+    fn query_parallel_map_matches_sequential() {
+        let val = Value::Array((0..500).map(|i| json!({"n": i, "s": format!("item-{i}")})).collect());
+        let q = r#"map(select(.n % 3 != 0) | {n: (.n * 2), s: (.s | sub("item"; "x")), twice: (.n, .n)})"#;
+
+        let sequential = query(&val, q).unwrap();
+        let mut env = env::Env::new();
+        env.set_parallel_map(true);
+        let parallel = query_with_env(&val, q, &env).unwrap();
+        assert_eq!(parallel, sequential);
+        assert_eq!(parallel[0].as_array().unwrap().len(), 333 * 2);
+        // Of several failing elements, the first one's error is reported, even
+        // when a later one fails sooner
+        let q = r#"[range(64)] | map(if . == 1 then [range(100000)] | error("e1")
+                                       elif . > 1 then error("e\(.)") else . end)"#;
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(4).build().unwrap();
+        for _ in 0..5 {
+            let err = pool.install(|| query_with_env(&json!(null), q, &env)).unwrap_err();
+            assert_eq!(err.to_string(), "e1");
+        }
+    }
+
+    #[test]
+    fn query_nth_generator_is_bounded() {
+        let n = json!(null);
+        assert_eq!(query(&n, "nth(2; range(1000000000))").unwrap(), vec![json!(2)]);
+        assert_eq!(query(&n, "first(range(1000000000))").unwrap(), vec![json!(0)]);
+        assert_eq!(
+            query(&n, "[limit(3; range(1000000000))]").unwrap(),
+            vec![json!([0, 1, 2])]
+        );
+        assert_eq!(
+            query(&n, "nth(1; range(5) | select(. > 1))").unwrap(),
+            vec![json!(3)]
+        );
+        assert_eq!(query(&json!(1), "first(repeat(. * 2) | select(. > 100))").unwrap(), vec![json!(128)]);
+    }
+
+    #[test]
+    fn query_nth_and_last_of_generator() {
+        let n = json!(null);
+        assert_eq!(query(&n, "nth(5; range(3))").unwrap(), Vec::<Value>::new());
+        assert_eq!(query(&n, "last(range(5))").unwrap(), vec![json!(4)]);
+        assert_eq!(query(&n, "[limit(0; 1, 2)]").unwrap(), vec![json!([])]);
+        assert!(query(&n, "nth(-1; range(3))").is_err());
+    }
+
+    #[test]
+    fn query_del_iterator() {
+        assert_eq!(query(&json!([1, 2, 3]), "del(.[])").unwrap(), vec![json!([])]);
+        assert_eq!(
+            query(&json!({"a": {"x": 1, "y": 2}, "b": 3}), "del(.a[])").unwrap(),
+            vec![json!({"a": {}, "b": 3})]
+        );
+        assert_eq!(
+            query(&json!([1, 2, 3, 4]), "del(.[] | select(. % 2 == 0))").unwrap(),
+            vec![json!([1, 3])]
+        );
+    }
+
+    #[test]
+    fn query_del_slice() {
+        let val = json!([0, 1, 2, 3, 4]);
+        assert_eq!(query(&val, "del(.[1:3])").unwrap(), vec![json!([0, 3, 4])]);
+        assert_eq!(query(&val, "del(.[-2:])").unwrap(), vec![json!([0, 1, 2])]);
+        assert_eq!(query(&val, "del(.[:0])").unwrap(), vec![val.clone()]);
+        assert_eq!(
+            query(&json!({"xs": [1, 2, 3]}), "del(.xs[1:])").unwrap(),
+            vec![json!({"xs": [1]})]
+        );
+    }
+
+    #[test]
+    fn query_assign_through_slice() {
+        let val = json!({"a": [0, 1, 2, 3, 4]});
+        let q = |f: &str| query(&val, f).unwrap();
+        assert_eq!(q(r#".a[1:3] = ["x"]"#), vec![json!({"a": [0, "x", 3, 4]})]);
+        assert_eq!(q(".a[-2:] |= map(. * 10)"), vec![json!({"a": [0, 1, 2, 30, 40]})]);
+        assert_eq!(q(".a[1:3][0] = 9"), vec![json!({"a": [0, 9, 2, 3, 4]})]);
+        assert_eq!(q(".a[:2] += [7]"), vec![json!({"a": [0, 1, 7, 2, 3, 4]})]);
+        assert!(query(&val, ".a[1:3] = 1").is_err());
+        // Deletion through a slice of a nested path
+        assert_eq!(q("del(.a[1:] | .[0])"), vec![json!({"a": [0, 2, 3, 4]})]);
+    }
+
+    #[test]
+    fn query_assign_through_alternative() {
+        let q = |input: Value, f: &str| query(&input, f).unwrap();
+        assert_eq!(q(json!({"a": 1, "b": 2}), "(.a // .b) = 5"), vec![json!({"a": 5, "b": 2})]);
+        assert_eq!(
+            q(json!({"a": null, "b": 2}), "(.a // .b) = 5"),
+            vec![json!({"a": null, "b": 5})]
+        );
+        assert_eq!(
+            q(json!({"a": false, "b": null}), "(.a // .b) //= 1"),
+            vec![json!({"a": false, "b": 1})]
+        );
+        assert_eq!(q(json!({"a": 1}), "del(.a // .b)"), vec![json!({})]);
+    }
+
+    #[test]
+    fn query_delpaths_sibling_indices() {
+        let val = json!(["a", "b", "c", "d"]);
+        assert_eq!(query(&val, "delpaths([[0], [2]])").unwrap(), vec![json!(["b", "d"])]);
+        assert_eq!(query(&val, "delpaths([[2], [0]])").unwrap(), vec![json!(["b", "d"])]);
+        let nested = json!({"a": [[1, 2, 3], 4]});
+        assert_eq!(
+            query(&nested, r#"delpaths([["a", 0, 0], ["a", 1], ["a", 0, 2]])"#).unwrap(),
+            vec![json!({"a": [[2]]})]
+        );
+        // Repeated and negative indices name each element once
+        let val = json!([0, 1, 2, 3]);
+        assert_eq!(query(&val, "delpaths([[1], [1]])").unwrap(), vec![json!([0, 2, 3])]);
+        assert_eq!(query(&val, "del(.[-1, 3])").unwrap(), vec![json!([0, 1, 2])]);
+        assert_eq!(query(&val, "delpaths([[-1], [0]])").unwrap(), vec![json!([1, 2])]);
+        assert_eq!(
+            query(&val, r#"delpaths([[{"start": 1, "end": 3}]])"#).unwrap(),
+            vec![json!([0, 3])]
+        );
+    }
+
+    #[test]
+    fn query_del_nested_index() {
+        assert_eq!(
+            query(&json!({"a": [1, 2, 3]}), "del(.a[0])").unwrap(),
+            vec![json!({"a": [2, 3]})]
+        );
+    }
+
+    #[test]
+    fn query_path_expressions() {
+        let val = json!({"a": [1, 2, 3], "b": null});
+        assert_eq!(query(&val, "(.a, .b) = 0").unwrap(), vec![json!({"a": 0, "b": 0})]);
+        assert_eq!(query(&val, "del(.a[0], .a[1])").unwrap(), vec![json!({"a": [3], "b": null})]);
+        assert_eq!(
+            query(&val, "del(.. | select(. == null))").unwrap(),
+            vec![json!({"a": [1, 2, 3]})]
+        );
+        assert_eq!(
+            query(&val, "def first_a: .a[0]; first_a |= . + 10").unwrap(),
+            vec![json!({"a": [11, 2, 3], "b": null})]
+        );
+        assert_eq!(query(&val, "(if .b then .b else .a[2] end) = 9").unwrap()[0]["a"][2], 9);
+        // Builtins that pass on part of their input have paths too
+        let rows = json!([1, null, 2, null]);
+        assert_eq!(query(&rows, "del(.[] | nulls)").unwrap(), vec![json!([1, 2])]);
+        assert_eq!(query(&rows, "del(first)").unwrap(), vec![json!([null, 2, null])]);
+        assert_eq!(query(&rows, "del(last, nth(1))").unwrap(), vec![json!([1, 2])]);
+        let cases = [
+            ("del(.a | first)", json!({"a": [2, 3], "b": null})),
+            ("(.a | last) |= 9", json!({"a": [1, 2, 9], "b": null})),
+            ("getpath([\"a\", 1]) |= 5", json!({"a": [1, 5, 3], "b": null})),
+            ("first(.a[] | select(. > 1)) |= 0", json!({"a": [1, 0, 3], "b": null})),
+            ("limit(2; .a[]) |= 0", json!({"a": [0, 0, 3], "b": null})),
+            ("(recurse | numbers) |= . + 1", json!({"a": [2, 3, 4], "b": null})),
+            ("(recurse(.a?) | arrays) |= length", json!({"a": 3, "b": null})),
+        ];
+        for (q, expected) in cases {
+            assert_eq!(query(&val, q).unwrap(), vec![expected], "{q}");
+        }
+        // Values that aren't part of the input have no path
+        for q in ["1 = 2", "(.a | length) |= 3", "del(1)"] {
+            let err = query(&val, q).unwrap_err().to_string();
+            assert!(err.contains("Invalid path expression"), "{q}: {err}");
+        }
+    }
+
+    #[test]
+    fn query_stderr_passes_through() {
+        let val = json!({"a": [1, "x"]});
+        assert_eq!(query(&val, "stderr").unwrap(), vec![val.clone()]);
+        assert_eq!(query(&val, ".a[] | stderr").unwrap(), vec![json!(1), json!("x")]);
+    }
+
+    #[test]
+    fn query_tostring_numbers_like_jq() {
+        let n = json!(null);
+        assert_eq!(query(&n, "1.0 | tostring").unwrap(), vec![json!("1")]);
+        assert_eq!(query(&n, "1.5 | tostring").unwrap(), vec![json!("1.5")]);
+        assert_eq!(query(&n, "1e100 | tostring").unwrap(), vec![json!("1e+100")]);
+        assert_eq!(query(&n, "[1.0, 2.5] | tostring").unwrap(), vec![json!("[1,2.5]")]);
+        assert_eq!(query(&n, r#""v\(1.0)""#).unwrap(), vec![json!("v1")]);
+    }
+
+    #[test]
+    fn query_extended_math() {
+        let approx = |q: &str, input: Value, expected: f64| {
+            let got = query(&input, q).unwrap()[0].as_f64().unwrap();
+            assert!((got - expected).abs() < 1e-12, "{q}: {got} != {expected}");
+        };
+        approx("tanh", json!(0.5), 0.5f64.tanh());
+        approx("asinh", json!(1), 1f64.asinh());
+        approx("log1p", json!(1e-10), 1e-10f64.ln_1p());
+        assert_eq!(query(&json!(27), "cbrt").unwrap(), vec![json!(3)]);
+        assert_eq!(query(&json!(null), "hypot(3; 4)").unwrap(), vec![json!(5)]);
+        assert_eq!(query(&json!(null), "copysign(2; -1)").unwrap(), vec![json!(-2)]);
+        assert_eq!(query(&json!(-2.7), "trunc").unwrap(), vec![json!(-2)]);
+        assert_eq!(query(&json!(10), "logb").unwrap(), vec![json!(3)]);
+        assert_eq!(query(&json!(10), "significand").unwrap(), vec![json!(1.25)]);
+        // Exact at the edges of the exponent range, subnormals included
+        let max = json!(f64::MAX);
+        assert_eq!(query(&max, "logb").unwrap(), vec![json!(1023)]);
+        assert_eq!(query(&json!(f64::MIN_POSITIVE), "logb").unwrap(), vec![json!(-1022)]);
+        assert_eq!(query(&json!(5e-324), "logb").unwrap(), vec![json!(-1074)]);
+        assert_eq!(query(&json!(-3e-310), "logb").unwrap(), vec![json!(-1029)]);
+        assert_eq!(query(&json!(5e-324), "significand").unwrap(), vec![json!(1)]);
+        assert_eq!(query(&max, "significand").unwrap()[0], json!(2.0 - f64::EPSILON));
+    }
+
+    #[test]
+    fn query_type_selectors() {
+        let val = json!([null, true, 1, "s", [2], {"k": 3}]);
+        let select = |f: &str| query(&val, &format!("[.[] | {f}]")).unwrap();
+        assert_eq!(select("values"), vec![json!([true, 1, "s", [2], {"k": 3}])]);
+        assert_eq!(select("nulls"), vec![json!([null])]);
+        assert_eq!(select("booleans"), vec![json!([true])]);
+        assert_eq!(select("numbers"), vec![json!([1])]);
+        assert_eq!(select("strings"), vec![json!(["s"])]);
+        assert_eq!(select("arrays"), vec![json!([[2]])]);
+        assert_eq!(select("objects"), vec![json!([{"k": 3}])]);
+        assert_eq!(select("iterables"), vec![json!([[2], {"k": 3}])]);
+        assert_eq!(select("scalars"), vec![json!([null, true, 1, "s"])]);
+        assert_eq!(query(&json!(null), "values").unwrap(), Vec::<Value>::new());
+    }
+
+    #[test]
+    fn query_sandboxed_env() {
+        let env = env::Env::sandboxed();
+        let val = json!(null);
+        assert_eq!(query_with_env(&val, "env", &env).unwrap(), vec![json!({})]);
+        assert_eq!(query_with_env(&val, "$ENV", &env).unwrap(), vec![json!({})]);
+        assert_eq!(query_with_env(&val, "[inputs]", &env).unwrap(), vec![json!([])]);
+        assert!(query_with_env(&val, "input", &env).is_err());
+        // The default environment still sees the process environment
+        assert_eq!(query(&val, "env | has(\"PATH\")").unwrap(), vec![json!(true)]);
+    }
+
+    #[test]
+    fn query_limits() {
+        let mut env = env::Env::new();
+        env.set_max_outputs(Some(100));
+        let n = json!(null);
+        let err = query_with_env(&n, "[range(1e12)]", &env).unwrap_err();
+        assert!(err.to_string().contains("output limit of 100 exceeded"));
+        assert!(query_with_env(&n, "[repeat(1)]", &env).is_err());
+        assert!(query_with_env(&n, "[0 | while(true; . + 1)]", &env).is_err());
+        assert!(query_with_env(&n, "[range(200)] | .[]", &env).is_err());
+        // Lazy consumers stop before the limit is reached
+        assert_eq!(
+            query_with_env(&n, "[limit(3; range(1e12))]", &env).unwrap(),
+            vec![json!([0, 1, 2])]
+        );
+        assert_eq!(query_with_env(&n, "[range(100)] | length", &env).unwrap(), vec![json!(100)]);
+
+        let mut env = env::Env::new();
+        env.set_max_depth(Some(50));
+        let err = query_with_env(&n, "def f: f; f", &env).unwrap_err();
+        assert!(err.to_string().contains("recursion depth limit of 50 exceeded"));
+        // Loops and recurse chains are iterations, not nested calls
+        assert_eq!(
+            query_with_env(&n, "0 | until(. >= 5000; . + 1)", &env).unwrap(),
+            vec![json!(5000)]
+        );
+        assert_eq!(
+            query_with_env(&n, "[0 | while(. < 5000; . + 1)] | length", &env).unwrap(),
+            vec![json!(5000)]
+        );
+        assert_eq!(
+            query_with_env(&n, "[limit(2000; repeat(1))] | length", &env).unwrap(),
+            vec![json!(2000)]
+        );
+        assert_eq!(
+            query_with_env(&n, "[0 | recurse(if . < 50 then . + 1 else empty end)] | length", &env)
+                .unwrap(),
+            vec![json!(51)]
+        );
+    }
+
+    #[test]
+    fn query_recurse_with_filter() {
+        let tree = json!({"n": 1, "c": [{"n": 2, "c": [{"n": 3, "c": []}]}, {"n": 4, "c": []}]});
+        // Depth-first, in the order the filter yields children
+        assert_eq!(query(&tree, "[recurse(.c[]) | .n]").unwrap(), vec![json!([1, 2, 3, 4])]);
+
+        let list = json!({"v": 1, "next": {"v": 2, "next": {"v": 3, "next": null}}});
+        assert_eq!(
+            query(&list, "[recurse(.next; . != null) | .v]").unwrap(),
+            vec![json!([1, 2, 3])]
+        );
+        assert_eq!(
+            query(&json!(2), "[recurse(if . < 4 then . + 1 else empty end)]").unwrap(),
+            vec![json!([2, 3, 4])]
+        );
+
+        // Errors from f propagate unless caught
+        assert!(query(&json!({"a": 1}), "[recurse(.[])]").is_err());
+        assert_eq!(query(&json!({"a": 1}), "[recurse(.[]?)]").unwrap(), vec![json!([{"a": 1}, 1])]);
+
+        // Long chains are walked without a cap
+        let n = json!(0);
+        assert_eq!(
+            query(&n, "[recurse(if . < 5000 then . + 1 else empty end)] | length").unwrap(),
+            vec![json!(5001)]
+        );
+    }
+
+    #[test]
+    fn query_any_all_generators() {
+        let n = json!(null);
+        assert_eq!(query(&n, "any(range(10); . == 3)").unwrap(), vec![json!(true)]);
+        assert_eq!(query(&n, "any(range(10); . > 10)").unwrap(), vec![json!(false)]);
+        assert_eq!(query(&n, "all(range(10); . < 10)").unwrap(), vec![json!(true)]);
+        assert_eq!(query(&n, "all(empty; false)").unwrap(), vec![json!(true)]);
+        assert_eq!(query(&n, "any(empty; true)").unwrap(), vec![json!(false)]);
+        // Infinite generators stop at the first decisive value
+        assert_eq!(query(&n, "any(repeat(1); . == 1)").unwrap(), vec![json!(true)]);
+        assert_eq!(query(&n, "all(range(1e12); . < 5)").unwrap(), vec![json!(false)]);
+        // Nothing past the decisive value is evaluated
+        assert_eq!(query(&n, "any(1, error(\"x\"); . == 1)").unwrap(), vec![json!(true)]);
+        assert_eq!(query(&n, "all(1, error(\"x\"); . == 2)").unwrap(), vec![json!(false)]);
+    }
+
+    #[test]
+    fn query_getpath_indices() {
+        let val = json!({"a": [1, 2, 3], "o": {"k": "v"}});
+        let get = |path: &str| query(&val, &format!("getpath({path})")).unwrap();
+        assert_eq!(get(r#"["a", -1]"#), vec![json!(3)]);
+        assert_eq!(get(r#"["a", -3]"#), vec![json!(1)]);
+        assert_eq!(get(r#"["a", -4]"#), vec![json!(null)]);
+        assert_eq!(get(r#"["a", 3]"#), vec![json!(null)]);
+        assert_eq!(get(r#"["a", 1.5]"#), vec![json!(2)]);
+        // Type mismatches read as null rather than erroring
+        assert_eq!(get(r#"["a", "x"]"#), vec![json!(null)]);
+        assert_eq!(get(r#"["o", 0]"#), vec![json!(null)]);
+        assert_eq!(get(r#"["o", "k", "deeper"]"#), vec![json!(null)]);
+        // Same results as the equivalent path expressions
+        assert_eq!(get(r#"["a", -1]"#), query(&val, ".a[-1]").unwrap());
+    }
+
+    #[test]
+    fn query_match_global_and_named() {
+        let s = json!("a1 b22 c333");
+        let q = r#"[match("[a-z](\\d+)"; "g") | [.offset, .length, .captures[0].string]]"#;
+        let offsets = query(&s, q).unwrap();
+        assert_eq!(offsets, vec![json!([[0, 2, "1"], [3, 3, "22"], [7, 4, "333"]])]);
+        // Without g only the first match; no match means no output
+        assert_eq!(query(&s, r#"[match("\\d+") | .string]"#).unwrap(), vec![json!(["1"])]);
+        assert_eq!(query(&s, r#"[match("z")]"#).unwrap(), vec![json!([])]);
+
+        let m = query(&json!("key=val"), r#"match("(?<k>\\w+)=(\\w+)(x)?")"#).unwrap();
+        assert_eq!(
+            m[0]["captures"],
+            json!([
+                {"offset": 0, "length": 3, "string": "key", "name": "k"},
+                {"offset": 4, "length": 3, "string": "val", "name": null},
+                {"offset": -1, "length": 0, "string": null, "name": null}
+            ])
+        );
+        // Offsets count codepoints, not bytes
+        assert_eq!(query(&json!("été x"), r#"match("x").offset"#).unwrap(), vec![json!(4)]);
+    }
+
+    #[test]
+    fn query_tostream_fromstream() {
+        let val = json!({"a": [1, {"b": null}], "c": {}, "d": "x"});
+        let events = query(&val, "[tostream]").unwrap();
+        assert_eq!(
+            events,
+            vec![json!([
+                [["a", 0], 1],
+                [["a", 1, "b"], null],
+                [["a", 1, "b"]],
+                [["a", 1]],
+                [["c"], {}],
+                [["d"], "x"],
+                [["d"]]
+            ])]
+        );
+        for v in [val, json!(3), json!([]), json!([[1], [[2]]])] {
+            let round_trip = query(&v, ". as $x | [tostream] | fromstream(.[]) == $x").unwrap();
+            assert_eq!(round_trip, vec![json!(true)], "{v}");
+        }
+        // Consecutive top-level values are emitted one by one
+        let many = query(&json!(null), "[fromstream(([1], 2, {}) | tostream)]").unwrap();
+        assert_eq!(many, vec![json!([[1], 2, {}])]);
+        assert!(query(&json!(null), "fromstream(1)").is_err());
+    }
+
+    #[test]
+    fn query_trim_and_affix_generator_args() {
+        let s = json!("foobar");
+        let q = |f: &str| query(&s, f).unwrap();
+        assert_eq!(q(r#"[ltrimstr("foo", "f", "x")]"#), vec![json!(["bar", "oobar", "foobar"])]);
+        assert_eq!(q(r#"[rtrimstr("bar", "r")]"#), vec![json!(["foo", "fooba"])]);
+        assert_eq!(q(r#"[startswith("foo", "bar")]"#), vec![json!([true, false])]);
+        assert_eq!(q(r#"[endswith("foo", "bar")]"#), vec![json!([false, true])]);
+        // Non-string arguments leave the input alone, one copy each
+        assert_eq!(q(r#"[ltrimstr(1, "f")]"#), vec![json!(["foobar", "oobar"])]);
+        assert_eq!(q(r#"[ltrimstr(empty)]"#), vec![json!([])]);
+        assert!(query(&s, r#"[startswith("f", 1)]"#).is_err());
+    }
+
+    #[test]
+    fn query_length_and_utf8bytelength() {
+        assert_eq!(query(&json!("é"), "[length, utf8bytelength]").unwrap(), vec![json!([1, 2])]);
+        assert_eq!(query(&json!(null), "length").unwrap(), vec![json!(0)]);
+        assert_eq!(query(&json!(-3), "length").unwrap(), vec![json!(3)]);
+        assert!(query(&json!(true), "length").is_err());
+        for input in [json!({"a": 1}), json!([1]), json!(null), json!(1), json!(true)] {
+            let err = query(&input, "utf8bytelength").unwrap_err();
+            assert!(err.to_string().contains("only strings have UTF-8 byte length"), "{input}");
+        }
+    }
+
+    #[test]
+    fn query_by_composite_keys() {
+        let val = json!([
+            {"a": 2, "b": 1, "n": "w"},
+            {"a": 1, "b": 2, "n": "x"},
+            {"a": 2, "b": 0, "n": "y"},
+            {"a": 1, "b": 1, "n": "z"}
+        ]);
+        let q = |f: &str| query(&val, f).unwrap();
+        assert_eq!(q("sort_by(.a, .b) | map(.n)"), vec![json!(["z", "x", "y", "w"])]);
+        assert_eq!(q("sort_by(.a, -.b) | map(.n)"), vec![json!(["x", "z", "w", "y"])]);
+        assert_eq!(q("min_by(.a, .b).n"), vec![json!("z")]);
+        assert_eq!(q("max_by(.a, .b).n"), vec![json!("w")]);
+        assert_eq!(
+            q("group_by(.a, .b > 0) | map(map(.n))"),
+            vec![json!([["x", "z"], ["y"], ["w"]])]
+        );
+        assert_eq!(q("unique_by(.a, .b > 0) | map(.n)"), vec![json!(["w", "x", "y"])]);
+        // Errors in the key filter are not swallowed
+        assert!(query(&val, "sort_by(error)").is_err());
+    }
+
+    #[test]
+    fn query_regex_flags() {
+        let s = json!("a-b");
+        // n drops empty matches
+        assert_eq!(query(&s, r#"[match("x*"; "g") | .offset]"#).unwrap(), vec![json!([0, 1, 2, 3])]);
+        assert_eq!(query(&s, r#"[match("x*"; "gn")]"#).unwrap(), vec![json!([])]);
+        assert_eq!(query(&s, r#"scan("[a-z]*"; "n")"#).unwrap(), vec![json!(["a", "b"])]);
+        assert_eq!(query(&s, r#"gsub("x*"; "."; "n")"#).unwrap(), vec![json!("a-b")]);
+        // sub with g replaces every match
+        assert_eq!(query(&s, r#"sub("[a-z]"; "X"; "g")"#).unwrap(), vec![json!("X-X")]);
+        assert_eq!(query(&s, r#"sub("[A-Z]"; "X"; "il")"#).unwrap(), vec![json!("X-b")]);
+        assert!(query(&s, r#"test("a"; "z")"#).is_err());
+        // Overlapping flags combine
+        let lines = json!("x\nA.b");
+        assert_eq!(query(&lines, r#"test("a.B"; "ii")"#).unwrap(), vec![json!(true)]);
+        assert_eq!(query(&lines, r#"test("^a.B$"; "ipm")"#).unwrap(), vec![json!(true)]);
+        assert_eq!(query(&lines, r#"test("x.a"; "ps")"#).unwrap(), vec![json!(false)]);
+        assert_eq!(query(&lines, r#"test("x.A"; "ps")"#).unwrap(), vec![json!(true)]);
+    }
+
+    #[test]
+    fn query_typed_paths() {
+        let val = json!({"a": [1, {"b": null}], "c": {"d": "x", "e": []}});
+        assert_eq!(
+            query(&val, "[scalar_paths]").unwrap(),
+            vec![json!([["a", 0], ["a", 1, "b"], ["c", "d"]])]
+        );
+        assert_eq!(
+            query(&val, "[array_paths]").unwrap(),
+            vec![json!([["a"], ["c", "e"]])]
+        );
+        assert_eq!(
+            query(&val, "[object_paths]").unwrap(),
+            vec![json!([["a", 1], ["c"]])]
+        );
+        // Unlike paths(scalars), null and false leaves are kept
+        assert_eq!(
+            query(&val, "[paths(scalars)]").unwrap(),
+            vec![json!([["a", 0], ["c", "d"]])]
+        );
+        assert_eq!(
+            query(&val, r#"[array_paths] == [paths(type == "array")]"#).unwrap(),
+            vec![json!(true)]
+        );
+        assert_eq!(query(&json!(1), "[array_paths]").unwrap(), vec![json!([])]);
+    }
+
+    #[test]
+    fn query_index_expression_context() {
+        let val = json!({"k": "x", "x": 42, "a": {"x": 1, "y": 2}, "ks": ["x", "y"]});
+        assert_eq!(query(&json!({"k": "x", "x": 42}), ".[.k]").unwrap(), vec![json!(42)]);
+        // The index is evaluated against `.`, not against `.a`
+        assert_eq!(query(&val, ".a[.k]").unwrap(), vec![json!(1)]);
+        assert_eq!(query(&val, "(.a[.k] = 5) | .a").unwrap(), vec![json!({"x": 5, "y": 2})]);
+        assert_eq!(query(&val, "del(.a[.k]) | .a").unwrap(), vec![json!({"y": 2})]);
+        // Generator indices yield one path per key
+        assert_eq!(
+            query(&val, "(.a[.ks[]] |= . * 10) | .a").unwrap(),
+            vec![json!({"x": 10, "y": 20})]
+        );
+    }
+
+    #[test]
+    fn query_csv_tsv_fields() {
+        let row = json!([1, 1.5, 2.0, null, true, "a,b", "c"]);
+        assert_eq!(query(&row, "@csv").unwrap(), vec![json!("1,1.5,2,,true,\"a,b\",c")]);
+        assert_eq!(query(&row, "@tsv").unwrap(), vec![json!("1\t1.5\t2\t\ttrue\ta,b\tc")]);
+        // A trailing null keeps its (empty) field
+        assert_eq!(query(&json!(["a", null]), "@tsv").unwrap(), vec![json!("a\t")]);
+        let err = query(&json!([1, [2]]), "@csv").unwrap_err();
+        assert!(err.to_string().contains("array ([2]) is not valid in a csv row"));
+        let err = query(&json!([{"a": 1}]), "@tsv").unwrap_err();
+        assert!(err.to_string().contains("object ({\"a\":1}) is not valid in a tsv row"));
+    }
+
+    #[test]
+    fn query_html_non_strings() {
+        assert_eq!(query(&json!(1.5), "@html").unwrap(), vec![json!("1.5")]);
+        assert_eq!(query(&json!(true), "@html").unwrap(), vec![json!("true")]);
+        assert_eq!(query(&json!(null), "@html").unwrap(), vec![json!("null")]);
+        // Collections are serialized first, then escaped
+        assert_eq!(
+            query(&json!({"a": "<b>"}), "@html").unwrap(),
+            vec![json!("{&quot;a&quot;:&quot;&lt;b&gt;&quot;}")]
+        );
+        // Existing entities are escaped once, not left alone
+        assert_eq!(
+            query(&json!("&amp; 'x'"), "@html").unwrap(),
+            vec![json!("&amp;amp; &#39;x&#39;")]
+        );
+    }
+
+    #[test]
+    fn query_unicode_case() {
+        let q = |v: Value, f: &str| query(&v, f).unwrap();
+        assert_eq!(q(json!("STRASSE"), "downcase"), vec![json!("strasse")]);
+        assert_eq!(q(json!("Straße"), "upcase"), vec![json!("STRASSE")]);
+        assert_eq!(q(json!("ÖL Ärger"), "downcase"), vec![json!("öl ärger")]);
+        assert_eq!(q(json!("crème brûlée"), "upcase"), vec![json!("CRÈME BRÛLÉE")]);
+        // The ascii_ variants leave non-ASCII letters alone
+        assert_eq!(q(json!("ÖL"), "ascii_downcase"), vec![json!("Öl")]);
+        assert!(query(&json!(1), "upcase").is_err());
+    }
+
+    #[test]
+    fn query_index_error_wording() {
+        let err = |v: Value, f: &str| query(&v, f).unwrap_err().to_string();
+        assert!(err(json!([1]), ".foo").ends_with(r#"Cannot index array with "foo""#));
+        assert!(err(json!([1]), r#".["foo"]"#).ends_with(r#"Cannot index array with "foo""#));
+        assert!(err(json!({"a": 1}), ".[0]").ends_with("Cannot index object with number"));
+        assert!(err(json!(1), ".a").ends_with(r#"Cannot index number with "a""#));
+        assert!(err(json!("s"), ".[0]").ends_with("Cannot index string with number"));
+        assert!(err(json!({}), ".[null]").ends_with("Cannot index object with null"));
+        // Iterating is still fine, as is indexing null
+        assert_eq!(query(&json!([{"a": 1}]), ".[] | .a").unwrap(), vec![json!(1)]);
+        assert_eq!(query(&json!(null), ".a, .[0]").unwrap(), vec![json!(null), json!(null)]);
+    }
+
+    #[test]
+    fn query_stepped_slices() {
+        let arr = json!([0, 1, 2, 3, 4, 5]);
+        let q = |v: &Value, f: &str| query(v, f).unwrap();
+        assert_eq!(q(&arr, ".[::2]"), vec![json!([0, 2, 4])]);
+        assert_eq!(q(&arr, ".[::-1]"), vec![json!([5, 4, 3, 2, 1, 0])]);
+        assert_eq!(q(&arr, ".[1:5:2]"), vec![json!([1, 3])]);
+        assert_eq!(q(&arr, ".[4:1:-2]"), vec![json!([4, 2])]);
+        assert_eq!(q(&arr, ".[-2::-3]"), vec![json!([4, 1])]);
+        assert_eq!(q(&arr, ".[10:0:1]"), vec![json!([])]);
+        assert_eq!(q(&arr, ".[:]"), vec![arr.clone()]);
+        assert_eq!(q(&json!("héllo"), ".[::-1]"), vec![json!("olléh")]);
+        assert_eq!(q(&json!("abcdef"), ".[1::2]"), vec![json!("bdf")]);
+        // Stepped slices are paths to each picked item
+        assert_eq!(q(&arr, ".[::2] |= . * 10"), vec![json!([0, 1, 20, 3, 40, 5])]);
+        assert_eq!(q(&arr, "del(.[::-2])"), vec![json!([0, 2, 4])]);
+        assert!(query(&arr, ".[::0]").is_err());
+        assert_eq!(q(&arr, ".[::-9223372036854775808]"), vec![json!([5])]);
+        assert_eq!(q(&arr, ".[1::9223372036854775807]"), vec![json!([1])]);
+        let err = query(&arr, ".[::1e30]").unwrap_err().to_string();
+        assert!(err.contains("slice step must be an integer"), "{err}");
+        // Plain slices of strings count characters too
+        assert_eq!(q(&json!("héllo"), ".[1:2]"), vec![json!("é")]);
+        assert_eq!(q(&json!("héllo"), ".[1:3]"), vec![json!("él")]);
+        assert_eq!(q(&json!("héllo"), ".[-2:]"), vec![json!("lo")]);
+    }
+
+    #[test]
+    fn query_tabulate() {
+        let rows = json!([{"name": "a", "n": 1}, {"n": 2, "extra": true}, {"name": "c,d"}]);
+        assert_eq!(
+            query(&rows, "tabulate").unwrap(),
+            vec![json!([
+                ["n", "name", "extra"],
+                [1, "a", null],
+                [2, null, true],
+                [null, "c,d", null]
+            ])]
+        );
+        assert_eq!(
+            query(&rows, "[tabulate[] | @csv]").unwrap(),
+            vec![json!(["n,name,extra", "1,a,", "2,,true", ",\"c,d\","])]
+        );
+        assert_eq!(query(&json!([]), "tabulate").unwrap(), vec![json!([])]);
+        assert_eq!(query(&json!([]), "[tabulate[] | @csv]").unwrap(), vec![json!([])]);
+        assert!(query(&json!([1]), "tabulate").is_err());
+    }
+
+    #[test]
+    fn query_yaml_format() {
+        let val = json!({"settings": {"port": 80, "hosts": ["a", "b"]}, "list": [1, {"k": "v"}]});
+        assert_eq!(
+            query(&val, ".settings | @yaml").unwrap(),
+            vec![json!("hosts:\n- a\n- b\nport: 80")]
+        );
+        assert_eq!(query(&val, ".list | @yaml").unwrap(), vec![json!("- 1\n- k: v")]);
+        assert_eq!(
+            query(&val, r#""config:\n\(.settings.hosts | @yaml)""#).unwrap(),
+            vec![json!("config:\n- a\n- b")]
+        );
+        // Strings that would read back as another type are quoted
+        assert_eq!(query(&json!("true"), "@yaml").unwrap(), vec![json!("'true'")]);
+    }
+
+    #[test]
+    fn query_min_max_ties() {
+        let val = json!([
+            {"k": 1, "id": "a"},
+            {"k": 0, "id": "b"},
+            {"k": 1, "id": "c"},
+            {"k": 0, "id": "d"}
+        ]);
+        assert_eq!(query(&val, "min_by(.k).id").unwrap(), vec![json!("b")]);
+        assert_eq!(query(&val, "max_by(.k).id").unwrap(), vec![json!("c")]);
+        let pairs = json!([[1, "x"], [0, "y"], [1, "z"], [0, "w"]]);
+        assert_eq!(query(&pairs, "min_by(.[0])").unwrap(), vec![json!([0, "y"])]);
+        assert_eq!(query(&pairs, "max_by(.[0])").unwrap(), vec![json!([1, "z"])]);
+    }
+
+    #[test]
+    fn query_env_is_read_once() {
+        let env = env::Env::new();
+        let path = std::env::var("PATH").map(Value::String).unwrap_or(Value::Null);
+        assert_eq!(query_with_env(&json!(null), "$ENV.PATH", &env).unwrap(), vec![path]);
+        assert_eq!(
+            query_with_env(&json!([1, 2, 3]), "map($ENV == env) | all", &env).unwrap(),
+            vec![json!(true)]
+        );
+        // Clones share the snapshot rather than re-reading the process
+        let child = env.child();
+        assert!(std::ptr::eq(env.process_env(), child.process_env()));
+    }
+
+    #[test]
+    fn query_set_negative_index() {
+        let arr = json!([1, 2, 3]);
+        assert_eq!(query(&arr, "setpath([-1]; 9)").unwrap(), vec![json!([1, 2, 9])]);
+        assert_eq!(query(&arr, ".[-3] = 0").unwrap(), vec![json!([0, 2, 3])]);
+        assert_eq!(query(&arr, ".[-2] |= . * 10").unwrap(), vec![json!([1, 20, 3])]);
+        for q in ["setpath([-4]; 9)", ".[-10] = 0"] {
+            let err = query(&arr, q).unwrap_err();
+            assert!(err.to_string().contains("Out of bounds negative array index"), "{q}");
+        }
+        assert!(query(&json!(null), "setpath([-1]; 1)").is_err());
+        // Past the end still pads with nulls
+        assert_eq!(query(&arr, ".[4] = 5").unwrap(), vec![json!([1, 2, 3, null, 5])]);
+    }
+
+    #[test]
+    fn query_has_and_in_type_errors() {
+        let err = query(&json!(null), r#"has("a")"#).unwrap_err();
+        assert!(err.to_string().contains("Cannot check whether null has a string key"));
+        let err = query(&json!({"a": 1}), "has(0)").unwrap_err();
+        assert!(err.to_string().contains("Cannot check whether object has a number key"));
+        // `in` checks array indices just like `has`
+        assert_eq!(
+            query(&json!([0, 1, 2, 5]), "map(in([10, 20, 30]))").unwrap(),
+            vec![json!([true, true, true, false])]
+        );
+        assert_eq!(query(&json!("b"), r#"in({"b": 1})"#).unwrap(), vec![json!(true)]);
+        let err = query(&json!("a"), "in([1])").unwrap_err();
+        assert!(err.to_string().contains("Cannot check whether array has a string key"));
+    }
+
+    #[test]
+    fn query_index_error_names_path() {
+        let val = json!({"a": {"b": "x", "c": [1]}});
+        let cases = [
+            (".a.b.c", r#"Cannot index string at .a.b with "c""#),
+            (".a.b.c.d", r#"Cannot index string at .a.b with "c""#),
+            (".a.c[0].d", r#"Cannot index number at .a.c[0] with "d""#),
+            (r#".a["c"].d"#, r#"Cannot index array at .a["c"] with "d""#),
+            (".a.b[0]", "Cannot index string at .a.b with number"),
+            // Locations are relative to the input across pipes
+            (".a | .b.c", r#"Cannot index string at .a.b with "c""#),
+            (".a | .b | .c", r#"Cannot index string at .a.b with "c""#),
+            (r#".a | .c | .[0].d"#, r#"Cannot index number at .a.c[0] with "d""#),
+            ("def f: .b.c; .a | f", r#"Cannot index string at .a.b with "c""#),
+        ];
+        for (q, expected) in cases {
+            let err = query(&val, q).unwrap_err().to_string();
+            assert!(err.contains(expected), "{q}: {err}");
+        }
+        // Without a path to report, the message is jq's
+        let err = query(&json!("x"), ".c").unwrap_err().to_string();
+        assert!(err.ends_with(r#"Cannot index string with "c""#), "{err}");
+        // Nor when the failing value isn't at a fixed path in the input
+        let unlocated = [
+            ".[] | .b.c",
+            "first(.a) | .b.c",
+            "[.a] | map(.b.c)",
+            "reduce 1 as $x (.a; .b.c)",
+        ];
+        for q in unlocated {
+            let err = query(&val, q).unwrap_err().to_string();
+            assert!(err.ends_with(r#"Cannot index string with "c""#), "{q}: {err}");
+        }
+    }
+
+    #[test]
+    fn query_tsv_escapes_fields() {
+        let row = json!(["a\tb", "line\nbreak\r", "back\\slash", "\"quoted\""]);
+        assert_eq!(
+            query(&row, "@tsv").unwrap(),
+            vec![json!("a\\tb\tline\\nbreak\\r\tback\\\\slash\t\"quoted\"")]
+        );
+        // @csv still quotes instead
+        assert_eq!(query(&json!(["a\nb"]), "@csv").unwrap(), vec![json!("\"a\nb\"")]);
+    }
+
+    #[test]
+    fn query_destructuring_alternatives() {
+        let q = ". as [$a] ?// {$a} | $a";
+        assert_eq!(query(&json!([1, 2]), q).unwrap(), vec![json!(1)]);
+        assert_eq!(query(&json!({"a": 3}), q).unwrap(), vec![json!(3)]);
+        // Variables only the other alternative binds are null
+        let q = ". as [$a] ?// {b: $b} | [$a, $b]";
+        assert_eq!(query(&json!({"b": 4}), q).unwrap(), vec![json!([null, 4])]);
+        assert_eq!(query(&json!([5]), q).unwrap(), vec![json!([5, null])]);
+        // The last alternative's error is reported when none fit
+        assert!(query(&json!("s"), q).is_err());
+        assert_eq!(
+            query(&json!([[1], {"a": 2}]), "reduce .[] as [$a] ?// {$a} (0; . + $a)").unwrap(),
+            vec![json!(3)]
+        );
+        // An error in the body moves on to the next alternative
+        let q = r#".[] as [$a] ?// [$b] | if $a != null then error("err: \($a)") else {$a,$b} end"#;
+        assert_eq!(query(&json!([[3]]), q).unwrap(), vec![json!({"a": null, "b": 3})]);
+        let err = query(&json!([[3]]), ". as [$a] ?// [$b] | error(\"last\")").unwrap_err();
+        assert!(err.to_string().contains("last"));
+    }
+
+    #[test]
+    fn query_try_keeps_earlier_outputs() {
+        let val = json!(null);
+        assert_eq!(query(&val, r#"[(1, error("x"), 3)?]"#).unwrap(), vec![json!([1])]);
+        assert_eq!(
+            query(&val, r#"[try (1, error("x"), 3) catch "caught"]"#).unwrap(),
+            vec![json!([1, "caught"])]
+        );
+        // The whole generator stops at its first error
+        let val = json!(["1", "x", "3"]);
+        assert_eq!(query(&val, "[(.[] | tonumber)?]").unwrap(), vec![json!([1])]);
+        assert_eq!(query(&val, "[.[] | tonumber?]").unwrap(), vec![json!([1, 3])]);
+    }
+
+    #[test]
+    fn query_and_or_short_circuit() {
+        let val = json!(null);
+        assert_eq!(query(&val, r#"false and error("x")"#).unwrap(), vec![json!(false)]);
+        assert_eq!(query(&val, r#"true or error("x")"#).unwrap(), vec![json!(true)]);
+        assert!(query(&val, r#"true and error("x")"#).is_err());
+        assert!(query(&val, r#"false or error("x")"#).is_err());
+        assert_eq!(query(&val, "[null, 1] | map(. and true)").unwrap(), vec![json!([false, true])]);
+    }
+
+    #[test]
+    fn query_and_or_output_order() {
+        // The left side is the outer loop; a false left output (true for
+        // `or`) gives one result without running the right side
+        let val = json!(null);
+        let cases = [
+            ("[(true, false) and (true, false)]", json!([true, false, false])),
+            ("[(true, false) or (true, false)]", json!([true, true, false])),
+            ("[(1, null) and (false, 2)]", json!([false, true, false])),
+            (
+                "[(true, false) and (true, false) and (true, false)]",
+                json!([true, false, false, false]),
+            ),
+            ("[(false, true) or (false, true) or false]", json!([false, true, true])),
+        ];
+        for (q, expected) in cases {
+            assert_eq!(query(&val, q).unwrap(), vec![expected], "{q}");
+        }
+    }
+
+    #[test]
+    fn query_alternative_catches_left_errors() {
+        let val = json!({"a": "x"});
+        assert_eq!(query(&val, r#"(error("x") // 42)"#).unwrap(), vec![json!(42)]);
+        assert_eq!(query(&val, "(.a | tonumber) // 0").unwrap(), vec![json!(0)]);
+        // Outputs before the error still count
+        assert_eq!(query(&val, r#"[(1, error("x")) // 2]"#).unwrap(), vec![json!([1])]);
+        assert_eq!(query(&val, r#"[(null, error("x")) // 2]"#).unwrap(), vec![json!([2])]);
+        // Errors on the right are not caught
+        assert!(query(&val, r#"null // error("y")"#).is_err());
+    }
+
+    #[test]
+    fn query_format_string_interpolation() {
+        let val = json!({"x": "hi", "user": "ab", "pass": 5, "h": "<b>"});
+        assert_eq!(query(&val, r#"@base64 "\(.x)""#).unwrap(), vec![json!("aGk=")]);
+        // Only the interpolated values are formatted, not the literal text
+        assert_eq!(
+            query(&val, r#"@base64 "\(.user):\(.pass)""#).unwrap(),
+            vec![json!("YWI=:NQ==")]
+        );
+        assert_eq!(
+            query(&val, r#"@html "<i>\(.h)</i>""#).unwrap(),
+            vec![json!("<i>&lt;b&gt;</i>")]
+        );
+        assert_eq!(query(&val, r#"{q: @uri "q=\(.h)"}"#).unwrap(), vec![json!({"q": "q=%3Cb%3E"})]);
+        // A plain format still applies to the input
+        assert_eq!(query(&json!("hi"), "@base64").unwrap(), vec![json!("aGk=")]);
+    }
+
+    #[test]
+    fn query_index_builds_object_by_key() {
+        let val = json!([{"id": 1, "n": "a"}, {"id": 2, "n": "b"}, {"id": 1, "n": "c"}]);
+        // Later rows win, and keys go through tostring
+        assert_eq!(
+            query(&val, "INDEX(.id)").unwrap(),
+            vec![json!({"1": {"id": 1, "n": "c"}, "2": {"id": 2, "n": "b"}})]
+        );
+        assert_eq!(
+            query(&val, "INDEX(.[] | select(.id == 1); .n) | keys").unwrap(),
+            vec![json!(["a", "c"])]
+        );
+        assert_eq!(query(&json!(null), "INDEX(empty; .)").unwrap(), vec![json!({})]);
+    }
+
+    #[test]
+    fn query_group_by_and_unique_by_keep_input_order() {
+        let val = json!([{"k": "b", "v": 1}, {"k": "a", "v": 2}, {"k": "b", "v": 3}]);
+        assert_eq!(
+            query(&val, "GROUP_BY(.k) | map_values(map(.v))").unwrap(),
+            vec![json!({"a": [2], "b": [1, 3]})]
+        );
+        assert_eq!(query(&val, "UNIQUE_BY(.k) | map(.v)").unwrap(), vec![json!([1, 2])]);
+        assert!(query(&json!(5), "GROUP_BY(.)").is_err());
+    }
+
+    #[test]
+    fn query_recurse_is_lazy() {
+        let val = json!(null);
+        assert_eq!(
+            query(&val, "[limit(5; 0 | recurse(. + 1))]").unwrap(),
+            vec![json!([0, 1, 2, 3, 4])]
+        );
+        assert_eq!(
+            query(&val, "first(0 | recurse(. + 1) | select(. > 3))").unwrap(),
+            vec![json!(4)]
+        );
+        let until_break = "[label $out | 0 | recurse(. + 1) | if . > 2 then ., break $out end]";
+        assert_eq!(query(&val, until_break).unwrap(), vec![json!([0, 1, 2, 3])]);
+        assert_eq!(query(&val, "[label $f | 1, break $f, 2]").unwrap(), vec![json!([1])]);
+    }
 }
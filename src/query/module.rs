@@ -0,0 +1,206 @@
+use std::path::{Path, PathBuf};
+
+use serde_json::Value;
+
+use crate::error::QfError;
+
+use super::ast::{Def, Directive, Expr, Pattern};
+use super::env::Env;
+use super::jq_parser::Parser;
+use super::lexer::Lexer;
+
+/// Resolve the `include`/`import` directives heading a program, wrapping
+/// `body` in the definitions and data they bring in.
+///
+/// Module paths are looked up relative to the including module's directory
+/// (for modules loading other modules), then in each `--library-path`
+/// directory, or the current directory when none is given. `"a/b"` names
+/// `a/b.jq` or `a/b/b.jq`; data imports read `a/b.json`.
+pub fn link(directives: &[Directive], body: Expr, env: &Env) -> Result<Expr, QfError> {
+    if directives.is_empty() {
+        return Ok(body);
+    }
+    if !env.allow_env() {
+        return Err(QfError::Runtime(
+            "modules cannot be loaded in a sandboxed environment".into(),
+        ));
+    }
+    let search = if env.library_path().is_empty() {
+        vec![PathBuf::from(".")]
+    } else {
+        env.library_path().to_vec()
+    };
+    let mut loader = Loader {
+        search,
+        loading: Vec::new(),
+        data: Vec::new(),
+    };
+    let defs = loader.directives(directives, None)?;
+    let body = wrap(defs, body);
+    Ok(loader.data.into_iter().rev().fold(body, |body, (name, value)| Expr::As {
+        expr: Box::new(Expr::Literal(value)),
+        pattern: Pattern::Variable(name),
+        body: Box::new(body),
+    }))
+}
+
+struct Loader {
+    search: Vec<PathBuf>,
+    /// Modules being loaded, innermost last, to catch cycles.
+    loading: Vec<PathBuf>,
+    /// `$name::name` bindings from data imports.
+    data: Vec<(String, Value)>,
+}
+
+impl Loader {
+    /// The definitions `directives` bring in, in order.
+    fn directives(
+        &mut self,
+        directives: &[Directive],
+        origin: Option<&Path>,
+    ) -> Result<Vec<Def>, QfError> {
+        let mut defs = Vec::new();
+        for directive in directives {
+            match directive {
+                Directive::Include(path) => defs.extend(self.module(path, origin)?),
+                Directive::Import { path, alias } => {
+                    let module = self.module(path, origin)?;
+                    defs.extend(namespaced(&module, alias));
+                }
+                Directive::ImportData { path, alias } => {
+                    let file = self.find(path, "json", origin)?;
+                    let text = read(&file)?;
+                    let values = serde_json::Deserializer::from_str(&text)
+                        .into_iter::<Value>()
+                        .collect::<Result<Vec<_>, _>>()
+                        .map_err(|e| QfError::Parse(format!("{}: {e}", file.display())))?;
+                    self.data.push((format!("{alias}::{alias}"), Value::Array(values)));
+                }
+            }
+        }
+        Ok(defs)
+    }
+
+    /// Every definition of the module at `path`, including those it
+    /// includes itself.
+    fn module(&mut self, path: &str, origin: Option<&Path>) -> Result<Vec<Def>, QfError> {
+        let file = self.find(path, "jq", origin)?;
+        if self.loading.contains(&file) {
+            return Err(QfError::Runtime(format!("module {path:?} includes itself")));
+        }
+        let text = read(&file)?;
+        // Syntax errors stay syntax errors, naming the module they are in
+        let (directives, own) = parse_module(&text).map_err(|e| match e {
+            QfError::SyntaxError { position, message } => QfError::SyntaxError {
+                position,
+                message: format!("{}: {message}", file.display()),
+            },
+            e => QfError::Parse(format!("{}: {e}", file.display())),
+        })?;
+        self.loading.push(file.clone());
+        let mut defs = self.directives(&directives, file.parent())?;
+        self.loading.pop();
+        defs.extend(own);
+        Ok(defs)
+    }
+
+    fn find(&self, path: &str, ext: &str, origin: Option<&Path>) -> Result<PathBuf, QfError> {
+        let name = Path::new(path).file_name().and_then(|n| n.to_str()).unwrap_or(path);
+        let candidates = [format!("{path}.{ext}"), format!("{path}/{name}.{ext}")];
+        origin
+            .into_iter()
+            .chain(self.search.iter().map(PathBuf::as_path))
+            .flat_map(|dir| candidates.iter().map(move |c| dir.join(c)))
+            .find(|file| file.is_file())
+            .map(|file| file.canonicalize().unwrap_or(file))
+            .ok_or_else(|| QfError::Runtime(format!("module not found: {path}")))
+    }
+}
+
+fn parse_module(text: &str) -> Result<(Vec<Directive>, Vec<Def>), QfError> {
+    let mut lex = Lexer::new(text);
+    lex.tokenize()?;
+    Parser::new(lex.tokens).parse_module()
+}
+
+fn read(file: &Path) -> Result<String, QfError> {
+    std::fs::read_to_string(file)
+        .map_err(|e| QfError::Runtime(format!("reading {}: {e}", file.display())))
+}
+
+/// `body` with `defs` in scope, later ones shadowing earlier ones.
+fn wrap(defs: Vec<Def>, body: Expr) -> Expr {
+    defs.into_iter().rev().fold(body, |rest, def| Expr::FuncDef {
+        name: def.name,
+        params: def.params,
+        body: Box::new(def.body),
+        rest: Box::new(rest),
+    })
+}
+
+/// The module's definitions as `alias::name`. Each one brings the whole
+/// module into scope before calling the original, so its helpers stay out
+/// of the importing program.
+fn namespaced(module: &[Def], alias: &str) -> Vec<Def> {
+    module
+        .iter()
+        .map(|def| {
+            let args = def.params.iter().map(|p| Expr::VarRef(p.clone())).collect();
+            Def {
+                name: format!("{alias}::{}", def.name),
+                params: def.params.clone(),
+                body: wrap(module.to_vec(), Expr::FuncCall(def.name.clone(), args)),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn run(dir: &Path, query: &str) -> Result<Vec<Value>, QfError> {
+        let mut env = Env::new();
+        env.set_library_path(vec![dir.to_path_buf()]);
+        crate::query::query_with_env(&json!(null), query, &env)
+    }
+
+    #[test]
+    fn include_and_import_modules() {
+        let dir = tempfile::tempdir().unwrap();
+        let lib = dir.path();
+        std::fs::write(lib.join("util.jq"), "def twice: . * 2; def quad: twice | twice;").unwrap();
+        std::fs::create_dir(lib.join("str")).unwrap();
+        std::fs::write(lib.join("str/str.jq"), "include \"../util\"; def shout: . + \"!\";")
+            .unwrap();
+        std::fs::write(lib.join("nums.json"), "1 2").unwrap();
+
+        assert_eq!(run(lib, "include \"util\"; 3 | quad").unwrap(), vec![json!(12)]);
+        assert_eq!(
+            run(lib, "import \"str\" as s; \"hi\" | s::shout, (2 | s::quad)").unwrap(),
+            vec![json!("hi!"), json!(8)]
+        );
+        // Imported helpers are only reachable through the prefix
+        assert!(run(lib, "import \"util\" as u; 1 | twice").is_err());
+        assert_eq!(run(lib, "import \"nums\" as $n; $n::n").unwrap(), vec![json!([1, 2])]);
+    }
+
+    #[test]
+    fn module_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let lib = dir.path();
+        std::fs::write(lib.join("loop.jq"), "include \"loop\"; def f: 1;").unwrap();
+        std::fs::write(lib.join("bad.jq"), "def f: 1; 2").unwrap();
+
+        let err = |query: &str| run(lib, query).unwrap_err().to_string();
+        assert!(err("include \"missing\"; .").contains("module not found: missing"));
+        assert!(err("include \"loop\"; .").contains("includes itself"));
+        assert!(err("include \"bad\"; .").contains("bad.jq"));
+        let bad = run(lib, "include \"bad\"; .").unwrap_err();
+        assert_eq!(bad.kind(), "syntax_error");
+        let sandboxed = Env::sandboxed();
+        assert!(crate::query::query_with_env(&json!(null), "include \"x\"; .", &sandboxed)
+            .is_err());
+    }
+}
@@ -5,7 +5,8 @@ use crate::error::QfError;
 /// A single segment of a query path.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Segment {
-    /// Object key lookup: `.foo`
+    /// Object key lookup: `.foo`, or `.["my.key"]` for keys with dots,
+    /// brackets or spaces
     Key(String),
     /// Array index: `[0]`, `[42]`
     Index(usize),
@@ -21,6 +22,9 @@ pub struct QueryPath {
 
 impl QueryPath {
     /// Parse a query string like `.foo.bar[0].baz` or `.items[].name`.
+    ///
+    /// Keys that are not plain words go in quoted brackets, `.["a.b"]`,
+    /// using JSON string escapes.
     pub fn parse(input: &str) -> Result<Self, QfError> {
         let input = input.trim();
 
@@ -49,6 +53,33 @@ impl QueryPath {
                     // Iterator: []
                     segments.push(Segment::Iterator);
                     i += 1; // skip ']'
+                } else if i < chars.len() && chars[i] == '"' {
+                    // Quoted key: ["..."]
+                    let start = i;
+                    i += 1; // skip opening quote
+                    while i < chars.len() && chars[i] != '"' {
+                        if chars[i] == '\\' {
+                            i += 1; // skip the escaped character
+                        }
+                        i += 1;
+                    }
+                    if i >= chars.len() {
+                        return Err(QfError::InvalidQuery(format!(
+                            "unterminated key at position {start}"
+                        )));
+                    }
+                    i += 1; // skip closing quote
+                    let quoted: String = chars[start..i].iter().collect();
+                    let key: String = serde_json::from_str(&quoted).map_err(|_| {
+                        QfError::InvalidQuery(format!("invalid quoted key: {quoted}"))
+                    })?;
+                    if i >= chars.len() || chars[i] != ']' {
+                        return Err(QfError::InvalidQuery(format!(
+                            "expected ']' after key at position {i}"
+                        )));
+                    }
+                    segments.push(Segment::Key(key));
+                    i += 1; // skip ']'
                 } else {
                     // Index: [N]
                     let start = i;
@@ -278,6 +309,33 @@ mod tests {
         );
     }
 
+    #[test]
+    fn parse_quoted_keys() {
+        let p = QueryPath::parse(r#".["a.b"]"#).unwrap();
+        assert_eq!(p.segments, vec![Segment::Key("a.b".into())]);
+        let p = QueryPath::parse(r#".meta["has space"][0].x"#).unwrap();
+        assert_eq!(
+            p.segments,
+            vec![
+                Segment::Key("meta".into()),
+                Segment::Key("has space".into()),
+                Segment::Index(0),
+                Segment::Key("x".into()),
+            ]
+        );
+        let p = QueryPath::parse(r#".["q\"[]"]"#).unwrap();
+        assert_eq!(p.segments, vec![Segment::Key("q\"[]".into())]);
+        assert!(QueryPath::parse(r#".["open"#).is_err());
+        assert!(QueryPath::parse(r#".["a"x]"#).is_err());
+    }
+
+    #[test]
+    fn eval_quoted_key() {
+        let val = json!({"a.b": {"c d": 1}});
+        let p = QueryPath::parse(r#".["a.b"]["c d"]"#).unwrap();
+        assert_eq!(p.evaluate(&val).unwrap(), json!(1));
+    }
+
     #[test]
     fn parse_error_no_dot() {
         assert!(QueryPath::parse("name").is_err());
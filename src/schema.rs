@@ -0,0 +1,194 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde_json::{json, Map, Value};
+
+/// String properties with at most this many distinct values, each seen more
+/// than once on average, are described with an `enum`.
+const MAX_ENUM_VALUES: usize = 5;
+
+/// Infer a draft JSON Schema describing every value in `values`.
+///
+/// Array elements and repeated values are merged: object keys present in
+/// every sample become `required`, differing types become a `type` list, and
+/// small sets of repeated strings become an `enum`.
+pub fn infer_schema(values: &[Value]) -> Value {
+    let mut shape = Shape::default();
+    for value in values {
+        shape.observe(value);
+    }
+    let mut schema = shape.to_schema();
+    if let Value::Object(map) = &mut schema {
+        map.insert(
+            "$schema".into(),
+            json!("https://json-schema.org/draft/2020-12/schema"),
+        );
+    }
+    schema
+}
+
+/// Everything observed about the values at one position in the document.
+#[derive(Default)]
+struct Shape {
+    null: bool,
+    boolean: bool,
+    integer: bool,
+    number: bool,
+    strings: usize,
+    /// Distinct strings seen, dropped once there are too many for an enum.
+    string_values: Option<BTreeSet<String>>,
+    items: Option<Box<Shape>>,
+    objects: usize,
+    /// Per key: the merged shape and how many objects contained it.
+    properties: BTreeMap<String, (Shape, usize)>,
+}
+
+impl Shape {
+    fn observe(&mut self, value: &Value) {
+        match value {
+            Value::Null => self.null = true,
+            Value::Bool(_) => self.boolean = true,
+            Value::Number(n) if n.is_i64() || n.is_u64() => self.integer = true,
+            Value::Number(_) => self.number = true,
+            Value::String(s) => {
+                if self.strings == 0 {
+                    self.string_values = Some(BTreeSet::new());
+                }
+                self.strings += 1;
+                if let Some(set) = &mut self.string_values {
+                    set.insert(s.clone());
+                    if set.len() > MAX_ENUM_VALUES {
+                        self.string_values = None;
+                    }
+                }
+            }
+            Value::Array(arr) => {
+                let items = self.items.get_or_insert_with(Box::default);
+                for item in arr {
+                    items.observe(item);
+                }
+            }
+            Value::Object(map) => {
+                self.objects += 1;
+                for (k, v) in map {
+                    let (shape, count) = self.properties.entry(k.clone()).or_default();
+                    shape.observe(v);
+                    *count += 1;
+                }
+            }
+        }
+    }
+
+    fn to_schema(&self) -> Value {
+        let mut types = Vec::new();
+        if self.null {
+            types.push("null");
+        }
+        if self.boolean {
+            types.push("boolean");
+        }
+        if self.number {
+            types.push("number");
+        } else if self.integer {
+            types.push("integer");
+        }
+        if self.strings > 0 {
+            types.push("string");
+        }
+        if self.items.is_some() {
+            types.push("array");
+        }
+        if self.objects > 0 {
+            types.push("object");
+        }
+
+        let mut schema = Map::new();
+        match types.as_slice() {
+            [] => {}
+            [single] => {
+                schema.insert("type".into(), json!(single));
+            }
+            many => {
+                schema.insert("type".into(), json!(many));
+            }
+        }
+
+        if let Some(values) = &self.string_values {
+            if self.strings > values.len() {
+                schema.insert("enum".into(), json!(values));
+            }
+        }
+
+        if let Some(items) = &self.items {
+            let item_schema = items.to_schema();
+            if item_schema.as_object().is_some_and(|m| !m.is_empty()) {
+                schema.insert("items".into(), item_schema);
+            }
+        }
+
+        if self.objects > 0 {
+            let properties: Map<String, Value> = self
+                .properties
+                .iter()
+                .map(|(k, (shape, _))| (k.clone(), shape.to_schema()))
+                .collect();
+            let required: Vec<&String> = self
+                .properties
+                .iter()
+                .filter(|(_, (_, count))| *count == self.objects)
+                .map(|(k, _)| k)
+                .collect();
+            schema.insert("properties".into(), Value::Object(properties));
+            if !required.is_empty() {
+                schema.insert("required".into(), json!(required));
+            }
+        }
+
+        Value::Object(schema)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uniform_objects() {
+        let data = json!([
+            {"name": "a", "age": 30, "role": "admin"},
+            {"name": "b", "age": 41, "role": "user", "email": "b@x"},
+            {"name": "c", "age": 27, "role": "user"}
+        ]);
+        let schema = infer_schema(&[data]);
+
+        assert_eq!(schema["type"], "array");
+        let items = &schema["items"];
+        assert_eq!(items["type"], "object");
+        assert_eq!(items["properties"]["name"], json!({"type": "string"}));
+        assert_eq!(items["properties"]["age"], json!({"type": "integer"}));
+        assert_eq!(
+            items["properties"]["role"],
+            json!({"type": "string", "enum": ["admin", "user"]})
+        );
+        assert_eq!(items["required"], json!(["age", "name", "role"]));
+        assert!(schema["$schema"].as_str().unwrap().contains("json-schema.org"));
+    }
+
+    #[test]
+    fn mixed_types_become_a_list() {
+        let schema = infer_schema(&[json!([1, 2.5, null, "x"])]);
+        assert_eq!(schema["items"]["type"], json!(["null", "number", "string"]));
+    }
+
+    #[test]
+    fn nested_objects_and_empty_arrays() {
+        let schema = infer_schema(&[json!({"meta": {"tags": []}})]);
+        assert_eq!(
+            schema["properties"]["meta"],
+            json!({
+                "type": "object",
+                "properties": {"tags": {"type": "array"}},
+                "required": ["tags"]
+            })
+        );
+    }
+}
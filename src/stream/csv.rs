@@ -1,44 +1,44 @@
 use serde_json::Value;
 
 use crate::error::QfError;
-use crate::query;
+use crate::parser::csv::{read_headers, reader, record_to_value, CsvOptions};
+use crate::query::env::Env;
+use crate::query::Program;
 
-/// Stream CSV/TSV rows, applying the query to each row (as a JSON object with header keys).
+use super::Records;
+
+/// Stream CSV/TSV rows, applying the query to each row (as a JSON object with
+/// header keys, or an array when `opts.headers` is off).
 pub fn stream_csv<F>(
     input: &str,
-    query_str: &str,
+    program: &Program,
     delimiter: u8,
+    opts: &CsvOptions,
+    env: &Env,
     on_result: &mut F,
 ) -> Result<(), QfError>
 where
     F: FnMut(Value) -> Result<(), QfError>,
 {
-    let mut rdr = csv::ReaderBuilder::new()
-        .delimiter(delimiter)
-        .from_reader(input.as_bytes());
-
-    let headers: Vec<String> = rdr
-        .headers()
-        .map_err(|e| QfError::Parse(e.to_string()))?
-        .iter()
-        .map(|h| h.to_string())
-        .collect();
+    Records::new(rows(input, delimiter, opts), program, env).for_each_result(on_result)
+}
 
-    for result in rdr.records() {
+/// Iterate over the rows of delimited text, each converted to a Value.
+pub fn rows<'a>(
+    input: &'a str,
+    delimiter: u8,
+    opts: &CsvOptions,
+) -> Box<dyn Iterator<Item = Result<Value, QfError>> + 'a> {
+    let opts = *opts;
+    let mut rdr = reader(input, delimiter, &opts);
+    let headers = match read_headers(&mut rdr, &opts) {
+        Ok(headers) => headers,
+        Err(e) => return Box::new(std::iter::once(Err(e))),
+    };
+    Box::new(rdr.into_records().map(move |result| {
         let record = result.map_err(|e| QfError::Parse(e.to_string()))?;
-        let obj: serde_json::Map<String, Value> = headers
-            .iter()
-            .zip(record.iter())
-            .map(|(h, v)| (h.clone(), Value::String(v.to_string())))
-            .collect();
-        let value = Value::Object(obj);
-        let results = query::query(&value, query_str)?;
-        for r in results {
-            on_result(r)?;
-        }
-    }
-
-    Ok(())
+        Ok(record_to_value(headers.as_deref(), &record, &opts))
+    }))
 }
 
 #[cfg(test)]
@@ -50,7 +50,8 @@ mod tests {
     fn stream_csv_rows() {
         let input = "name,age\nAlice,30\nBob,25\n";
         let mut results = Vec::new();
-        stream_csv(input, ".name", b',', &mut |v| {
+        let program = Program::compile(".name").unwrap();
+        stream_csv(input, &program, b',', &CsvOptions::default(), &Env::new(), &mut |v| {
             results.push(v);
             Ok(())
         })
@@ -62,7 +63,8 @@ mod tests {
     fn stream_tsv_rows() {
         let input = "name\tage\nAlice\t30\nBob\t25\n";
         let mut results = Vec::new();
-        stream_csv(input, ".age", b'\t', &mut |v| {
+        let program = Program::compile(".age").unwrap();
+        stream_csv(input, &program, b'\t', &CsvOptions::default(), &Env::new(), &mut |v| {
             results.push(v);
             Ok(())
         })
@@ -74,7 +76,8 @@ mod tests {
     fn stream_csv_identity() {
         let input = "x,y\n1,2\n3,4\n";
         let mut results = Vec::new();
-        stream_csv(input, ".", b',', &mut |v| {
+        let program = Program::compile(".").unwrap();
+        stream_csv(input, &program, b',', &CsvOptions::default(), &Env::new(), &mut |v| {
             results.push(v);
             Ok(())
         })
@@ -83,4 +86,21 @@ mod tests {
         assert_eq!(results[0]["x"], "1");
         assert_eq!(results[1]["y"], "4");
     }
+
+    #[test]
+    fn stream_csv_headerless_typed() {
+        let opts = CsvOptions {
+            headers: false,
+            infer_types: true,
+            ..CsvOptions::default()
+        };
+        let mut results = Vec::new();
+        let program = Program::compile(".[0]").unwrap();
+        stream_csv("1,a\n2,b\n", &program, b',', &opts, &Env::new(), &mut |v| {
+            results.push(v);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(results, vec![json!(1), json!(2)]);
+    }
 }
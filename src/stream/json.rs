@@ -1,54 +1,158 @@
 use serde_json::Value;
 
 use crate::error::QfError;
-use crate::query;
+use crate::parser::json::{parse_value_with, parse_with};
+use crate::parser::DuplicateKeys;
+use crate::query::env::Env;
+use crate::query::Program;
+
+use super::Records;
 
 /// Stream a JSON array, applying the query to each element.
 pub fn stream_json<F>(
     input: &str,
-    query_str: &str,
+    program: &Program,
+    env: &Env,
     on_result: &mut F,
 ) -> Result<(), QfError>
 where
     F: FnMut(Value) -> Result<(), QfError>,
 {
-    // Use serde_json::StreamDeserializer for lazy parsing
-    let stream = serde_json::Deserializer::from_str(input).into_iter::<Value>();
-
-    for item in stream {
-        let value = item.map_err(|e| QfError::Parse(e.to_string()))?;
-        let results = query::query(&value, query_str)?;
-        for result in results {
-            on_result(result)?;
-        }
-    }
-
-    Ok(())
+    Records::new(Box::new(values(input)), program, env).for_each_result(on_result)
 }
 
 /// Stream newline-delimited JSON (NDJSON/JSON Lines).
 pub fn stream_ndjson<F>(
     input: &str,
-    query_str: &str,
+    program: &Program,
+    env: &Env,
     on_result: &mut F,
 ) -> Result<(), QfError>
 where
     F: FnMut(Value) -> Result<(), QfError>,
 {
-    for line in input.lines() {
-        let line = line.trim();
-        if line.is_empty() {
-            continue;
+    Records::numbered(Box::new(ndjson_lines(input)), program, std::borrow::Cow::Borrowed(env))
+        .for_each_result(on_result)
+}
+
+/// Iterate over consecutive JSON values in `input`.
+///
+/// After a malformed value, parsing resumes at the next value (see
+/// [`resync_offset`]), so callers that skip errors still see the values that
+/// follow, even on the same line.
+pub fn values(input: &str) -> impl Iterator<Item = Result<Value, QfError>> + '_ {
+    values_with(input, None)
+}
+
+/// Like [`values`], resolving repeated object keys by `policy`.
+pub fn values_with(
+    input: &str,
+    policy: Option<DuplicateKeys>,
+) -> impl Iterator<Item = Result<Value, QfError>> + '_ {
+    let mut rest = input;
+    // Where `rest` starts, to report errors against the whole input: the
+    // lines before it, and the columns before it on its first line
+    let (mut lines_before, mut columns_before) = (0, 0);
+    std::iter::from_fn(move || {
+        // Use serde_json::StreamDeserializer for lazy parsing
+        let mut stream = serde_json::Deserializer::from_str(rest).into_iter::<Value>();
+        let item = stream.next()?;
+        let consumed = match &item {
+            Ok(_) => stream.byte_offset(),
+            Err(e) => {
+                let line_start: usize =
+                    rest.split_inclusive('\n').take(e.line().max(1) - 1).map(str::len).sum();
+                let error_at = (line_start + e.column()).saturating_sub(1).min(rest.len());
+                resync_offset(rest, error_at).max(1)
+            }
+        };
+        let item = match (item, policy) {
+            // The stream found where the value ends; parse it again to apply
+            // the policy, which serde_json's own maps can't
+            (Ok(_), Some(policy)) => parse_value_with(&rest[..consumed], policy),
+            (item, _) => item,
+        };
+        let item =
+            item.map_err(|e| QfError::Parse(shift_position(&e, lines_before, columns_before)));
+        let done = &rest[..consumed];
+        lines_before += done.matches('\n').count();
+        columns_before = match done.rfind('\n') {
+            Some(newline) => done.len() - newline - 1,
+            None => columns_before + done.len(),
+        };
+        rest = &rest[consumed..];
+        Some(item)
+    })
+}
+
+/// Where to pick up after the malformed value at the start of `text`, whose
+/// error is at byte `error_at`: the first value after that point that starts
+/// outside the broken value's brackets, or a `{`/`[` opening a line from the
+/// error on, in case the broken value was never closed.
+fn resync_offset(text: &str, error_at: usize) -> usize {
+    let (mut depth, mut in_string, mut escaped) = (0usize, false, false);
+    let mut prev = '\n';
+    for (i, c) in text.char_indices() {
+        if i >= error_at && !in_string {
+            let fresh = i > error_at && prev.is_whitespace() && !c.is_whitespace();
+            if (fresh && depth == 0) || (prev == '\n' && matches!(c, '{' | '[')) {
+                return i;
+            }
         }
-        let value: Value =
-            serde_json::from_str(line).map_err(|e| QfError::Parse(e.to_string()))?;
-        let results = query::query(&value, query_str)?;
-        for result in results {
-            on_result(result)?;
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+        } else {
+            match c {
+                '"' => in_string = true,
+                '{' | '[' => depth += 1,
+                '}' | ']' => depth = depth.saturating_sub(1),
+                _ => {}
+            }
         }
+        prev = c;
     }
+    text.len()
+}
 
-    Ok(())
+/// The message of `e`, for text that starts `columns_before` bytes into line
+/// `lines_before + 1`.
+fn shift_position(e: &serde_json::Error, lines_before: usize, columns_before: usize) -> String {
+    let message = e.to_string();
+    let column = if e.line() == 1 { e.column() + columns_before } else { e.column() };
+    match message.rsplit_once(" at line ") {
+        Some((text, _)) if lines_before > 0 || columns_before > 0 => {
+            format!("{text} at line {} column {column}", e.line() + lines_before)
+        }
+        _ => message,
+    }
+}
+
+/// Iterate over the values of an NDJSON document, skipping blank lines.
+pub fn ndjson_values(input: &str) -> impl Iterator<Item = Result<Value, QfError>> + '_ {
+    ndjson_lines(input).map(|(_, value)| value)
+}
+
+/// Like [`ndjson_values`], pairing each value with its 1-based line number.
+pub fn ndjson_lines(input: &str) -> impl Iterator<Item = (usize, Result<Value, QfError>)> + '_ {
+    ndjson_lines_with(input, None)
+}
+
+/// Like [`ndjson_lines`], resolving repeated object keys by `policy`.
+pub fn ndjson_lines_with(
+    input: &str,
+    policy: Option<DuplicateKeys>,
+) -> impl Iterator<Item = (usize, Result<Value, QfError>)> + '_ {
+    input
+        .lines()
+        .map(str::trim)
+        .enumerate()
+        .filter(|(_, line)| !line.is_empty())
+        .map(move |(i, line)| (i + 1, parse_with(line, policy)))
 }
 
 #[cfg(test)]
@@ -60,7 +164,7 @@ mod tests {
     fn stream_json_array() {
         let input = r#"[1,2,3]"#;
         let mut results = Vec::new();
-        stream_json(input, ".", &mut |v| {
+        stream_json(input, &Program::compile(".").unwrap(), &Env::new(), &mut |v| {
             results.push(v);
             Ok(())
         })
@@ -73,7 +177,7 @@ mod tests {
     fn stream_multiple_json_values() {
         let input = r#"{"a":1}{"a":2}{"a":3}"#;
         let mut results = Vec::new();
-        stream_json(input, ".a", &mut |v| {
+        stream_json(input, &Program::compile(".a").unwrap(), &Env::new(), &mut |v| {
             results.push(v);
             Ok(())
         })
@@ -81,11 +185,39 @@ mod tests {
         assert_eq!(results, vec![json!(1), json!(2), json!(3)]);
     }
 
+    #[test]
+    fn values_resume_after_malformed_value() {
+        let input = "{\"a\":1}\n\n{\"a\":x, \"b\":{}} 2\n{\"a\":3} 4\n";
+        let items: Vec<_> = values(input).collect();
+        assert_eq!(items.len(), 5);
+        assert_eq!(items[0].as_ref().unwrap(), &json!({"a": 1}));
+        let err = items[1].as_ref().unwrap_err().to_string();
+        assert!(err.contains("at line 3"), "{err}");
+        assert_eq!(items[2].as_ref().unwrap(), &json!(2));
+        assert_eq!(items[3].as_ref().unwrap(), &json!({"a": 3}));
+        assert_eq!(items[4].as_ref().unwrap(), &json!(4));
+        // Junk between values on one line, and a record that is never closed
+        let ok = |input| values(input).filter_map(Result::ok).collect::<Vec<_>>();
+        assert_eq!(ok("{\"a\":1} bad {\"a\":3}"), vec![json!({"a": 1}), json!({"a": 3})]);
+        let err = values("{\"a\":1} bad").nth(1).unwrap().unwrap_err().to_string();
+        assert!(err.contains("at line 1 column 9"), "{err}");
+        assert_eq!(ok("{\"a\":\n{\"a\":2}\n{\"a\":3}\n"), vec![json!({"a": 3})]);
+    }
+
+    #[test]
+    fn values_apply_duplicate_key_policy() {
+        let input = "{\"b\":0}\n{\"a\":1,\"a\":2}";
+        let items: Vec<_> = values_with(input, Some(DuplicateKeys::Array)).collect();
+        assert_eq!(items[1].as_ref().unwrap(), &json!({"a": [1, 2]}));
+        let err = values_with(input, Some(DuplicateKeys::Error)).nth(1).unwrap().unwrap_err();
+        assert!(err.to_string().contains("duplicate key \"a\" at line 2"), "{err}");
+    }
+
     #[test]
     fn stream_ndjson_lines() {
         let input = "{\"a\":1}\n{\"a\":2}\n{\"a\":3}\n";
         let mut results = Vec::new();
-        stream_ndjson(input, ".a", &mut |v| {
+        stream_ndjson(input, &Program::compile(".a").unwrap(), &Env::new(), &mut |v| {
             results.push(v);
             Ok(())
         })
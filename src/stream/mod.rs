@@ -1,11 +1,17 @@
 pub mod csv;
 pub mod json;
 pub mod xml;
+pub mod yaml;
+
+use std::borrow::Cow;
 
 use serde_json::Value;
 
 use crate::error::QfError;
 use crate::format::Format;
+use crate::parser::ParseOptions;
+use crate::query::env::Env;
+use crate::query::Program;
 
 /// Process input in streaming mode, applying a query to each record.
 /// Returns results one at a time via a callback.
@@ -13,31 +19,263 @@ pub fn stream_process<F>(
     input: &str,
     format: Format,
     query_str: &str,
+    env: &Env,
+    on_result: F,
+) -> Result<(), QfError>
+where
+    F: FnMut(Value) -> Result<(), QfError>,
+{
+    stream_process_with(input, format, query_str, &ParseOptions::default(), env, on_result)
+}
+
+/// Like [`stream_process`], honouring `opts` for formats that take options.
+pub fn stream_process_with<F>(
+    input: &str,
+    format: Format,
+    query_str: &str,
+    opts: &ParseOptions,
+    env: &Env,
     mut on_result: F,
 ) -> Result<(), QfError>
 where
     F: FnMut(Value) -> Result<(), QfError>,
 {
-    match format {
-        Format::Json => json::stream_json(input, query_str, &mut on_result),
-        Format::Xml => xml::stream_xml(input, query_str, &mut on_result),
-        Format::Csv => csv::stream_csv(input, query_str, b',', &mut on_result),
-        Format::Tsv => csv::stream_csv(input, query_str, b'\t', &mut on_result),
-        _ => Err(QfError::Runtime(format!(
-            "streaming not supported for {}",
-            format
-        ))),
-    }
+    let program = Program::compile(query_str)?;
+    records_with(input, format, &program, opts, env).for_each_result(&mut on_result)
 }
 
 /// Process NDJSON (newline-delimited JSON) input.
 pub fn stream_ndjson<F>(
     input: &str,
     query_str: &str,
+    env: &Env,
     mut on_result: F,
 ) -> Result<(), QfError>
 where
     F: FnMut(Value) -> Result<(), QfError>,
 {
-    json::stream_ndjson(input, query_str, &mut on_result)
+    let program = Program::compile(query_str)?;
+    json::stream_ndjson(input, &program, env, &mut on_result)
+}
+
+/// Iterate over the results of running `program` on each record of `input`.
+pub fn records<'a>(input: &'a str, format: Format, program: &'a Program) -> Records<'a> {
+    let source = record_source(input, format, &ParseOptions::default());
+    Records::from_source(source, program, Cow::Owned(Env::new()))
+}
+
+/// Like [`records`], with input options and an evaluation environment.
+pub fn records_with<'a>(
+    input: &'a str,
+    format: Format,
+    program: &'a Program,
+    opts: &ParseOptions,
+    env: &'a Env,
+) -> Records<'a> {
+    let source = record_source(input, format, opts);
+    Records::new(source, program, env)
+}
+
+/// Like [`records`], reading `input` as NDJSON. `input_line_number` gives
+/// the line each record is on.
+pub fn ndjson_records<'a>(input: &'a str, program: &'a Program, env: &'a Env) -> Records<'a> {
+    ndjson_records_with(input, program, &ParseOptions::default(), env)
+}
+
+/// Like [`ndjson_records`], with input options.
+pub fn ndjson_records_with<'a>(
+    input: &'a str,
+    program: &'a Program,
+    opts: &ParseOptions,
+    env: &'a Env,
+) -> Records<'a> {
+    let lines = json::ndjson_lines_with(input, opts.duplicate_keys);
+    Records::numbered(Box::new(lines), program, Cow::Borrowed(env))
+}
+
+type RecordSource<'a> = Box<dyn Iterator<Item = Result<Value, QfError>> + 'a>;
+
+/// Records paired with the 1-based input line they are on.
+type NumberedSource<'a> = Box<dyn Iterator<Item = (usize, Result<Value, QfError>)> + 'a>;
+
+/// Split `input` into the records streaming mode runs the query over.
+fn record_source<'a>(input: &'a str, format: Format, opts: &ParseOptions) -> RecordSource<'a> {
+    match format {
+        Format::Json => Box::new(json::values_with(input, opts.duplicate_keys)),
+        Format::Xml => Box::new(xml::elements(input)),
+        Format::Yaml => Box::new(yaml::documents(input, opts.duplicate_keys)),
+        Format::Csv => csv::rows(input, b',', &opts.csv),
+        Format::Tsv => csv::rows(input, b'\t', &opts.csv),
+        _ => Box::new(std::iter::once(Err(QfError::Runtime(format!(
+            "streaming not supported for {}",
+            format
+        ))))),
+    }
+}
+
+/// Called with each record that fails to parse, and its line if known.
+type SkipHandler<'a> = Box<dyn FnMut(Option<usize>, QfError) + 'a>;
+
+/// Iterator over query results across a stream of records.
+///
+/// Stops after the first error, whether from reading a record or running
+/// the query, unless [`Records::skip_errors`] is set.
+pub struct Records<'a> {
+    source: Box<dyn Iterator<Item = (Option<usize>, Result<Value, QfError>)> + 'a>,
+    program: &'a Program,
+    env: Cow<'a, Env>,
+    pending: std::vec::IntoIter<Value>,
+    done: bool,
+    on_skip: Option<SkipHandler<'a>>,
+}
+
+impl<'a> Records<'a> {
+    pub(crate) fn new(source: RecordSource<'a>, program: &'a Program, env: &'a Env) -> Self {
+        Self::from_source(source, program, Cow::Borrowed(env))
+    }
+
+    fn from_source(source: RecordSource<'a>, program: &'a Program, env: Cow<'a, Env>) -> Self {
+        Records {
+            source: Box::new(source.map(|record| (None, record))),
+            program,
+            env,
+            pending: Vec::new().into_iter(),
+            done: false,
+            on_skip: None,
+        }
+    }
+
+    /// Records from a source that knows their line numbers, which are
+    /// exposed to the query through `input_line_number`.
+    pub(crate) fn numbered(
+        source: NumberedSource<'a>,
+        program: &'a Program,
+        env: Cow<'a, Env>,
+    ) -> Self {
+        Records {
+            source: Box::new(source.map(|(line, record)| (Some(line), record))),
+            program,
+            env,
+            pending: Vec::new().into_iter(),
+            done: false,
+            on_skip: None,
+        }
+    }
+
+    /// Hand records that fail to parse to `on_skip` and carry on with the
+    /// next one. Errors raised by the query still end the iteration.
+    pub fn skip_errors(mut self, on_skip: impl FnMut(Option<usize>, QfError) + 'a) -> Self {
+        self.on_skip = Some(Box::new(on_skip));
+        self
+    }
+
+    /// Drive the iterator, handing each result to `on_result`.
+    pub(crate) fn for_each_result<F>(self, on_result: &mut F) -> Result<(), QfError>
+    where
+        F: FnMut(Value) -> Result<(), QfError>,
+    {
+        for result in self {
+            on_result(result?)?;
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for Records<'_> {
+    type Item = Result<Value, QfError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(value) = self.pending.next() {
+                return Some(Ok(value));
+            }
+            if self.done {
+                return None;
+            }
+            let (line, record) = self.source.next()?;
+            if let Some(line) = line {
+                self.env.to_mut().set_input_line_number(line);
+            }
+            let outcome = match (record, &mut self.on_skip) {
+                (Ok(record), _) => self.program.run_with_env(&record, &self.env),
+                (Err(e), Some(on_skip)) => {
+                    on_skip(line, e);
+                    continue;
+                }
+                (Err(e), None) => Err(e),
+            };
+            match outcome {
+                Ok(results) => self.pending = results.into_iter(),
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn records_collects_results() {
+        let program = Program::compile(".a, .a * 10").unwrap();
+        let results: Vec<Value> = records(r#"{"a":1} {"a":2}"#, Format::Json, &program)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(results, vec![json!(1), json!(10), json!(2), json!(20)]);
+    }
+
+    #[test]
+    fn records_stop_after_error() {
+        let program = Program::compile(".a + 1").unwrap();
+        let results: Vec<_> = records(r#"{"a":1} {"a":"x"} {"a":3}"#, Format::Json, &program).collect();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].as_ref().unwrap(), &json!(2));
+        assert!(results[1].is_err());
+
+        let unsupported: Vec<_> = records("x = 1", Format::Toml, &program).collect();
+        assert!(unsupported[0].is_err());
+    }
+
+    #[test]
+    fn records_skip_malformed_input() {
+        let program = Program::compile(".a").unwrap();
+        let env = Env::new();
+        let input = "{\"a\":1}\n{\"a\":\n{\"a\":2}\n{\"a\":3}\n";
+        let mut skipped = Vec::new();
+        let results: Vec<Value> = ndjson_records(input, &program, &env)
+            .skip_errors(|line, _| skipped.push(line))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(results, vec![json!(1), json!(2), json!(3)]);
+        assert_eq!(skipped, vec![Some(2)]);
+        // Query errors are not skipped
+        let program = Program::compile(".a + 1").unwrap();
+        let results: Vec<_> = records(r#"{"a":"x"} {"a":1}"#, Format::Json, &program)
+            .skip_errors(|_, _| {})
+            .collect();
+        assert_eq!(results.len(), 1);
+        assert!(results[0].is_err());
+    }
+
+    #[test]
+    fn ndjson_records_track_line_numbers() {
+        let program = Program::compile("[.a, input_line_number]").unwrap();
+        let env = Env::new();
+        let input = "{\"a\":1}\n\n{\"a\":2}\n{\"a\":3}\n";
+        let results: Vec<Value> = ndjson_records(input, &program, &env)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(results, vec![json!([1, 1]), json!([2, 3]), json!([3, 4])]);
+        // Other sources don't track lines
+        let program = Program::compile("input_line_number").unwrap();
+        let results: Vec<Value> = records("1 2", Format::Json, &program)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(results, vec![json!(0), json!(0)]);
+    }
 }
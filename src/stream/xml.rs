@@ -1,139 +1,142 @@
-use quick_xml::events::Event;
+use quick_xml::events::{BytesStart, Event};
 use quick_xml::Reader;
 use serde_json::Value;
 
 use crate::error::QfError;
-use crate::query;
+use crate::query::env::Env;
+use crate::query::Program;
+
+use super::Records;
 
 /// Stream XML elements, applying the query to each top-level child element.
 pub fn stream_xml<F>(
     input: &str,
-    query_str: &str,
+    program: &Program,
+    env: &Env,
     on_result: &mut F,
 ) -> Result<(), QfError>
 where
     F: FnMut(Value) -> Result<(), QfError>,
 {
-    let mut reader = Reader::from_str(input);
-    let mut depth: usize = 0;
-    let mut current_element = String::new();
-    let mut buf = Vec::new();
-
-    loop {
-        match reader.read_event_into(&mut buf) {
-            Ok(Event::Start(ref e)) => {
-                depth += 1;
-                if depth == 2 {
-                    // Start collecting a top-level child element
-                    let tag = String::from_utf8_lossy(e.name().as_ref()).to_string();
-                    let mut element_xml = String::new();
-                    element_xml.push('<');
-                    element_xml.push_str(&tag);
-                    for attr in e.attributes().flatten() {
-                        let key = String::from_utf8_lossy(attr.key.as_ref());
-                        let val = String::from_utf8_lossy(&attr.value);
-                        element_xml.push(' ');
-                        element_xml.push_str(&key);
-                        element_xml.push_str("=\"");
-                        element_xml.push_str(&val);
-                        element_xml.push('"');
+    Records::new(Box::new(elements(input)), program, env).for_each_result(on_result)
+}
+
+/// Iterate over the top-level child elements of the root, each parsed to a
+/// Value.
+pub fn elements(input: &str) -> Elements<'_> {
+    Elements {
+        reader: Reader::from_str(input),
+        depth: 0,
+        current_element: String::new(),
+        buf: Vec::new(),
+        done: false,
+    }
+}
+
+/// Iterator returned by [`elements`].
+pub struct Elements<'a> {
+    reader: Reader<&'a [u8]>,
+    // Depth of the element we are inside: the root is 1, its children 2.
+    depth: usize,
+    current_element: String,
+    buf: Vec<u8>,
+    // Set once the reader fails or runs out; it is not resumed after that.
+    done: bool,
+}
+
+impl Elements<'_> {
+    /// Advance to the next complete top-level child and return its XML.
+    fn next_element(&mut self) -> Result<Option<String>, QfError> {
+        loop {
+            self.buf.clear();
+            match self.reader.read_event_into(&mut self.buf) {
+                Ok(Event::Start(ref e)) => {
+                    self.depth += 1;
+                    if self.depth >= 2 {
+                        push_open_tag(&mut self.current_element, e, false)?;
+                    }
+                }
+                Ok(Event::End(ref e)) => {
+                    if self.depth >= 2 {
+                        self.current_element.push_str("</");
+                        self.current_element.push_str(&String::from_utf8_lossy(e.name().as_ref()));
+                        self.current_element.push('>');
                     }
-                    element_xml.push('>');
-                    current_element = element_xml;
-                } else if depth > 2 {
-                    let tag = String::from_utf8_lossy(e.name().as_ref()).to_string();
-                    current_element.push('<');
-                    current_element.push_str(&tag);
-                    for attr in e.attributes().flatten() {
-                        let key = String::from_utf8_lossy(attr.key.as_ref());
-                        let val = String::from_utf8_lossy(&attr.value);
-                        current_element.push(' ');
-                        current_element.push_str(&key);
-                        current_element.push_str("=\"");
-                        current_element.push_str(&val);
-                        current_element.push('"');
+                    let done = self.depth == 2;
+                    self.depth = self.depth.saturating_sub(1);
+                    if done {
+                        return Ok(Some(std::mem::take(&mut self.current_element)));
                     }
-                    current_element.push('>');
                 }
-            }
-            Ok(Event::End(ref e)) => {
-                let tag = String::from_utf8_lossy(e.name().as_ref()).to_string();
-                if depth == 2 {
-                    current_element.push_str("</");
-                    current_element.push_str(&tag);
-                    current_element.push('>');
-
-                    // Parse the collected element and apply query
-                    let value: Value = quick_xml::de::from_str(&current_element)
-                        .map_err(|e| QfError::Parse(e.to_string()))?;
-                    let results = query::query(&value, query_str)?;
-                    for result in results {
-                        on_result(result)?;
+                Ok(Event::Empty(ref e)) => {
+                    // A self-closing element opens and closes at the depth a
+                    // Start would have taken it to
+                    if self.depth + 1 >= 2 {
+                        push_open_tag(&mut self.current_element, e, true)?;
+                    }
+                    if self.depth + 1 == 2 {
+                        return Ok(Some(std::mem::take(&mut self.current_element)));
                     }
-                    current_element.clear();
-                } else if depth > 2 {
-                    current_element.push_str("</");
-                    current_element.push_str(&tag);
-                    current_element.push('>');
                 }
-                depth -= 1;
-            }
-            Ok(Event::Empty(ref e)) => {
-                if depth >= 1 {
-                    let tag = String::from_utf8_lossy(e.name().as_ref()).to_string();
-                    if depth == 1 {
-                        // Self-closing top-level child
-                        let mut element_xml = format!("<{}", tag);
-                        for attr in e.attributes().flatten() {
-                            let key = String::from_utf8_lossy(attr.key.as_ref());
-                            let val = String::from_utf8_lossy(&attr.value);
-                            element_xml.push(' ');
-                            element_xml.push_str(&key);
-                            element_xml.push_str("=\"");
-                            element_xml.push_str(&val);
-                            element_xml.push('"');
-                        }
-                        element_xml.push_str("/>");
-
-                        let value: Value = quick_xml::de::from_str(&element_xml)
-                            .map_err(|e| QfError::Parse(e.to_string()))?;
-                        let results = query::query(&value, query_str)?;
-                        for result in results {
-                            on_result(result)?;
-                        }
-                    } else {
-                        current_element.push('<');
-                        current_element.push_str(&tag);
-                        for attr in e.attributes().flatten() {
-                            let key = String::from_utf8_lossy(attr.key.as_ref());
-                            let val = String::from_utf8_lossy(&attr.value);
-                            current_element.push(' ');
-                            current_element.push_str(&key);
-                            current_element.push_str("=\"");
-                            current_element.push_str(&val);
-                            current_element.push('"');
-                        }
-                        current_element.push_str("/>");
+                Ok(Event::Text(ref e)) => {
+                    if self.depth >= 2 {
+                        let text = e.unescape().map_err(|e| QfError::Parse(e.to_string()))?;
+                        self.current_element.push_str(&escape_xml(&text));
                     }
                 }
+                Ok(Event::Eof) => return Ok(None),
+                Ok(_) => {}
+                Err(e) => return Err(QfError::Parse(e.to_string())),
             }
-            Ok(Event::Text(ref e)) => {
-                if depth >= 2 {
-                    let text = e.unescape().map_err(|e| QfError::Parse(e.to_string()))?;
-                    // Escape for XML
-                    current_element.push_str(&text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;"));
-                }
+        }
+    }
+}
+
+impl Iterator for Elements<'_> {
+    type Item = Result<Value, QfError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.next_element() {
+            Ok(Some(xml)) => Some(crate::parser::xml::parse(&xml)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
             }
-            Ok(Event::Eof) => break,
-            Ok(_) => {}
-            Err(e) => return Err(QfError::Parse(e.to_string())),
         }
-        buf.clear();
     }
+}
+
+impl std::iter::FusedIterator for Elements<'_> {}
 
+/// Append `<tag attr="...">` (or `<tag .../>` when self-closing) to `out`.
+fn push_open_tag(out: &mut String, e: &BytesStart, self_closing: bool) -> Result<(), QfError> {
+    out.push('<');
+    out.push_str(&String::from_utf8_lossy(e.name().as_ref()));
+    for attr in e.attributes().flatten() {
+        let key = String::from_utf8_lossy(attr.key.as_ref());
+        let val = attr.unescape_value().map_err(|e| QfError::Parse(e.to_string()))?;
+        out.push(' ');
+        out.push_str(&key);
+        out.push_str("=\"");
+        out.push_str(&escape_xml(&val).replace('"', "&quot;"));
+        out.push('"');
+    }
+    out.push_str(if self_closing { "/>" } else { ">" });
     Ok(())
 }
 
+/// Escape text so it can be re-read as XML character data.
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -142,11 +145,49 @@ mod tests {
     fn stream_xml_elements() {
         let input = "<root><item><name>a</name></item><item><name>b</name></item></root>";
         let mut results = Vec::new();
-        stream_xml(input, ".", &mut |v| {
+        stream_xml(input, &Program::compile(".").unwrap(), &Env::new(), &mut |v| {
             results.push(v);
             Ok(())
         })
         .unwrap();
         assert_eq!(results.len(), 2);
     }
+
+    #[test]
+    fn stream_xml_self_closing_children() {
+        let input = r#"<root><item id="1"/><item><name>b</name></item><item id="3"/><item><tags><tag/></tags></item></root>"#;
+        let mut results = Vec::new();
+        stream_xml(input, &Program::compile(".").unwrap(), &Env::new(), &mut |v| {
+            results.push(v);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(results.len(), 4);
+        assert_eq!(results[0]["@id"], "1");
+        assert_eq!(results[1]["name"]["$text"], "b");
+        assert_eq!(results[2]["@id"], "3");
+        assert!(results[3]["tags"].is_object());
+    }
+
+    #[test]
+    fn stream_xml_escapes_attribute_values() {
+        let input = r#"<root><item title='Tom &amp; "Jerry"'/><item note="a &lt; b"><x>1</x></item></root>"#;
+        let mut results = Vec::new();
+        stream_xml(input, &Program::compile(".").unwrap(), &Env::new(), &mut |v| {
+            results.push(v);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0]["@title"], r#"Tom & "Jerry""#);
+        assert_eq!(results[1]["@note"], "a < b");
+    }
+
+    #[test]
+    fn elements_stop_after_an_error() {
+        let mut items = elements("<root><a>1</b><c/><d/></root>");
+        assert!(items.next().unwrap().is_err());
+        assert!(items.next().is_none());
+        assert!(items.next().is_none());
+    }
 }
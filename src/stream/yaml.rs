@@ -0,0 +1,131 @@
+use serde_json::Value;
+
+use crate::error::QfError;
+use crate::parser::{yaml, DuplicateKeys};
+use crate::query::env::Env;
+use crate::query::Program;
+
+use super::Records;
+
+/// Stream a multi-document YAML file, applying the query to each document.
+pub fn stream_yaml<F>(
+    input: &str,
+    program: &Program,
+    env: &Env,
+    on_result: &mut F,
+) -> Result<(), QfError>
+where
+    F: FnMut(Value) -> Result<(), QfError>,
+{
+    Records::new(Box::new(documents(input, None)), program, env).for_each_result(on_result)
+}
+
+/// Iterate over the documents of a YAML stream, each parsed to a Value.
+///
+/// Documents are split on `---` and `...` marker lines; documents holding
+/// nothing but blank lines and comments are skipped.
+pub fn documents(input: &str, policy: Option<DuplicateKeys>) -> Documents<'_> {
+    Documents {
+        rest: input,
+        policy,
+    }
+}
+
+/// Iterator returned by [`documents`].
+pub struct Documents<'a> {
+    rest: &'a str,
+    policy: Option<DuplicateKeys>,
+}
+
+impl<'a> Documents<'a> {
+    /// Split off the text of the next document, up to its end marker.
+    fn next_chunk(&mut self) -> Option<&'a str> {
+        while !self.rest.is_empty() {
+            let text = self.rest;
+            let mut end = text.len();
+            let mut next = text.len();
+            let mut offset = 0;
+            for line in text.split_inclusive('\n') {
+                let bare = line.trim_end();
+                // A start marker opens a new document, unless it is the
+                // first line of this one
+                if offset > 0 && is_marker(bare, "---") {
+                    (end, next) = (offset, offset);
+                    break;
+                }
+                if is_marker(bare, "...") {
+                    (end, next) = (offset, offset + line.len());
+                    break;
+                }
+                offset += line.len();
+            }
+            self.rest = &text[next..];
+            let chunk = &text[..end];
+            if has_content(chunk) {
+                return Some(chunk);
+            }
+        }
+        None
+    }
+}
+
+impl Iterator for Documents<'_> {
+    type Item = Result<Value, QfError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let chunk = self.next_chunk()?;
+        Some(yaml::parse_with(chunk, self.policy))
+    }
+}
+
+/// Whether `line` is the document marker `marker`, alone or followed by
+/// content (`--- value`).
+fn is_marker(line: &str, marker: &str) -> bool {
+    line.strip_prefix(marker)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with([' ', '\t']))
+}
+
+/// Whether a document has anything besides markers, comments and blanks.
+fn has_content(chunk: &str) -> bool {
+    chunk.lines().any(|line| {
+        let line = line.strip_prefix("---").unwrap_or(line).trim();
+        !line.is_empty() && !line.starts_with('#')
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn parse_all(input: &str) -> Vec<Value> {
+        documents(input, None).collect::<Result<_, _>>().unwrap()
+    }
+
+    #[test]
+    fn splits_on_markers() {
+        let input = "# leading comment\n---\na: 1\n---\nb: [x, y]\n...\n--- 3\n";
+        assert_eq!(parse_all(input), vec![json!({"a": 1}), json!({"b": ["x", "y"]}), json!(3)]);
+        // Without any marker the whole input is one document
+        assert_eq!(parse_all("a: 1\nb: 2\n"), vec![json!({"a": 1, "b": 2})]);
+        assert!(parse_all("---\n# only a comment\n---\n").is_empty());
+    }
+
+    #[test]
+    fn markers_inside_block_scalars_are_kept() {
+        let input = "text: |\n  ---\n  body\n---\nn: 1\n";
+        assert_eq!(parse_all(input), vec![json!({"text": "---\nbody\n"}), json!({"n": 1})]);
+    }
+
+    #[test]
+    fn stream_two_documents() {
+        let program = Program::compile(".name").unwrap();
+        let mut out = Vec::new();
+        stream_yaml("name: a\n---\nname: b\n", &program, &Env::new(), &mut |v| {
+            out.push(v);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(out, vec![json!("a"), json!("b")]);
+    }
+}
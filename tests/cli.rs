@@ -0,0 +1,607 @@
+use assert_cmd::Command;
+
+fn qf() -> Command {
+    let mut cmd = Command::cargo_bin("qf").unwrap();
+    cmd.env_remove("NO_COLOR");
+    cmd
+}
+
+#[test]
+fn null_input_sees_arg_binding() {
+    qf().args(["-n", "--arg", "x", "hi", "$x"])
+        .assert()
+        .success()
+        .stdout("\"hi\"\n");
+}
+
+#[test]
+fn null_input_sees_argjson_and_args_named() {
+    qf().args(["-n", "-c", "--argjson", "n", "{\"a\":1}", "--arg", "s", "t", "$ARGS.named"])
+        .assert()
+        .success()
+        .stdout("{\"n\":{\"a\":1},\"s\":\"t\"}\n");
+}
+
+#[test]
+fn rawfile_binds_file_contents_as_string() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("header.txt");
+    std::fs::write(&path, "MIT License\n").unwrap();
+
+    qf().args(["-n", "-c", "--rawfile", "license"])
+        .arg(&path)
+        .arg("{header: $license}")
+        .assert()
+        .success()
+        .stdout("{\"header\":\"MIT License\\n\"}\n");
+}
+
+#[test]
+fn slurpfile_binds_array_of_documents() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("data.json");
+    std::fs::write(&path, "{\"a\":1}\n{\"a\":2}\n").unwrap();
+
+    qf().args(["-n", "--slurpfile", "data"])
+        .arg(&path)
+        .arg("$data | length == 2")
+        .assert()
+        .success()
+        .stdout("true\n");
+}
+
+#[cfg(feature = "cbor")]
+#[test]
+fn cbor_round_trip_through_cli() {
+    let encoded = qf()
+        .args(["-o", "cbor", "."])
+        .write_stdin("{\"a\":[1,2],\"b\":\"x\"}")
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+
+    qf().args(["-p", "cbor", "-o", "json", "-c", ".a[1]"])
+        .write_stdin(encoded)
+        .assert()
+        .success()
+        .stdout("2\n");
+}
+
+#[test]
+fn tojson_indent_pretty_prints_embedded_json() {
+    qf().args(["-r", "--tojson-indent", "2", ".a | tojson"])
+        .write_stdin("{\"a\":{\"b\":1}}")
+        .assert()
+        .success()
+        .stdout("{\n  \"b\": 1\n}\n");
+}
+
+fn gzip(bytes: &[u8]) -> Vec<u8> {
+    use std::io::Write;
+    let mut enc = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    enc.write_all(bytes).unwrap();
+    enc.finish().unwrap()
+}
+
+#[test]
+fn reads_gzip_file_using_inner_extension() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("data.json.gz");
+    std::fs::write(&path, gzip(b"{\"name\":\"qf\"}")).unwrap();
+
+    qf().arg(".name")
+        .arg(&path)
+        .assert()
+        .success()
+        .stdout("\"qf\"\n");
+}
+
+#[test]
+fn sniffs_gzip_on_stdin() {
+    qf().args(["-c", ".a"])
+        .write_stdin(gzip(b"{\"a\":[1,2]}"))
+        .assert()
+        .success()
+        .stdout("[1,2]\n");
+}
+
+#[test]
+fn raw_output0_terminates_with_nul() {
+    qf().args(["-n", "--raw-output0", "\"a\", \"b c\""])
+        .assert()
+        .success()
+        .stdout("a\0b c\0");
+}
+
+#[test]
+fn raw_output0_rejects_non_strings() {
+    // Outputs are written as they come, up to the first non-string
+    qf().args(["-n", "--raw-output0", "\"a\", 1"])
+        .assert()
+        .failure()
+        .stdout("a\0")
+        .stderr(predicates::str::contains("requires string outputs, got number"));
+}
+
+#[test]
+fn seq_reads_and_writes_json_text_sequences() {
+    qf().args(["--seq", "-c", ".a"])
+        .write_stdin("\x1e{\"a\":1}\n\x1e{\"a\":[2]}\n")
+        .assert()
+        .success()
+        .stdout("\x1e1\n\x1e[2]\n");
+}
+
+#[test]
+fn qf_colors_overrides_theme() {
+    qf().args(["--color", "always", "."])
+        .env_remove("NO_COLOR")
+        .env("QF_COLORS", "0;31:0;31:0;31:38;5;214")
+        .write_stdin("[1]")
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("\x1b[38;5;214m1\x1b[0m"));
+}
+
+#[test]
+fn args_collects_positional_strings() {
+    qf().args(["-n", "-c", "$ARGS", "--args", "a", "1", "{}"])
+        .assert()
+        .success()
+        .stdout("{\"named\":{},\"positional\":[\"a\",\"1\",\"{}\"]}\n");
+}
+
+#[test]
+fn jsonargs_collects_positional_json() {
+    qf().args(["-n", "-c", "$ARGS.positional", "--jsonargs", "1", "{\"a\":[true]}", "\"s\""])
+        .assert()
+        .success()
+        .stdout("[1,{\"a\":[true]},\"s\"]\n");
+    qf().args(["-n", "$ARGS.positional", "--jsonargs", "{oops"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("--jsonargs"));
+    // Positional values replace files, so input comes from stdin
+    qf().args(["-c", "[.a, $ARGS.positional[0]]", "--args", "x"])
+        .write_stdin("{\"a\":1}")
+        .assert()
+        .success()
+        .stdout("[1,\"x\"]\n");
+}
+
+#[test]
+fn duplicate_keys_policy() {
+    qf().args(["-c", "--duplicate-keys", "array", "."])
+        .write_stdin("{\"a\":1,\"a\":2}")
+        .assert()
+        .success()
+        .stdout("{\"a\":[1,2]}\n");
+    qf().args(["--duplicate-keys", "error", "."])
+        .write_stdin("{\"a\":1,\"a\":2}")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("duplicate key \"a\""));
+    // Record by record too
+    for mode in [&["--stream", "-p", "json"][..], &["--jsonl"]] {
+        qf().args(mode)
+            .args(["--duplicate-keys", "error", "."])
+            .write_stdin("{\"b\":0}\n{\"a\":1,\"a\":2}\n")
+            .assert()
+            .failure()
+            .stderr(predicates::str::contains("duplicate key \"a\""));
+        qf().args(mode)
+            .args(["-c", "--duplicate-keys", "first", "."])
+            .write_stdin("{\"a\":1,\"a\":2}\n")
+            .assert()
+            .success()
+            .stdout("{\"a\":1}\n");
+    }
+}
+
+#[test]
+fn input_filename_and_line_number() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("events.json");
+    std::fs::write(&path, "{\"a\":1}\n{\"a\":2}\n").unwrap();
+    let name = path.display().to_string();
+    qf().args(["--jsonl", "-c", "[input_filename, input_line_number]"])
+        .arg(&path)
+        .assert()
+        .success()
+        .stdout(format!("[{name:?},1]\n[{name:?},2]\n"));
+    qf().args(["-n", "input_filename"]).assert().success().stdout("null\n");
+}
+
+#[test]
+fn skip_errors_continues_past_bad_lines() {
+    let input = "{\"a\":1}\n{\"a\":\n{\"a\":2}\n{\"a\":3}\n";
+    qf().args(["--jsonl", ".a"])
+        .write_stdin(input)
+        .assert()
+        .failure()
+        .stdout("1\n");
+    qf().args(["--jsonl", "--skip-errors", ".a"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("1\n2\n3\n")
+        .stderr(predicates::str::contains("qf: skipping line 2: "))
+        .stderr(predicates::str::contains("skipped 1 malformed record(s)"));
+    // A stream of JSON values resumes at the value after the bad one
+    qf().args(["--stream", "-p", "json", "--skip-errors", "-c", "."])
+        .write_stdin("{\"a\":1}\n{\"a\":x}\n[2]\n")
+        .assert()
+        .success()
+        .stdout("{\"a\":1}\n[2]\n");
+    qf().args(["--stream", "-p", "json", "--skip-errors", "-c", "."])
+        .write_stdin("{\"a\":1} bad {\"a\":3}\n")
+        .assert()
+        .success()
+        .stdout("{\"a\":1}\n{\"a\":3}\n")
+        .stderr(predicates::str::contains("skipped 1 malformed record(s)"));
+}
+
+#[test]
+fn yaml_preserve_keeps_comments() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("config.yaml");
+    let text = "# deploy settings\nversion: \"1.0\"  # bump on release\nreplicas: 2\n";
+    std::fs::write(&path, text).unwrap();
+    qf().args(["-i", "--yaml-preserve", r#".version = "2""#])
+        .arg(&path)
+        .assert()
+        .success();
+    assert_eq!(
+        std::fs::read_to_string(&path).unwrap(),
+        "# deploy settings\nversion: '2'  # bump on release\nreplicas: 2\n"
+    );
+    qf().args(["-i", "--yaml-preserve", ".extra = 1"])
+        .arg(&path)
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("keys cannot be added or removed"));
+}
+
+#[test]
+fn unbuffered_streams_each_output() {
+    qf().args(["--jsonl", "--unbuffered", "-j", "-r", ".a"])
+        .write_stdin("{\"a\":\"x\"}\n{\"a\":\"y\"}\n")
+        .assert()
+        .success()
+        .stdout("xy");
+    qf().args(["--stream", "-p", "json", "--unbuffered", "-c", ".[0]"])
+        .write_stdin("[1] [2]")
+        .assert()
+        .success()
+        .stdout("1\n2\n");
+}
+
+#[test]
+fn bare_scalar_input() {
+    for (input, expected) in [("42\n", "42\n"), ("\"hi\"\n", "\"hi\"\n"), ("null", "null\n")] {
+        qf().arg(".").write_stdin(input).assert().success().stdout(expected);
+    }
+    // The same scalar read as YAML gives the same value
+    qf().args(["-p", "yaml", "-o", "json", "[., type]", "-c"])
+        .write_stdin("42\n")
+        .assert()
+        .success()
+        .stdout("[42,\"number\"]\n");
+    qf().args(["-o", "json", "-c", "[., type]"])
+        .write_stdin("yes\n")
+        .assert()
+        .success()
+        .stdout("[\"yes\",\"string\"]\n");
+}
+
+#[test]
+fn in_place_edits_every_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let json = dir.path().join("a.json");
+    let yaml = dir.path().join("b.yaml");
+    std::fs::write(&json, "{\"n\": 1}").unwrap();
+    std::fs::write(&yaml, "n: 2\n").unwrap();
+    qf().args(["-i", ".n += 10"]).arg(&json).arg(&yaml).assert().success().stdout("");
+    // Each file keeps its own format
+    assert_eq!(std::fs::read_to_string(&json).unwrap(), "{\n  \"n\": 11\n}\n");
+    assert_eq!(std::fs::read_to_string(&yaml).unwrap(), "n: 12\n");
+}
+
+#[test]
+fn in_place_converts_to_the_output_format() {
+    let dir = tempfile::tempdir().unwrap();
+    let yaml = dir.path().join("config.yaml");
+    std::fs::write(&yaml, "name: api\nport: 80\n").unwrap();
+    qf().args(["-i", "-o", "json", "-c", "."]).arg(&yaml).assert().success().stdout("");
+    assert_eq!(std::fs::read_to_string(&yaml).unwrap(), "{\"name\":\"api\",\"port\":80}\n");
+
+    // With --rename-extension the file takes the new format's extension
+    std::fs::write(&yaml, "port: 80\n").unwrap();
+    qf().args(["-i", "-o", "json", "--rename-extension", ".port += 1"])
+        .arg(&yaml)
+        .assert()
+        .success();
+    assert!(!yaml.exists());
+    let json = dir.path().join("config.json");
+    assert_eq!(std::fs::read_to_string(&json).unwrap(), "{\n  \"port\": 81\n}\n");
+
+    // An existing file is not overwritten
+    std::fs::write(&yaml, "port: 1\n").unwrap();
+    qf().args(["-i", "-o", "json", "--rename-extension", "."])
+        .arg(&yaml)
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("already exists"));
+    assert_eq!(std::fs::read_to_string(&yaml).unwrap(), "port: 1\n");
+}
+
+#[test]
+fn include_loads_helpers_from_the_library_path() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("util.jq"), "def total: map(.n) | add;\n").unwrap();
+    qf().arg("-L")
+        .arg(dir.path())
+        .args(["-c", "include \"util\"; {sum: total}"])
+        .write_stdin("[{\"n\": 2}, {\"n\": 3}]")
+        .assert()
+        .success()
+        .stdout("{\"sum\":5}\n");
+    qf().args(["include \"util\"; ."])
+        .write_stdin("1")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("module not found: util"));
+}
+
+#[test]
+fn runaway_recursion_fails_cleanly() {
+    qf().args(["-n", "def f: f; f"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("recursion depth limit of 1000 exceeded"));
+    qf().args(["-n", "--max-depth", "2000", "def f: if . < 1500 then . + 1 | f end; 0 | f"])
+        .assert()
+        .success()
+        .stdout("1500\n");
+    // A huge limit still leaves qf a stack it can start with
+    qf().args(["--max-depth", "1000000", "-n", "1"]).assert().success().stdout("1\n");
+}
+
+#[test]
+fn raw_prints_each_string_output() {
+    let input = "{\"tags\": [\"a b\", \"c\"]}";
+    qf().args(["-r", ".tags[]"]).write_stdin(input).assert().success().stdout("a b\nc\n");
+    // The array itself is still a value to format
+    qf().args(["-r", "-c", ".tags"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("[\"a b\",\"c\"]\n");
+    qf().args(["-r", "-o", "yaml", ".tags[]"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("a b\nc\n");
+    qf().args(["--jsonl", "-r", ".tags[0]"])
+        .write_stdin(format!("{input}\n{input}\n"))
+        .assert()
+        .success()
+        .stdout("a b\na b\n");
+}
+
+#[test]
+fn error_format_json() {
+    let output = qf().args(["-n", "--error-format", "json", ".a |"]).output().unwrap();
+    assert!(!output.status.success());
+    let err: serde_json::Value = serde_json::from_slice(&output.stderr).unwrap();
+    assert_eq!(
+        err,
+        serde_json::json!({
+            "kind": "syntax_error",
+            "message": "unexpected token: Eof",
+            "position": 3,
+        })
+    );
+    let output = qf().args(["--error-format", "json", ".a"]).write_stdin("[1]").output().unwrap();
+    let err: serde_json::Value = serde_json::from_slice(&output.stderr).unwrap();
+    assert_eq!(err["kind"], "type_error");
+    assert!(err.get("position").is_none());
+    // The message leaves the kind out, whatever the kind
+    let output = qf().args(["--error-format", "json", ".a"]).write_stdin("{").output().unwrap();
+    let err: serde_json::Value = serde_json::from_slice(&output.stderr).unwrap();
+    assert_eq!(err["kind"], "parse");
+    assert!(!err["message"].as_str().unwrap().contains("parse error"), "{err}");
+    let output = qf().args(["-n", "--error-format", "json", "error(\"boom\")"]).output().unwrap();
+    let err: serde_json::Value = serde_json::from_slice(&output.stderr).unwrap();
+    assert_eq!(err, serde_json::json!({"kind": "user_error", "message": "boom"}));
+    // Text stays the default
+    qf().args(["-n", ".a |"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("syntax error at position 3"));
+}
+
+#[test]
+fn repl_runs_each_line_against_the_input() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("data.json");
+    std::fs::write(&path, "{\"a\": [1, 2], \"b\": \"x\"}").unwrap();
+    // Errors are reported and the loop carries on with the same document
+    qf().args(["--repl", "-c"])
+        .arg(&path)
+        .write_stdin(".a\n\n.b | tonumber\n.a | length\n.[\n.b\n")
+        .assert()
+        .success()
+        .stdout("[1,2]\n2\n\"x\"\n")
+        .stderr(predicates::str::contains("cannot convert \"x\" to number"))
+        .stderr(predicates::str::contains("syntax error"));
+    qf().args(["--repl", "-n"]).write_stdin("1 + 1\n").assert().success().stdout("2\n");
+    qf().arg("--repl").write_stdin(".\n").assert().failure();
+    // A JSON text sequence is read record by record, which the loop can't do
+    qf().args(["--repl", "--seq"])
+        .arg(&path)
+        .write_stdin(".a\n")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("cannot be used with"));
+}
+
+#[test]
+fn xml_output_is_indented_under_a_named_root() {
+    qf().args(["-o", "xml", "--xml-root", "service", "."])
+        .write_stdin("{\"name\": \"api\", \"ports\": [80, 443]}")
+        .assert()
+        .success()
+        .stdout(
+            "<?xml version=\"1.0\"?>\n<service>\n  <name>api</name>\n  <ports>80</ports>\n  \
+             <ports>443</ports>\n</service>\n",
+        );
+    qf().args(["-p", "xml", "-c", "."])
+        .write_stdin("<root><a>1</a><a>2</a></root>")
+        .assert()
+        .success()
+        .stdout("<?xml version=\"1.0\"?><root><a>1</a><a>2</a></root>\n");
+}
+
+fn write_numbered_files(dir: &std::path::Path, count: usize) -> Vec<std::path::PathBuf> {
+    (0..count)
+        .map(|i| {
+            let path = dir.join(format!("{i}.json"));
+            std::fs::write(&path, format!("{{\"n\":{i}}}")).unwrap();
+            path
+        })
+        .collect()
+}
+
+#[test]
+fn jobs_preserves_file_order() {
+    let dir = tempfile::tempdir().unwrap();
+    let files = write_numbered_files(dir.path(), 8);
+
+    qf().args(["--jobs", "4", ".n"])
+        .args(&files)
+        .assert()
+        .success()
+        .stdout("0\n1\n2\n3\n4\n5\n6\n7\n");
+}
+
+#[test]
+fn jobs_skips_failing_files_without_fail_fast() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut files = write_numbered_files(dir.path(), 3);
+    std::fs::write(&files[1], "{not json").unwrap();
+    files.push(dir.path().join("missing.json"));
+
+    qf().args(["--jobs", "2", ".n"])
+        .args(&files)
+        .assert()
+        .failure()
+        .stdout("0\n2\n")
+        .stderr(predicates::str::contains("1.json"));
+
+    qf().args(["--jobs", "2", "--fail-fast", ".n"])
+        .args(&files)
+        .assert()
+        .failure()
+        .stdout("0\n");
+}
+
+#[test]
+fn infer_schema_describes_query_output() {
+    qf().args(["-c", "--infer-schema", ".users"])
+        .write_stdin("{\"users\":[{\"id\":1},{\"id\":2}]}")
+        .assert()
+        .success()
+        .stdout(concat!(
+            "{\"$schema\":\"https://json-schema.org/draft/2020-12/schema\",",
+            "\"items\":{\"properties\":{\"id\":{\"type\":\"integer\"}},",
+            "\"required\":[\"id\"],\"type\":\"object\"},\"type\":\"array\"}\n"
+        ));
+}
+
+#[test]
+fn stderr_builtin_writes_compact_input_without_newline() {
+    qf().args(["-c", ".a | stderr | length"])
+        .write_stdin("{\"a\":[1,\"x\"]}")
+        .assert()
+        .success()
+        .stdout("2\n")
+        .stderr("[1,\"x\"]");
+}
+
+#[test]
+fn csv_input_options() {
+    qf().args([
+        "-p", "csv", "-o", "json", "-c",
+        "--csv-no-header", "--csv-delimiter", ";", "--csv-infer-types", ".",
+    ])
+    .write_stdin("1;x;true\n2.5;y;false\n")
+    .assert()
+    .success()
+    .stdout("[[1,\"x\",true],[2.5,\"y\",false]]\n");
+}
+
+#[test]
+fn max_outputs_stops_runaway_generators() {
+    qf().args(["-n", "--max-outputs", "1000", "[repeat(1)] | length"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("output limit of 1000 exceeded"));
+}
+
+#[test]
+fn yaml_indent_and_flow() {
+    qf().args(["-o", "yaml", "--yaml-indent", "4", "."])
+        .write_stdin("{\"a\":{\"b\":[1,2]}}")
+        .assert()
+        .success()
+        .stdout("a:\n    b:\n    - 1\n    - 2\n");
+    qf().args(["-o", "yaml", "--yaml-flow", "."])
+        .write_stdin("{\"a\":{\"b\":[1,2]}}")
+        .assert()
+        .success()
+        .stdout("a:\n  b: [1, 2]\n");
+}
+
+#[test]
+fn detects_piped_toml_and_csv() {
+    qf().args(["-c", "-o", "json", ".server.port"])
+        .write_stdin("[server]\nport = 8080\n")
+        .assert()
+        .success()
+        .stdout("8080\n");
+    qf().args(["-c", "-o", "json", ".[1].name"])
+        .write_stdin("name,age\nAlice,30\nBob,25\n")
+        .assert()
+        .success()
+        .stdout("\"Bob\"\n");
+}
+
+#[test]
+fn raw_input_slurp_is_one_string() {
+    qf().args(["-Rs", "."])
+        .write_stdin("a,b\nc\n\nd")
+        .assert()
+        .success()
+        .stdout("\"a,b\\nc\\n\\nd\"\n");
+
+    let dir = tempfile::tempdir().unwrap();
+    let first = dir.path().join("one.txt");
+    let second = dir.path().join("two.txt");
+    std::fs::write(&first, "x\n").unwrap();
+    std::fs::write(&second, "y\n").unwrap();
+    qf().args(["-Rs", "."])
+        .arg(&first)
+        .arg(&second)
+        .assert()
+        .success()
+        .stdout("\"x\\ny\\n\"\n");
+    qf().args(["-R", "."])
+        .arg(&first)
+        .arg(&second)
+        .assert()
+        .success()
+        .stdout("\"x\"\n\"y\"\n");
+}